@@ -0,0 +1,4 @@
+fn main() {
+    // Rebuild when a migration is added, they're embedded in the binary
+    println!("cargo:rerun-if-changed=migrations");
+}
@@ -0,0 +1,8 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE follows;
+
+ALTER TABLE users DROP COLUMN private;
+
+ALTER TABLE reviews DROP COLUMN visibility;
+
+DROP TYPE review_visibility;
@@ -0,0 +1,16 @@
+-- Your SQL goes here
+CREATE TYPE review_visibility AS ENUM ('Public', 'Followers', 'Private');
+
+ALTER TABLE reviews ADD COLUMN visibility review_visibility NOT NULL DEFAULT 'Public';
+
+ALTER TABLE users ADD COLUMN private BOOLEAN NOT NULL DEFAULT false;
+
+CREATE TABLE follows (
+  follower_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+  followee_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+  accepted BOOLEAN NOT NULL,
+  created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  PRIMARY KEY (follower_id, followee_id)
+);
+
+CREATE INDEX follows_followee_id_idx ON follows (followee_id);
@@ -0,0 +1,11 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE reports;
+
+ALTER TABLE reviews DROP COLUMN hidden;
+
+ALTER TABLE users DROP COLUMN suspended_at;
+ALTER TABLE users DROP COLUMN role;
+
+DROP TYPE report_status;
+DROP TYPE report_reason;
+DROP TYPE user_role;
@@ -0,0 +1,31 @@
+-- Your SQL goes here
+CREATE TYPE user_role AS ENUM ('User', 'Admin');
+CREATE TYPE report_reason AS ENUM ('Spam', 'Harassment', 'Spoilers', 'Inappropriate', 'Other');
+CREATE TYPE report_status AS ENUM ('Open', 'Resolved', 'Dismissed');
+
+ALTER TABLE users ADD COLUMN role user_role NOT NULL DEFAULT 'User';
+ALTER TABLE users ADD COLUMN suspended_at TIMESTAMP WITH TIME ZONE;
+
+-- User 1 used to be hard-coded as the only privileged user
+UPDATE users SET role = 'Admin' WHERE id = 1;
+
+ALTER TABLE reviews ADD COLUMN hidden BOOLEAN NOT NULL DEFAULT false;
+
+CREATE TABLE reports (
+  id SERIAL NOT NULL PRIMARY KEY,
+  reporter_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+  user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+  tmdb_id INTEGER,
+  category media_category,
+  season INTEGER,
+  reason report_reason NOT NULL,
+  details TEXT NOT NULL,
+  status report_status NOT NULL DEFAULT 'Open',
+  resolved_by INTEGER REFERENCES users (id) ON DELETE SET NULL,
+  created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX reports_status_idx ON reports (status);
+
+SELECT diesel_manage_updated_at('reports');
@@ -0,0 +1,16 @@
+-- Your SQL goes here
+CREATE TABLE blocks (
+  user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+  blocked_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+  created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  PRIMARY KEY (user_id, blocked_id)
+);
+
+CREATE INDEX blocks_blocked_id_idx ON blocks (blocked_id);
+
+CREATE TABLE mutes (
+  user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+  muted_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+  created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  PRIMARY KEY (user_id, muted_id)
+);
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE import_jobs;
+DROP TYPE job_status;
+DROP TYPE import_source;
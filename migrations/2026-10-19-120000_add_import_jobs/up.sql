@@ -0,0 +1,21 @@
+-- Your SQL goes here
+CREATE TYPE import_source AS ENUM ('Letterboxd', 'Trakt', 'MyAnimeList');
+CREATE TYPE job_status AS ENUM ('Pending', 'Running', 'Completed', 'Failed');
+
+CREATE TABLE import_jobs (
+  id SERIAL NOT NULL PRIMARY KEY,
+  user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+  source import_source NOT NULL,
+  status job_status NOT NULL DEFAULT 'Pending',
+  dry_run BOOLEAN NOT NULL,
+  total INTEGER NOT NULL,
+  processed INTEGER NOT NULL DEFAULT 0,
+  imported INTEGER NOT NULL DEFAULT 0,
+  skipped INTEGER NOT NULL DEFAULT 0,
+  unmatched TEXT[] NOT NULL DEFAULT '{}',
+  error TEXT,
+  created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+SELECT diesel_manage_updated_at('import_jobs');
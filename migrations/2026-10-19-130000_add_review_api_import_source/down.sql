@@ -0,0 +1,8 @@
+-- This file should undo anything in `up.sql`
+-- Postgres can't drop a value from an enum, so recreate it
+DELETE FROM import_jobs WHERE source = 'ReviewApi';
+
+ALTER TYPE import_source RENAME TO import_source_old;
+CREATE TYPE import_source AS ENUM ('Letterboxd', 'Trakt', 'MyAnimeList');
+ALTER TABLE import_jobs ALTER COLUMN source TYPE import_source USING source::text::import_source;
+DROP TYPE import_source_old;
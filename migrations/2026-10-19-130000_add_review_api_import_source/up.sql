@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TYPE import_source ADD VALUE 'ReviewApi';
@@ -0,0 +1,21 @@
+-- Your SQL goes here
+CREATE TABLE media (
+  tmdb_id INTEGER NOT NULL,
+  category media_category NOT NULL,
+  title TEXT NOT NULL,
+  original_title TEXT NOT NULL,
+  original_language TEXT NOT NULL,
+  release_date DATE,
+  overview TEXT NOT NULL,
+  poster_path TEXT,
+  number_of_seasons INTEGER,
+  number_of_episodes INTEGER,
+  -- Search results don't have everything, so this stays null until details are fetched
+  details_fetched_at TIMESTAMP WITH TIME ZONE,
+  updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  PRIMARY KEY (tmdb_id, category)
+);
+
+CREATE INDEX media_title_idx ON media (lower(title));
+
+SELECT diesel_manage_updated_at('media');
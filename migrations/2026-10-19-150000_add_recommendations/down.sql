@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE recommendations;
+DROP TYPE recommendation_source;
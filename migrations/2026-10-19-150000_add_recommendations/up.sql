@@ -0,0 +1,17 @@
+-- Your SQL goes here
+CREATE TYPE recommendation_source AS ENUM ('Community', 'Similar');
+
+CREATE TABLE recommendations (
+  user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+  tmdb_id INTEGER NOT NULL,
+  category media_category NOT NULL,
+  score DOUBLE PRECISION NOT NULL,
+  source recommendation_source NOT NULL,
+  -- The user's own title this is recommended because of
+  because_tmdb_id INTEGER NOT NULL,
+  because_category media_category NOT NULL,
+  created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  PRIMARY KEY (user_id, tmdb_id, category)
+);
+
+CREATE INDEX recommendations_score_idx ON recommendations (user_id, score DESC);
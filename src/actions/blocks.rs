@@ -0,0 +1,281 @@
+use diesel::prelude::*;
+use serde::Deserialize;
+use utoipa::IntoParams;
+use validator::Validate;
+
+use crate::{
+    errors::{DbError, ServiceError},
+    models::{Block, Mute, NewBlock, NewMute},
+    pagination::{per_page_limit, Paginate, PaginatedResults},
+    PooledConn,
+};
+
+#[derive(Deserialize, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct BlocksQuery {
+    #[validate(range(min = 1, max = 1000000))]
+    #[param(minimum = 1, maximum = 1000000)]
+    pub page: Option<i64>,
+    /// Up to `pagination.max_per_page`
+    #[validate(custom = "per_page_limit")]
+    #[param(minimum = 1)]
+    pub per_page: Option<i64>,
+}
+
+// Blocks and mutes reference users, so a missing one would otherwise be a foreign key violation
+fn ensure_user_exists(conn: &mut PooledConn, idx: i32) -> Result<(), ServiceError> {
+    use crate::schema::users;
+
+    let exists: bool =
+        diesel::select(diesel::dsl::exists(users::table.find(idx))).get_result(conn)?;
+    if !exists {
+        return Err(ServiceError::new(404, "user_not_found", "No such user"));
+    }
+
+    Ok(())
+}
+
+/// Blocks `blocked_idx` and removes any follows between the two users.
+pub fn block_user(
+    conn: &mut PooledConn,
+    user_idx: i32,
+    blocked_idx: i32,
+) -> Result<Block, ServiceError> {
+    use crate::schema::blocks::dsl::*;
+    use crate::schema::follows;
+
+    if user_idx == blocked_idx {
+        return Err(ServiceError::new(400, "self_block", "Can't block yourself"));
+    }
+
+    ensure_user_exists(conn, blocked_idx)?;
+
+    conn.transaction(|conn| {
+        let new_block = NewBlock {
+            user_id: user_idx,
+            blocked_id: blocked_idx,
+        };
+
+        diesel::insert_into(blocks)
+            .values(new_block)
+            .on_conflict_do_nothing()
+            .execute(conn)?;
+
+        diesel::delete(
+            follows::table.filter(
+                follows::follower_id
+                    .eq(user_idx)
+                    .and(follows::followee_id.eq(blocked_idx))
+                    .or(follows::follower_id
+                        .eq(blocked_idx)
+                        .and(follows::followee_id.eq(user_idx))),
+            ),
+        )
+        .execute(conn)?;
+
+        let block = blocks.find((user_idx, blocked_idx)).first::<Block>(conn)?;
+
+        Ok(block)
+    })
+}
+
+pub fn unblock_user(
+    conn: &mut PooledConn,
+    user_idx: i32,
+    blocked_idx: i32,
+) -> Result<usize, DbError> {
+    use crate::schema::blocks::dsl::*;
+
+    let deleted = diesel::delete(blocks.find((user_idx, blocked_idx))).execute(conn)?;
+
+    Ok(deleted)
+}
+
+pub fn get_blocks(
+    conn: &mut PooledConn,
+    user_idx: i32,
+    params: BlocksQuery,
+) -> Result<PaginatedResults<Block>, DbError> {
+    use crate::schema::blocks::dsl::*;
+
+    let results = blocks
+        .filter(user_id.eq(user_idx))
+        .order(created_at.desc())
+        .paginate_safe(params.page, params.per_page)
+        .load_paginated(conn)?;
+
+    Ok(results)
+}
+
+/// Whether either user has blocked the other.
+pub fn is_blocked_between(conn: &mut PooledConn, a: i32, b: i32) -> Result<bool, DbError> {
+    use crate::schema::blocks::dsl::*;
+
+    let blocked = diesel::select(diesel::dsl::exists(
+        blocks.filter(
+            user_id
+                .eq(a)
+                .and(blocked_id.eq(b))
+                .or(user_id.eq(b).and(blocked_id.eq(a))),
+        ),
+    ))
+    .get_result(conn)?;
+
+    Ok(blocked)
+}
+
+pub fn mute_user(
+    conn: &mut PooledConn,
+    user_idx: i32,
+    muted_idx: i32,
+) -> Result<Mute, ServiceError> {
+    use crate::schema::mutes::dsl::*;
+
+    if user_idx == muted_idx {
+        return Err(ServiceError::new(400, "self_mute", "Can't mute yourself"));
+    }
+
+    ensure_user_exists(conn, muted_idx)?;
+
+    let new_mute = NewMute {
+        user_id: user_idx,
+        muted_id: muted_idx,
+    };
+
+    diesel::insert_into(mutes)
+        .values(new_mute)
+        .on_conflict_do_nothing()
+        .execute(conn)?;
+
+    let mute = mutes.find((user_idx, muted_idx)).first::<Mute>(conn)?;
+
+    Ok(mute)
+}
+
+pub fn unmute_user(conn: &mut PooledConn, user_idx: i32, muted_idx: i32) -> Result<usize, DbError> {
+    use crate::schema::mutes::dsl::*;
+
+    let deleted = diesel::delete(mutes.find((user_idx, muted_idx))).execute(conn)?;
+
+    Ok(deleted)
+}
+
+pub fn get_mutes(
+    conn: &mut PooledConn,
+    user_idx: i32,
+    params: BlocksQuery,
+) -> Result<PaginatedResults<Mute>, DbError> {
+    use crate::schema::mutes::dsl::*;
+
+    let results = mutes
+        .filter(user_id.eq(user_idx))
+        .order(created_at.desc())
+        .paginate_safe(params.page, params.per_page)
+        .load_paginated(conn)?;
+
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use diesel::prelude::*;
+
+    use super::{block_user, is_blocked_between, mute_user, unblock_user, unmute_user};
+    use crate::{
+        actions::reviews::not_muted_by,
+        models::ReviewVisibility,
+        schema::{follows, reviews},
+        testing::{self, follow, review, user},
+        PooledConn,
+    };
+
+    fn follows_between(conn: &mut PooledConn, a: i32, b: i32) -> i64 {
+        follows::table
+            .filter(follows::follower_id.eq_any([a, b]))
+            .filter(follows::followee_id.eq_any([a, b]))
+            .count()
+            .get_result(conn)
+            .unwrap()
+    }
+
+    #[test]
+    fn blocking_removes_follows_both_ways() {
+        let Some(mut conn) = testing::conn() else {
+            return;
+        };
+        let blocker = user(&mut conn, "blocker");
+        let blocked = user(&mut conn, "blocked");
+        follow(&mut conn, blocker, blocked, true);
+        follow(&mut conn, blocked, blocker, false);
+
+        let block = block_user(&mut conn, blocker, blocked).unwrap();
+
+        assert_eq!((block.user_id, block.blocked_id), (blocker, blocked));
+        assert_eq!(follows_between(&mut conn, blocker, blocked), 0);
+        assert!(is_blocked_between(&mut conn, blocker, blocked).unwrap());
+        assert!(is_blocked_between(&mut conn, blocked, blocker).unwrap());
+    }
+
+    #[test]
+    fn blocking_twice_keeps_one_block() {
+        let Some(mut conn) = testing::conn() else {
+            return;
+        };
+        let blocker = user(&mut conn, "blocker");
+        let blocked = user(&mut conn, "blocked");
+
+        block_user(&mut conn, blocker, blocked).unwrap();
+        block_user(&mut conn, blocker, blocked).unwrap();
+
+        assert_eq!(unblock_user(&mut conn, blocker, blocked).unwrap(), 1);
+        assert_eq!(unblock_user(&mut conn, blocker, blocked).unwrap(), 0);
+        assert!(!is_blocked_between(&mut conn, blocker, blocked).unwrap());
+    }
+
+    #[test]
+    fn blocks_and_mutes_need_another_user_who_exists() {
+        let Some(mut conn) = testing::conn() else {
+            return;
+        };
+        let me = user(&mut conn, "me");
+
+        assert_eq!(
+            block_user(&mut conn, me, me).unwrap_err().code,
+            "self_block"
+        );
+        assert_eq!(mute_user(&mut conn, me, me).unwrap_err().code, "self_mute");
+        assert_eq!(
+            block_user(&mut conn, me, -1).unwrap_err().code,
+            "user_not_found"
+        );
+        assert_eq!(
+            mute_user(&mut conn, me, -1).unwrap_err().code,
+            "user_not_found"
+        );
+    }
+
+    #[test]
+    fn mutes_hide_reviews_from_listings() {
+        let Some(mut conn) = testing::conn() else {
+            return;
+        };
+        let muter = user(&mut conn, "muter");
+        let muted = user(&mut conn, "muted");
+        review(&mut conn, muted, 1, None, ReviewVisibility::Public);
+        let listed = |conn: &mut PooledConn| -> i64 {
+            reviews::table
+                .filter(reviews::user_id.eq(muted))
+                .filter(not_muted_by(muter))
+                .count()
+                .get_result(conn)
+                .unwrap()
+        };
+
+        mute_user(&mut conn, muter, muted).unwrap();
+        assert_eq!(listed(&mut conn), 0);
+        assert!(!is_blocked_between(&mut conn, muter, muted).unwrap());
+
+        assert_eq!(unmute_user(&mut conn, muter, muted).unwrap(), 1);
+        assert_eq!(listed(&mut conn), 1);
+    }
+}
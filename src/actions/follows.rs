@@ -0,0 +1,131 @@
+use diesel::prelude::*;
+use serde::Deserialize;
+use utoipa::IntoParams;
+use validator::Validate;
+
+use crate::{
+    actions::blocks::is_blocked_between,
+    errors::{DbError, ServiceError},
+    models::{Follow, NewFollow},
+    pagination::{per_page_limit, Paginate, PaginatedResults},
+    PooledConn,
+};
+
+#[derive(Deserialize, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct FollowsQuery {
+    #[validate(range(min = 1, max = 1000000))]
+    #[param(minimum = 1, maximum = 1000000)]
+    pub page: Option<i64>,
+    /// Up to `pagination.max_per_page`
+    #[validate(custom = "per_page_limit")]
+    #[param(minimum = 1)]
+    pub per_page: Option<i64>,
+    /// Only pending or only accepted follows, pending ones are only shown to the followee
+    pub pending: Option<bool>,
+}
+
+/// Follows `followee_idx`. Following a private profile creates a pending
+/// request that has to be accepted by the followee.
+pub fn follow_user(
+    conn: &mut PooledConn,
+    follower_idx: i32,
+    followee_idx: i32,
+) -> Result<Follow, ServiceError> {
+    use crate::schema::follows::dsl::*;
+    use crate::schema::users;
+
+    if follower_idx == followee_idx {
+        return Err(ServiceError::new(
+            400,
+            "self_follow",
+            "Can't follow yourself",
+        ));
+    }
+
+    let existing = follows
+        .find((follower_idx, followee_idx))
+        .first::<Follow>(conn)
+        .optional()?;
+
+    if let Some(follow) = existing {
+        return Ok(follow);
+    }
+
+    // Don't reveal who blocked who
+    if is_blocked_between(conn, follower_idx, followee_idx)? {
+        return Err(ServiceError::new(404, "user_not_found", "No such user"));
+    }
+
+    let private = users::table
+        .find(followee_idx)
+        .select(users::private)
+        .first::<bool>(conn)?;
+
+    let new_follow = NewFollow {
+        follower_id: follower_idx,
+        followee_id: followee_idx,
+        accepted: !private,
+    };
+
+    let follow = diesel::insert_into(follows)
+        .values(new_follow)
+        .get_result::<Follow>(conn)?;
+
+    Ok(follow)
+}
+
+pub fn unfollow_user(
+    conn: &mut PooledConn,
+    follower_idx: i32,
+    followee_idx: i32,
+) -> Result<usize, DbError> {
+    use crate::schema::follows::dsl::*;
+
+    let deleted = diesel::delete(follows.find((follower_idx, followee_idx))).execute(conn)?;
+
+    Ok(deleted)
+}
+
+/// Lists followers of `followee_idx`. Pending requests are only shown to the followee.
+pub fn get_followers(
+    conn: &mut PooledConn,
+    viewer: Option<i32>,
+    followee_idx: i32,
+    params: FollowsQuery,
+) -> Result<PaginatedResults<Follow>, DbError> {
+    use crate::schema::follows::dsl::*;
+
+    let mut query = follows
+        .filter(followee_id.eq(followee_idx))
+        .order(created_at.desc())
+        .into_boxed();
+
+    if viewer == Some(followee_idx) {
+        if let Some(pending) = params.pending {
+            query = query.filter(accepted.eq(!pending));
+        }
+    } else {
+        query = query.filter(accepted.eq(true));
+    }
+
+    let results = query
+        .paginate_safe(params.page, params.per_page)
+        .load_paginated(conn)?;
+
+    Ok(results)
+}
+
+pub fn accept_follower(
+    conn: &mut PooledConn,
+    followee_idx: i32,
+    follower_idx: i32,
+) -> Result<Follow, ServiceError> {
+    use crate::schema::follows::dsl::*;
+
+    let follow = diesel::update(follows.find((follower_idx, followee_idx)))
+        .set(accepted.eq(true))
+        .get_result(conn)?;
+
+    Ok(follow)
+}
@@ -0,0 +1,183 @@
+use diesel::prelude::*;
+
+use crate::{
+    errors::DbError,
+    import::ImportEntry,
+    models::{ImportJob, ImportSource, JobStatus, NewImportJob, NewReview},
+    PooledConn,
+};
+
+pub fn create_import_job(
+    conn: &mut PooledConn,
+    user_idx: i32,
+    source_v: ImportSource,
+    dry_run_v: bool,
+    total_v: i32,
+) -> Result<ImportJob, DbError> {
+    use crate::schema::import_jobs::dsl::*;
+
+    let new_job = NewImportJob {
+        user_id: user_idx,
+        source: source_v,
+        dry_run: dry_run_v,
+        total: total_v,
+    };
+
+    let job = diesel::insert_into(import_jobs)
+        .values(new_job)
+        .get_result::<ImportJob>(conn)?;
+
+    Ok(job)
+}
+
+pub fn find_import_job(
+    conn: &mut PooledConn,
+    user_idx: i32,
+    job_idx: i32,
+) -> Result<Option<ImportJob>, DbError> {
+    use crate::schema::import_jobs::dsl::*;
+
+    let job = import_jobs
+        .find(job_idx)
+        .filter(user_id.eq(user_idx))
+        .first(conn)
+        .optional()?;
+
+    Ok(job)
+}
+
+#[derive(Debug, Default, AsChangeset)]
+#[diesel(table_name = crate::schema::import_jobs)]
+pub struct ImportProgress {
+    pub status: Option<JobStatus>,
+    pub processed: Option<i32>,
+    pub imported: Option<i32>,
+    pub skipped: Option<i32>,
+    pub unmatched: Option<Vec<String>>,
+    pub error: Option<String>,
+}
+
+pub fn update_import_job(
+    conn: &mut PooledConn,
+    job_idx: i32,
+    progress: ImportProgress,
+) -> Result<ImportJob, DbError> {
+    use crate::schema::import_jobs::dsl::*;
+
+    let job = diesel::update(import_jobs.find(job_idx))
+        .set(progress)
+        .get_result(conn)?;
+
+    Ok(job)
+}
+
+/// Fails every unfinished job that hasn't saved progress for `stale_after`, returning how many.
+/// Jobs run in the process that accepted them and the upload isn't kept, so once that process
+/// is gone they can't be picked up again. Jobs still running on other instances are left alone.
+pub fn fail_stale_import_jobs(
+    conn: &mut PooledConn,
+    stale_after: chrono::Duration,
+) -> Result<usize, DbError> {
+    use crate::schema::import_jobs::dsl::*;
+
+    let cutoff = chrono::Utc::now() - stale_after;
+
+    let failed = diesel::update(
+        import_jobs
+            .filter(status.eq_any([JobStatus::Pending, JobStatus::Running]))
+            .filter(updated_at.lt(cutoff)),
+    )
+    .set(ImportProgress {
+        status: Some(JobStatus::Failed),
+        error: Some("Interrupted by a restart, import again to finish".to_string()),
+        ..Default::default()
+    })
+    .execute(conn)?;
+
+    Ok(failed)
+}
+
+/// Inserts an imported review. Returns false if the user already reviewed it.
+pub fn import_review(
+    conn: &mut PooledConn,
+    user_idx: i32,
+    tmdb_idx: i32,
+    entry: &ImportEntry,
+) -> Result<bool, DbError> {
+    use crate::schema::reviews::dsl::*;
+
+    let new_review = NewReview {
+        user_id: user_idx,
+        tmdb_id: tmdb_idx,
+        category: entry.category,
+        season: entry.season,
+        status: entry.status,
+        text: &entry.text,
+        fun_before: entry.fun_before,
+        fun_during: entry.fun_during,
+        fun_after: entry.fun_after,
+        visibility: entry.visibility,
+    };
+
+    let inserted = diesel::insert_into(reviews)
+        .values(new_review)
+        .on_conflict_do_nothing()
+        .execute(conn)?;
+
+    Ok(inserted > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use diesel::prelude::*;
+
+    use super::{create_import_job, fail_stale_import_jobs, update_import_job, ImportProgress};
+    use crate::{
+        models::{ImportSource, JobStatus},
+        schema::import_jobs,
+        testing::{self, user},
+        PooledConn,
+    };
+
+    fn running_job(conn: &mut PooledConn, user_id: i32, minutes_ago: i64) -> i32 {
+        let job = create_import_job(conn, user_id, ImportSource::Trakt, false, 10).unwrap();
+        let progress = ImportProgress {
+            status: Some(JobStatus::Running),
+            ..Default::default()
+        };
+        update_import_job(conn, job.id, progress).unwrap();
+
+        diesel::update(import_jobs::table.find(job.id))
+            .set(
+                import_jobs::updated_at
+                    .eq(chrono::Utc::now() - chrono::Duration::minutes(minutes_ago)),
+            )
+            .execute(conn)
+            .unwrap();
+        job.id
+    }
+
+    fn status(conn: &mut PooledConn, id: i32) -> JobStatus {
+        import_jobs::table
+            .find(id)
+            .select(import_jobs::status)
+            .first(conn)
+            .unwrap()
+    }
+
+    #[test]
+    fn fails_only_jobs_without_recent_progress() {
+        let Some(mut conn) = testing::conn() else {
+            return;
+        };
+        let importer = user(&mut conn, "importer");
+        let stale = running_job(&mut conn, importer, 10);
+        let live = running_job(&mut conn, importer, 1);
+
+        let failed = fail_stale_import_jobs(&mut conn, chrono::Duration::minutes(5)).unwrap();
+
+        assert_eq!(failed, 1);
+        assert_eq!(status(&mut conn, stale), JobStatus::Failed);
+        assert_eq!(status(&mut conn, live), JobStatus::Running);
+    }
+}
@@ -0,0 +1,136 @@
+use diesel::{pg::upsert::excluded, prelude::*};
+
+use crate::{
+    errors::DbError,
+    models::{Media, MediaCategory, NewMedia},
+    pagination::{Paginate, PaginatedResults},
+    PooledConn,
+};
+
+// Same as TMDB
+const SEARCH_PER_PAGE: i64 = 20;
+
+/// Saves what search results know about each title, keeping any details already fetched.
+pub fn save_search_results(conn: &mut PooledConn, results: &[NewMedia]) -> Result<usize, DbError> {
+    use crate::schema::media::dsl::*;
+
+    let saved = diesel::insert_into(media)
+        .values(results)
+        .on_conflict((tmdb_id, category))
+        .do_update()
+        .set((
+            title.eq(excluded(title)),
+            original_title.eq(excluded(original_title)),
+            original_language.eq(excluded(original_language)),
+            release_date.eq(excluded(release_date)),
+            overview.eq(excluded(overview)),
+            poster_path.eq(excluded(poster_path)),
+        ))
+        .execute(conn)?;
+
+    Ok(saved)
+}
+
+pub fn save_media_details(conn: &mut PooledConn, details: &NewMedia) -> Result<Media, DbError> {
+    use crate::schema::media::dsl::*;
+
+    let saved = diesel::insert_into(media)
+        .values(details)
+        .on_conflict((tmdb_id, category))
+        .do_update()
+        .set((
+            title.eq(excluded(title)),
+            original_title.eq(excluded(original_title)),
+            original_language.eq(excluded(original_language)),
+            release_date.eq(excluded(release_date)),
+            overview.eq(excluded(overview)),
+            poster_path.eq(excluded(poster_path)),
+            number_of_seasons.eq(excluded(number_of_seasons)),
+            number_of_episodes.eq(excluded(number_of_episodes)),
+            details_fetched_at.eq(excluded(details_fetched_at)),
+        ))
+        .get_result(conn)?;
+
+    Ok(saved)
+}
+
+pub fn find_media(
+    conn: &mut PooledConn,
+    tmdb_id_v: i32,
+    category_v: MediaCategory,
+) -> Result<Option<Media>, DbError> {
+    use crate::schema::media::dsl::*;
+
+    let found = media.find((tmdb_id_v, category_v)).first(conn).optional()?;
+
+    Ok(found)
+}
+
+/// Finds every cached title out of `keys` in one query.
+pub fn find_media_batch(
+    conn: &mut PooledConn,
+    keys: &[(i32, MediaCategory)],
+) -> Result<Vec<Media>, DbError> {
+    use crate::schema::media::dsl::*;
+
+    if keys.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let ids: Vec<i32> = keys.iter().map(|(id, _)| *id).collect();
+
+    // Ids are only unique per category, so drop any other category's title with the same id
+    let found = media
+        .filter(tmdb_id.eq_any(ids))
+        .load::<Media>(conn)?
+        .into_iter()
+        .filter(|m| keys.contains(&(m.tmdb_id, m.category)))
+        .collect();
+
+    Ok(found)
+}
+
+/// Searches cached titles, for when TMDB can't be reached.
+pub fn search_media(
+    conn: &mut PooledConn,
+    category_v: MediaCategory,
+    query: &str,
+    year: Option<i32>,
+    page: Option<i64>,
+) -> Result<PaginatedResults<Media>, DbError> {
+    use crate::schema::media::dsl::*;
+
+    let pattern = format!(
+        "%{}%",
+        query
+            .replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_")
+    );
+
+    let mut sql_query = media
+        .filter(category.eq(category_v))
+        .filter(
+            title
+                .ilike(pattern.clone())
+                .or(original_title.ilike(pattern)),
+        )
+        .order(title.asc())
+        .into_boxed();
+
+    if let Some(year) = year {
+        let start = chrono::NaiveDate::from_ymd_opt(year, 1, 1);
+        let end = chrono::NaiveDate::from_ymd_opt(year + 1, 1, 1);
+        if let (Some(start), Some(end)) = (start, end) {
+            sql_query = sql_query
+                .filter(release_date.ge(start))
+                .filter(release_date.lt(end));
+        }
+    }
+
+    let results = sql_query
+        .paginate_safe(page, Some(SEARCH_PER_PAGE))
+        .load_paginated(conn)?;
+
+    Ok(results)
+}
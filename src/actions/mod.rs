@@ -1,2 +1,8 @@
+pub mod blocks;
+pub mod follows;
+pub mod imports;
+pub mod media;
+pub mod recommendations;
+pub mod reports;
 pub mod reviews;
 pub mod users;
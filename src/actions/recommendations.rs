@@ -0,0 +1,232 @@
+use std::collections::HashMap;
+
+use diesel::{prelude::*, sql_query, sql_types::Bool};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::{
+    actions::{
+        media::find_media_batch,
+        reviews::{visible_to, ReviewFilter},
+    },
+    errors::DbError,
+    models::{Media, MediaCategory, NewRecommendation, Recommendation, WatchStatus},
+    pagination::{Paginate, PaginatedResults},
+    PooledConn,
+};
+
+pub type TitleKey = (i32, MediaCategory);
+
+// Held by whichever instance is recomputing recommendations
+const BATCH_LOCK_KEY: i64 = 0x7265_636f_6d6d;
+
+#[derive(QueryableByName)]
+struct Locked {
+    #[diesel(sql_type = Bool)]
+    locked: bool,
+}
+
+/// Takes the batch's advisory lock for this connection's session, unless another has it.
+pub fn try_lock_batch(conn: &mut PooledConn) -> Result<bool, DbError> {
+    let row: Locked = sql_query(format!(
+        "SELECT pg_try_advisory_lock({BATCH_LOCK_KEY}) AS locked"
+    ))
+    .get_result(conn)?;
+
+    Ok(row.locked)
+}
+
+pub fn unlock_batch(conn: &mut PooledConn) -> Result<(), DbError> {
+    sql_query(format!("SELECT pg_advisory_unlock({BATCH_LOCK_KEY})")).execute(conn)?;
+    Ok(())
+}
+
+/// When the last batch saved anything.
+pub fn get_last_recommended_at(
+    conn: &mut PooledConn,
+) -> Result<Option<chrono::DateTime<chrono::Utc>>, DbError> {
+    use crate::schema::recommendations::dsl::*;
+
+    let last = recommendations
+        .select(diesel::dsl::max(created_at))
+        .first(conn)?;
+
+    Ok(last)
+}
+
+/// Finished and had fun with it while or after watching.
+pub fn liked() -> ReviewFilter {
+    use crate::schema::reviews::dsl::*;
+
+    Box::new(
+        status
+            .eq(WatchStatus::Completed)
+            .and(fun_during.or(fun_after)),
+    )
+}
+
+/// Every publicly visible liked title, by user.
+pub fn get_public_likes(conn: &mut PooledConn) -> Result<Vec<(i32, TitleKey)>, DbError> {
+    use crate::schema::reviews::dsl::*;
+
+    let likes = reviews
+        .filter(visible_to(None))
+        .filter(liked())
+        .select((user_id, tmdb_id, category))
+        .distinct()
+        .load::<(i32, i32, MediaCategory)>(conn)?
+        .into_iter()
+        .map(|(user_id_v, tmdb_id_v, category_v)| (user_id_v, (tmdb_id_v, category_v)))
+        .collect();
+
+    Ok(likes)
+}
+
+/// Users who liked anything, including privately.
+pub fn get_users_with_likes(conn: &mut PooledConn) -> Result<Vec<i32>, DbError> {
+    use crate::schema::reviews::dsl::*;
+
+    let users = reviews
+        .filter(liked())
+        .select(user_id)
+        .distinct()
+        .load(conn)?;
+
+    Ok(users)
+}
+
+pub struct OwnReview {
+    pub key: TitleKey,
+    pub liked: bool,
+    // How many fun_* are true
+    pub fun: i32,
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// All of a user's own reviews, whatever their visibility.
+pub fn get_own_reviews(conn: &mut PooledConn, user_id_v: i32) -> Result<Vec<OwnReview>, DbError> {
+    use crate::schema::reviews::dsl::*;
+
+    let own = reviews
+        .filter(user_id.eq(user_id_v))
+        .select((
+            tmdb_id, category, status, fun_before, fun_during, fun_after, updated_at,
+        ))
+        .load::<(
+            i32,
+            MediaCategory,
+            WatchStatus,
+            bool,
+            bool,
+            bool,
+            chrono::DateTime<chrono::Utc>,
+        )>(conn)?
+        .into_iter()
+        .map(
+            |(tmdb_id_v, category_v, status_v, before, during, after, updated_at_v)| OwnReview {
+                key: (tmdb_id_v, category_v),
+                liked: status_v == WatchStatus::Completed && (during || after),
+                fun: [before, during, after]
+                    .into_iter()
+                    .filter(|fun| *fun)
+                    .count() as i32,
+                updated_at: updated_at_v,
+            },
+        )
+        .collect();
+
+    Ok(own)
+}
+
+pub fn replace_recommendations(
+    conn: &mut PooledConn,
+    user_id_v: i32,
+    new_recommendations: Vec<NewRecommendation>,
+) -> Result<usize, DbError> {
+    use crate::schema::recommendations::dsl::*;
+
+    let inserted = conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        diesel::delete(recommendations.filter(user_id.eq(user_id_v))).execute(conn)?;
+
+        diesel::insert_into(recommendations)
+            .values(&new_recommendations)
+            .execute(conn)
+    })?;
+
+    Ok(inserted)
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Because {
+    pub tmdb_id: i32,
+    pub category: MediaCategory,
+    pub title: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ExplainedRecommendation {
+    #[serde(flatten)]
+    pub recommendation: Recommendation,
+    pub because: Because,
+    // None until the title is cached
+    pub media: Option<Media>,
+}
+
+pub fn get_recommendations(
+    conn: &mut PooledConn,
+    user_id_v: i32,
+    page: Option<i64>,
+    per_page: Option<i64>,
+) -> Result<PaginatedResults<ExplainedRecommendation>, DbError> {
+    use crate::schema::recommendations::dsl::*;
+
+    let found: PaginatedResults<Recommendation> = recommendations
+        .filter(user_id.eq(user_id_v))
+        .order((score.desc(), tmdb_id.asc()))
+        .paginate_safe(page, per_page)
+        .load_paginated(conn)?;
+
+    let keys: Vec<TitleKey> = found
+        .results
+        .iter()
+        .flat_map(|r| {
+            [
+                (r.tmdb_id, r.category),
+                (r.because_tmdb_id, r.because_category),
+            ]
+        })
+        .collect();
+
+    let media: HashMap<TitleKey, Media> = find_media_batch(conn, &keys)?
+        .into_iter()
+        .map(|m| ((m.tmdb_id, m.category), m))
+        .collect();
+
+    let results = found
+        .results
+        .into_iter()
+        .map(|recommendation| ExplainedRecommendation {
+            because: Because {
+                tmdb_id: recommendation.because_tmdb_id,
+                category: recommendation.because_category,
+                title: media
+                    .get(&(
+                        recommendation.because_tmdb_id,
+                        recommendation.because_category,
+                    ))
+                    .map(|m| m.title.clone()),
+            },
+            media: media
+                .get(&(recommendation.tmdb_id, recommendation.category))
+                .cloned(),
+            recommendation,
+        })
+        .collect();
+
+    Ok(PaginatedResults {
+        results,
+        page: found.page,
+        total_pages: found.total_pages,
+        total_results: found.total_results,
+    })
+}
@@ -0,0 +1,408 @@
+use diesel::prelude::*;
+use serde::Deserialize;
+use utoipa::{IntoParams, ToSchema};
+use validator::{Validate, ValidationError};
+
+use crate::{
+    actions::{
+        reviews::{set_review_hidden, visible_to},
+        users::{moderate_user, profile_visible_to, ModerateUser},
+    },
+    errors::{DbError, ServiceError},
+    models::{MediaCategory, NewReport, Report, ReportReason, ReportStatus},
+    pagination::{per_page_limit, Paginate, PaginatedResults},
+    PooledConn,
+};
+
+#[derive(Deserialize, Debug, Validate, ToSchema)]
+pub struct InputReport {
+    reason: ReportReason,
+    #[validate(length(max = 1000))]
+    #[schema(max_length = 1000)]
+    details: Option<String>,
+}
+
+pub fn report_review(
+    conn: &mut PooledConn,
+    reporter_idx: i32,
+    user_id_v: i32,
+    tmdb_id_v: i32,
+    category_v: MediaCategory,
+    season_v: Option<i32>,
+    input_report: InputReport,
+) -> Result<Report, ServiceError> {
+    use crate::schema::reports::dsl::reports;
+    use crate::schema::reviews::dsl::*;
+
+    let season_v = season_v.unwrap_or(-1);
+
+    // Can only report what you can see
+    reviews
+        .filter(visible_to(Some(reporter_idx)))
+        .filter(user_id.eq(user_id_v))
+        .filter(tmdb_id.eq(tmdb_id_v))
+        .filter(category.eq(category_v))
+        .filter(season.eq(season_v))
+        .select(user_id)
+        .first::<i32>(conn)?;
+
+    let new_report = NewReport {
+        reporter_id: reporter_idx,
+        user_id: user_id_v,
+        tmdb_id: Some(tmdb_id_v),
+        category: Some(category_v),
+        season: Some(season_v),
+        reason: input_report.reason,
+        details: input_report.details.as_deref().unwrap_or(""),
+    };
+
+    let report = diesel::insert_into(reports)
+        .values(new_report)
+        .get_result::<Report>(conn)?;
+
+    Ok(report)
+}
+
+pub fn report_user(
+    conn: &mut PooledConn,
+    reporter_idx: i32,
+    user_idx: i32,
+    input_report: InputReport,
+) -> Result<Report, ServiceError> {
+    use crate::schema::reports::dsl::reports;
+    use crate::schema::users::dsl::{id, users};
+
+    if reporter_idx == user_idx {
+        return Err(ServiceError::new(
+            400,
+            "self_report",
+            "Can't report yourself",
+        ));
+    }
+
+    users
+        .find(user_idx)
+        .filter(profile_visible_to(Some(reporter_idx)))
+        .select(id)
+        .first::<i32>(conn)?;
+
+    let new_report = NewReport {
+        reporter_id: reporter_idx,
+        user_id: user_idx,
+        tmdb_id: None,
+        category: None,
+        season: None,
+        reason: input_report.reason,
+        details: input_report.details.as_deref().unwrap_or(""),
+    };
+
+    let report = diesel::insert_into(reports)
+        .values(new_report)
+        .get_result::<Report>(conn)?;
+
+    Ok(report)
+}
+
+#[derive(Deserialize, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ReportsQuery {
+    #[validate(range(min = 1, max = 1000000))]
+    #[param(minimum = 1, maximum = 1000000)]
+    pub page: Option<i64>,
+    /// Up to `pagination.max_per_page`
+    #[validate(custom = "per_page_limit")]
+    #[param(minimum = 1)]
+    pub per_page: Option<i64>,
+    #[param(inline)]
+    pub status: Option<ReportStatus>,
+    #[param(inline)]
+    pub reason: Option<ReportReason>,
+    pub user_id: Option<i32>,
+}
+
+/// Oldest reports first, so the queue is worked through in order.
+pub fn get_all_reports(
+    conn: &mut PooledConn,
+    params: ReportsQuery,
+) -> Result<PaginatedResults<Report>, DbError> {
+    use crate::schema::reports::dsl::*;
+
+    let mut query = reports.order(created_at.asc()).into_boxed();
+
+    if let Some(status_in) = params.status {
+        query = query.filter(status.eq(status_in));
+    }
+    if let Some(reason_in) = params.reason {
+        query = query.filter(reason.eq(reason_in));
+    }
+    if let Some(user_id_in) = params.user_id {
+        query = query.filter(user_id.eq(user_id_in));
+    }
+
+    let results = query
+        .paginate_safe(params.page, params.per_page)
+        .load_paginated(conn)?;
+
+    Ok(results)
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, ToSchema)]
+pub enum ModerationAction {
+    HideReview,
+    SuspendUser,
+}
+
+// Resolving a report to Open would leave it in the queue, after acting on it
+fn closed(status: &ReportStatus) -> Result<(), ValidationError> {
+    if *status != ReportStatus::Open {
+        return Ok(());
+    }
+
+    let mut error = ValidationError::new("still_open");
+    error.message = Some("Must be Resolved or Dismissed".into());
+    Err(error)
+}
+
+#[derive(Deserialize, Debug, Validate, ToSchema)]
+pub struct ResolveReport {
+    /// `Resolved` or `Dismissed`
+    #[validate(custom = "closed")]
+    status: ReportStatus,
+    action: Option<ModerationAction>,
+}
+
+pub fn resolve_report(
+    conn: &mut PooledConn,
+    report_idx: i32,
+    admin_idx: i32,
+    resolution: ResolveReport,
+) -> Result<Report, ServiceError> {
+    use crate::schema::reports::dsl::*;
+
+    conn.transaction(|conn| {
+        let report = reports.find(report_idx).first::<Report>(conn)?;
+
+        match resolution.action {
+            Some(ModerationAction::HideReview) => {
+                let (Some(tmdb_id_v), Some(category_v)) = (report.tmdb_id, report.category) else {
+                    return Err(ServiceError::new(
+                        400,
+                        "not_a_review_report",
+                        "Report is not about a review",
+                    ));
+                };
+                set_review_hidden(
+                    conn,
+                    report.user_id,
+                    tmdb_id_v,
+                    category_v,
+                    report.season,
+                    true,
+                )?;
+            }
+            Some(ModerationAction::SuspendUser) => {
+                let suspend = ModerateUser {
+                    role: None,
+                    suspended: Some(true),
+                };
+                moderate_user(conn, report.user_id, suspend)?;
+            }
+            None => {}
+        }
+
+        let report = diesel::update(reports.find(report_idx))
+            .set((status.eq(resolution.status), resolved_by.eq(admin_idx)))
+            .get_result::<Report>(conn)?;
+
+        Ok(report)
+    })
+}
+
+pub fn count_open_reports(conn: &mut PooledConn) -> Result<i64, DbError> {
+    use crate::schema::reports::dsl::*;
+
+    let count = reports
+        .filter(status.eq(ReportStatus::Open))
+        .count()
+        .get_result(conn)?;
+
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use diesel::prelude::*;
+    use validator::Validate;
+
+    use super::{
+        report_review, report_user, resolve_report, InputReport, ModerationAction, ResolveReport,
+    };
+    use crate::{
+        errors::ServiceError,
+        models::{MediaCategory, Report, ReportReason, ReportStatus, ReviewVisibility},
+        schema::{reports, reviews, users},
+        testing::{self, review, user},
+        PooledConn,
+    };
+
+    fn spam() -> InputReport {
+        InputReport {
+            reason: ReportReason::Spam,
+            details: None,
+        }
+    }
+
+    fn resolve(
+        conn: &mut PooledConn,
+        report: &Report,
+        admin: i32,
+        action: Option<ModerationAction>,
+    ) -> Result<Report, ServiceError> {
+        let resolution = ResolveReport {
+            status: ReportStatus::Resolved,
+            action,
+        };
+        resolve_report(conn, report.id, admin, resolution)
+    }
+
+    fn is_hidden(conn: &mut PooledConn, author: i32) -> bool {
+        reviews::table
+            .filter(reviews::user_id.eq(author))
+            .select(reviews::hidden)
+            .first(conn)
+            .unwrap()
+    }
+
+    fn is_suspended(conn: &mut PooledConn, id: i32) -> bool {
+        users::table
+            .find(id)
+            .select(users::suspended_at.is_not_null())
+            .first(conn)
+            .unwrap()
+    }
+
+    #[test]
+    fn hiding_a_reported_review_resolves_the_report() {
+        let Some(mut conn) = testing::conn() else {
+            return;
+        };
+        let author = user(&mut conn, "author");
+        let reporter = user(&mut conn, "reporter");
+        let admin = user(&mut conn, "admin");
+        review(&mut conn, author, 1, Some(2), ReviewVisibility::Public);
+        let report = report_review(
+            &mut conn,
+            reporter,
+            author,
+            1,
+            MediaCategory::Show,
+            Some(2),
+            spam(),
+        )
+        .unwrap();
+
+        let resolved = resolve(
+            &mut conn,
+            &report,
+            admin,
+            Some(ModerationAction::HideReview),
+        )
+        .unwrap();
+
+        assert_eq!(resolved.status, ReportStatus::Resolved);
+        assert_eq!(resolved.resolved_by, Some(admin));
+        assert!(is_hidden(&mut conn, author));
+        assert!(!is_suspended(&mut conn, author));
+    }
+
+    #[test]
+    fn suspending_a_reported_user_resolves_the_report() {
+        let Some(mut conn) = testing::conn() else {
+            return;
+        };
+        let author = user(&mut conn, "author");
+        let reporter = user(&mut conn, "reporter");
+        let admin = user(&mut conn, "admin");
+        let report = report_user(&mut conn, reporter, author, spam()).unwrap();
+
+        let resolved = resolve(
+            &mut conn,
+            &report,
+            admin,
+            Some(ModerationAction::SuspendUser),
+        )
+        .unwrap();
+
+        assert_eq!(resolved.status, ReportStatus::Resolved);
+        assert!(is_suspended(&mut conn, author));
+    }
+
+    #[test]
+    fn only_review_reports_can_hide_reviews() {
+        let Some(mut conn) = testing::conn() else {
+            return;
+        };
+        let author = user(&mut conn, "author");
+        let reporter = user(&mut conn, "reporter");
+        let admin = user(&mut conn, "admin");
+        let report = report_user(&mut conn, reporter, author, spam()).unwrap();
+
+        let error = resolve(
+            &mut conn,
+            &report,
+            admin,
+            Some(ModerationAction::HideReview),
+        )
+        .unwrap_err();
+        let status: ReportStatus = reports::table
+            .find(report.id)
+            .select(reports::status)
+            .first(&mut conn)
+            .unwrap();
+
+        assert_eq!(error.code, "not_a_review_report");
+        assert_eq!(status, ReportStatus::Open);
+    }
+
+    #[test]
+    fn only_visible_reviews_and_other_users_can_be_reported() {
+        let Some(mut conn) = testing::conn() else {
+            return;
+        };
+        let author = user(&mut conn, "author");
+        let reporter = user(&mut conn, "reporter");
+        review(&mut conn, author, 1, None, ReviewVisibility::Private);
+
+        let error = report_review(
+            &mut conn,
+            reporter,
+            author,
+            1,
+            MediaCategory::Film,
+            None,
+            spam(),
+        )
+        .unwrap_err();
+        assert_eq!(error.status, 404);
+
+        let error = report_user(&mut conn, reporter, reporter, spam()).unwrap_err();
+        assert_eq!(error.code, "self_report");
+    }
+
+    #[test]
+    fn resolutions_have_to_close_the_report() {
+        let resolution = |status| ResolveReport {
+            status,
+            action: None,
+        };
+
+        assert!(resolution(ReportStatus::Resolved).validate().is_ok());
+        assert!(resolution(ReportStatus::Dismissed).validate().is_ok());
+
+        let e = ServiceError::from(resolution(ReportStatus::Open).validate().unwrap_err());
+        assert_eq!(
+            (e.fields[0].field.as_str(), &*e.fields[0].code),
+            ("status", "still_open")
+        );
+    }
+}
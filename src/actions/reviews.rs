@@ -1,14 +1,95 @@
-use diesel::{associations::HasTable, ExpressionMethods, QueryDsl, RunQueryDsl};
-use serde::Deserialize;
+use std::collections::HashMap;
+
+use diesel::{
+    associations::HasTable, pg::Pg, sql_types::Bool, BoolExpressionMethods, BoxableExpression,
+    ExpressionMethods, QueryDsl, RunQueryDsl,
+};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+use validator::Validate;
 
 use crate::{
-    errors::DbError,
-    models::{EditReview, MediaCategory, NewReview, Review, WatchStatus},
-    pagination::{Paginate, PaginatedResults},
+    actions::{media::find_media_batch, users::find_users_by_ids},
+    errors::{DbError, ServiceError},
+    models::{
+        EditReview, Media, MediaCategory, NewReview, Review, ReviewVisibility, User, WatchStatus,
+    },
+    pagination::{per_page_limit, Paginate, PaginatedResults},
+    schema::reviews,
+    tmdb::{Movie, MovieDetails, SeasonDetails, Show, ShowDetails},
     PooledConn,
 };
 
-#[derive(Deserialize)]
+pub type ReviewFilter = Box<dyn BoxableExpression<reviews::table, Pg, SqlType = Bool>>;
+
+/// Matches the reviews `viewer` is allowed to see, where `None` is an anonymous caller.
+///
+/// Authors see all of their own reviews. Everyone else sees `Public` reviews by public
+/// profiles, and accepted followers also see `Public` and `Followers` reviews. Reviews hidden
+/// by moderators and reviews by suspended users are only visible to their author, and
+/// nobody sees reviews across a block in either direction.
+/// Anything that returns other users' reviews must filter with this.
+pub fn visible_to(viewer: Option<i32>) -> ReviewFilter {
+    use crate::schema::{blocks, follows, reviews::dsl::*, users};
+
+    let public_authors = users::table
+        .filter(users::private.eq(false))
+        .filter(users::suspended_at.is_null())
+        .select(users::id);
+    let public = hidden.eq(false).and(
+        visibility
+            .eq(ReviewVisibility::Public)
+            .and(user_id.eq_any(public_authors)),
+    );
+
+    let Some(viewer) = viewer else {
+        return Box::new(public);
+    };
+
+    let followed = follows::table
+        .filter(follows::follower_id.eq(viewer))
+        .filter(follows::accepted.eq(true))
+        .select(follows::followee_id);
+    let active_authors = users::table
+        .filter(users::suspended_at.is_null())
+        .select(users::id);
+    let followers = hidden.eq(false).and(
+        visibility
+            .ne(ReviewVisibility::Private)
+            .and(user_id.eq_any(followed))
+            .and(user_id.eq_any(active_authors)),
+    );
+
+    let blocked_by = blocks::table
+        .filter(blocks::blocked_id.eq(viewer))
+        .select(blocks::user_id);
+    let blocking = blocks::table
+        .filter(blocks::user_id.eq(viewer))
+        .select(blocks::blocked_id);
+
+    Box::new(
+        user_id.eq(viewer).or(public
+            .or(followers)
+            .and(user_id.ne_all(blocked_by))
+            .and(user_id.ne_all(blocking))),
+    )
+}
+
+/// Filters out reviews by users `viewer` has muted.
+///
+/// Unlike [`visible_to`], this only applies to listings. Muted users' reviews can still
+/// be looked up directly.
+pub fn not_muted_by(viewer: i32) -> ReviewFilter {
+    use crate::schema::{mutes, reviews::dsl::*};
+
+    let muted = mutes::table
+        .filter(mutes::user_id.eq(viewer))
+        .select(mutes::muted_id);
+
+    Box::new(user_id.ne_all(muted))
+}
+
+#[derive(Deserialize, ToSchema)]
 pub enum SortBy {
     #[serde(rename = "tmdb_id.asc")]
     TmdbIdAsc,
@@ -24,28 +105,92 @@ pub enum SortBy {
     UpdatedAtDesc,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct ReviewsQuery {
+    #[validate(range(min = 1, max = 1000000))]
+    #[param(minimum = 1, maximum = 1000000)]
     pub page: Option<i64>,
+    /// Up to `pagination.max_per_page`
+    #[validate(custom = "per_page_limit")]
+    #[param(minimum = 1)]
     pub per_page: Option<i64>,
+    #[param(inline)]
     pub sort_by: Option<SortBy>,
     pub user_id: Option<i32>,
+    #[param(inline)]
     pub category: Option<MediaCategory>,
+    #[validate(range(min = 1))]
+    #[param(minimum = 1)]
     pub tmdb_id: Option<i32>,
+    #[validate(range(min = 0, max = 1000))]
+    #[param(minimum = 0, maximum = 1000)]
     pub season: Option<i32>,
+    #[param(inline)]
     pub status: Option<WatchStatus>,
     pub fun_before: Option<bool>,
     pub fun_during: Option<bool>,
     pub fun_after: Option<bool>,
+    /// Comma separated, any of `media` and `user`
+    #[validate(length(max = 100))]
+    #[param(max_length = 100)]
+    pub include: Option<String>,
+}
+
+/// What to embed in each review, from a comma separated `include` param.
+#[derive(Default, Clone, Copy)]
+pub struct Include {
+    pub media: bool,
+    pub user: bool,
+}
+
+impl Include {
+    pub fn parse(include: Option<&str>) -> Result<Include, ServiceError> {
+        let mut parsed = Include::default();
+
+        for value in include.unwrap_or_default().split(',').map(str::trim) {
+            match value {
+                "media" => parsed.media = true,
+                "user" => parsed.user = true,
+                "" => {}
+                _ => {
+                    return Err(ServiceError::new(
+                        400,
+                        "invalid_include",
+                        format!("Can't include {value}, try media or user"),
+                    ))
+                }
+            }
+        }
+
+        Ok(parsed)
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ReviewWithIncludes {
+    #[serde(flatten)]
+    pub review: Review,
+    // Outer None when not requested, inner None when not cached yet
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<Media>)]
+    pub media: Option<Option<Media>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<User>,
 }
 
 pub fn get_all_reviews(
     conn: &mut PooledConn,
+    viewer: Option<i32>,
     params: ReviewsQuery,
 ) -> Result<PaginatedResults<Review>, DbError> {
     use crate::schema::reviews::dsl::*;
 
-    let mut query = reviews::table().into_boxed();
+    let mut query = reviews::table().into_boxed().filter(visible_to(viewer));
+
+    if let (Some(viewer), None) = (viewer, params.user_id) {
+        query = query.filter(not_muted_by(viewer));
+    }
 
     if let Some(user_id_in) = params.user_id {
         query = query.filter(user_id.eq(user_id_in));
@@ -92,12 +237,216 @@ pub fn get_all_reviews(
     Ok(results)
 }
 
-#[derive(Deserialize, Debug)]
+/// Embeds media and authors into a page of reviews, with one query for each.
+pub fn include_details(
+    conn: &mut PooledConn,
+    page: PaginatedResults<Review>,
+    include: Include,
+) -> Result<PaginatedResults<ReviewWithIncludes>, DbError> {
+    let media: HashMap<_, Media> = if include.media {
+        let keys: Vec<_> = page
+            .results
+            .iter()
+            .map(|r| (r.tmdb_id, r.category))
+            .collect();
+        find_media_batch(conn, &keys)?
+            .into_iter()
+            .map(|m| ((m.tmdb_id, m.category), m))
+            .collect()
+    } else {
+        HashMap::new()
+    };
+
+    let users: HashMap<_, User> = if include.user {
+        let ids: Vec<_> = page.results.iter().map(|r| r.user_id).collect();
+        find_users_by_ids(conn, &ids)?
+            .into_iter()
+            .map(|u| (u.id, u))
+            .collect()
+    } else {
+        HashMap::new()
+    };
+
+    let results = page
+        .results
+        .into_iter()
+        .map(|review| ReviewWithIncludes {
+            media: include
+                .media
+                .then(|| media.get(&(review.tmdb_id, review.category)).cloned()),
+            user: users.get(&review.user_id).cloned(),
+            review,
+        })
+        .collect();
+
+    Ok(PaginatedResults {
+        results,
+        page: page.page,
+        total_pages: page.total_pages,
+        total_results: page.total_results,
+    })
+}
+
+/// Which of `keys` the user has reviewed, in any season.
+pub fn find_reviewed(
+    conn: &mut PooledConn,
+    user_id_v: i32,
+    keys: &[(i32, MediaCategory)],
+) -> Result<Vec<(i32, MediaCategory)>, DbError> {
+    use crate::schema::reviews::dsl::*;
+
+    let ids: Vec<i32> = keys.iter().map(|(id, _)| *id).collect();
+
+    let reviewed = reviews
+        .filter(user_id.eq(user_id_v))
+        .filter(tmdb_id.eq_any(ids))
+        .select((tmdb_id, category))
+        .distinct()
+        .load::<(i32, MediaCategory)>(conn)?
+        .into_iter()
+        .filter(|key| keys.contains(key))
+        .collect();
+
+    Ok(reviewed)
+}
+
+#[derive(Serialize, Default, ToSchema)]
+pub struct ReviewStats {
+    pub total: i64,
+    pub completed: i64,
+    pub watching: i64,
+    pub dropped: i64,
+    pub plan_to_watch: i64,
+    pub fun_before: i64,
+    pub fun_during: i64,
+    pub fun_after: i64,
+}
+
+impl ReviewStats {
+    fn add(
+        &mut self,
+        status: WatchStatus,
+        (before, during, after): (bool, bool, bool),
+        count: i64,
+    ) {
+        self.total += count;
+        match status {
+            WatchStatus::Completed => self.completed += count,
+            WatchStatus::Watching => self.watching += count,
+            WatchStatus::Dropped => self.dropped += count,
+            WatchStatus::PlanToWatch => self.plan_to_watch += count,
+        }
+        self.fun_before += if before { count } else { 0 };
+        self.fun_during += if during { count } else { 0 };
+        self.fun_after += if after { count } else { 0 };
+    }
+}
+
+/// A title with stats attached when they were asked for.
+#[derive(Serialize, ToSchema)]
+#[aliases(
+    FilmWithStats = WithStats<Movie>,
+    ShowWithStats = WithStats<Show>,
+    FilmDetailsWithStats = WithStats<MovieDetails>,
+    ShowDetailsWithStats = WithStats<ShowDetails>,
+    SeasonWithStats = WithStats<SeasonDetails>,
+)]
+pub struct WithStats<T> {
+    #[serde(flatten)]
+    pub item: T,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stats: Option<ReviewStats>,
+}
+
+/// Counts the reviews of a title `viewer` can see, optionally for just one season.
+pub fn get_review_stats(
+    conn: &mut PooledConn,
+    viewer: Option<i32>,
+    tmdb_id_v: i32,
+    category_v: MediaCategory,
+    season_v: Option<i32>,
+) -> Result<ReviewStats, DbError> {
+    use crate::schema::reviews::dsl::*;
+    use diesel::{dsl::count_star, IntoSql};
+
+    // Boxed queries can't be grouped, so only the season filter is boxed
+    let season_filter: ReviewFilter = match season_v {
+        Some(season_v) => Box::new(season.eq(season_v)),
+        None => Box::new(true.into_sql::<Bool>()),
+    };
+
+    // At most one row per combination, so this stays small however many reviews there are
+    let groups = reviews
+        .filter(visible_to(viewer))
+        .filter(tmdb_id.eq(tmdb_id_v))
+        .filter(category.eq(category_v))
+        .filter(season_filter)
+        .group_by((status, fun_before, fun_during, fun_after))
+        .select((status, fun_before, fun_during, fun_after, count_star()))
+        .load::<(WatchStatus, bool, bool, bool, i64)>(conn)?;
+
+    let mut stats = ReviewStats::default();
+
+    for (status_v, before, during, after, count) in groups {
+        stats.add(status_v, (before, during, after), count);
+    }
+
+    Ok(stats)
+}
+
+/// Stats for a page of titles in one query, titles without visible reviews are left out.
+pub fn get_review_stats_batch(
+    conn: &mut PooledConn,
+    viewer: Option<i32>,
+    keys: &[(i32, MediaCategory)],
+) -> Result<HashMap<(i32, MediaCategory), ReviewStats>, DbError> {
+    use crate::schema::reviews::dsl::*;
+    use diesel::dsl::count_star;
+
+    let ids: Vec<i32> = keys.iter().map(|(id, _)| *id).collect();
+
+    let groups = reviews
+        .filter(visible_to(viewer))
+        .filter(tmdb_id.eq_any(ids))
+        .group_by((tmdb_id, category, status, fun_before, fun_during, fun_after))
+        .select((
+            tmdb_id,
+            category,
+            status,
+            fun_before,
+            fun_during,
+            fun_after,
+            count_star(),
+        ))
+        .load::<(i32, MediaCategory, WatchStatus, bool, bool, bool, i64)>(conn)?;
+
+    let mut stats: HashMap<_, ReviewStats> = HashMap::new();
+
+    for (tmdb_id_v, category_v, status_v, before, during, after, count) in groups {
+        if keys.contains(&(tmdb_id_v, category_v)) {
+            stats.entry((tmdb_id_v, category_v)).or_default().add(
+                status_v,
+                (before, during, after),
+                count,
+            );
+        }
+    }
+
+    Ok(stats)
+}
+
+#[derive(Deserialize, Debug, Validate, ToSchema)]
 pub struct InputReview {
-    tmdb_id: i32,
-    category: MediaCategory,
-    status: WatchStatus,
-    season: Option<i32>,
+    #[validate(range(min = 1))]
+    #[schema(minimum = 1)]
+    pub tmdb_id: i32,
+    pub category: MediaCategory,
+    pub status: WatchStatus,
+    /// Required for shows, 0 is for specials
+    #[validate(range(min = 0, max = 1000))]
+    #[schema(minimum = 0, maximum = 1000)]
+    pub season: Option<i32>,
+    pub visibility: Option<ReviewVisibility>,
 }
 
 pub fn create_review_for_user(
@@ -117,6 +466,7 @@ pub fn create_review_for_user(
         fun_before: false,
         fun_during: false,
         fun_after: false,
+        visibility: input_review.visibility.unwrap_or(ReviewVisibility::Public),
     };
 
     let res = diesel::insert_into(reviews)
@@ -173,3 +523,203 @@ pub fn delete_review(
 
     Ok(deleted)
 }
+
+pub fn set_review_hidden(
+    conn: &mut PooledConn,
+    user_id_v: i32,
+    tmdb_id_v: i32,
+    category_v: MediaCategory,
+    season_v: Option<i32>,
+    hidden_v: bool,
+) -> Result<Review, ServiceError> {
+    use crate::schema::reviews::dsl::*;
+
+    let season_v = season_v.unwrap_or(-1);
+
+    let review = diesel::update(
+        reviews
+            .filter(user_id.eq(user_id_v))
+            .filter(tmdb_id.eq(tmdb_id_v))
+            .filter(category.eq(category_v))
+            .filter(season.eq(season_v)),
+    )
+    .set(hidden.eq(hidden_v))
+    .get_result(conn)?;
+
+    Ok(review)
+}
+
+/// Position of a review within a user's reviews, used to page through all of them.
+pub type ReviewKey = (i32, MediaCategory, i32);
+
+/// All of a user's reviews after `after`, ordered by primary key. Unlike [`get_all_reviews`],
+/// this isn't limited by `pagination.max_per_page` and doesn't check visibility.
+pub fn get_reviews_after(
+    conn: &mut PooledConn,
+    user_id_v: i32,
+    after: Option<ReviewKey>,
+    limit: i64,
+) -> Result<Vec<Review>, DbError> {
+    use crate::schema::reviews::dsl::*;
+
+    let mut query = reviews
+        .filter(user_id.eq(user_id_v))
+        .order((tmdb_id.asc(), category.asc(), season.asc()))
+        .limit(limit)
+        .into_boxed();
+
+    if let Some((tmdb_id_v, category_v, season_v)) = after {
+        query = query.filter(
+            tmdb_id
+                .gt(tmdb_id_v)
+                .or(tmdb_id.eq(tmdb_id_v).and(category.gt(category_v)))
+                .or(tmdb_id
+                    .eq(tmdb_id_v)
+                    .and(category.eq(category_v))
+                    .and(season.gt(season_v))),
+        );
+    }
+
+    let results = query.load(conn)?;
+
+    Ok(results)
+}
+
+/// Deletes every one of a user's reviews, e.g. after spam. Returns how many there were.
+pub fn delete_reviews_by_user(conn: &mut PooledConn, user_id_v: i32) -> Result<usize, DbError> {
+    use crate::schema::reviews::dsl::*;
+
+    let deleted = diesel::delete(reviews.filter(user_id.eq(user_id_v))).execute(conn)?;
+
+    Ok(deleted)
+}
+
+pub fn count_reviews(conn: &mut PooledConn) -> Result<i64, DbError> {
+    use crate::schema::reviews::dsl::*;
+
+    let count = reviews.count().get_result(conn)?;
+
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use diesel::prelude::*;
+
+    use super::visible_to;
+    use crate::{
+        models::{NewBlock, ReviewVisibility},
+        schema::{blocks, reviews},
+        testing::{self, follow, make_private, review, suspend, user},
+        PooledConn,
+    };
+
+    // The tmdb_ids of the reviews `viewer` can see by `author`
+    fn visible(conn: &mut PooledConn, viewer: Option<i32>, author: i32) -> Vec<i32> {
+        reviews::table
+            .filter(visible_to(viewer))
+            .filter(reviews::user_id.eq(author))
+            .select(reviews::tmdb_id)
+            .order(reviews::tmdb_id)
+            .load(conn)
+            .unwrap()
+    }
+
+    // One review of each visibility, with tmdb_ids 1 to 3
+    fn reviews_of_each_visibility(conn: &mut PooledConn, author: i32) {
+        review(conn, author, 1, None, ReviewVisibility::Public);
+        review(conn, author, 2, None, ReviewVisibility::Followers);
+        review(conn, author, 3, None, ReviewVisibility::Private);
+    }
+
+    #[test]
+    fn everyone_sees_public_reviews_by_public_profiles() {
+        let Some(mut conn) = testing::conn() else {
+            return;
+        };
+        let author = user(&mut conn, "author");
+        let stranger = user(&mut conn, "stranger");
+        reviews_of_each_visibility(&mut conn, author);
+
+        assert_eq!(visible(&mut conn, None, author), [1]);
+        assert_eq!(visible(&mut conn, Some(stranger), author), [1]);
+    }
+
+    #[test]
+    fn accepted_followers_also_see_followers_reviews() {
+        let Some(mut conn) = testing::conn() else {
+            return;
+        };
+        let author = user(&mut conn, "author");
+        let accepted = user(&mut conn, "accepted");
+        let pending = user(&mut conn, "pending");
+        make_private(&mut conn, author);
+        follow(&mut conn, accepted, author, true);
+        follow(&mut conn, pending, author, false);
+        reviews_of_each_visibility(&mut conn, author);
+
+        assert_eq!(visible(&mut conn, Some(accepted), author), [1, 2]);
+        assert!(visible(&mut conn, Some(pending), author).is_empty());
+        assert!(visible(&mut conn, None, author).is_empty());
+    }
+
+    #[test]
+    fn authors_see_all_of_their_own() {
+        let Some(mut conn) = testing::conn() else {
+            return;
+        };
+        let author = user(&mut conn, "author");
+        reviews_of_each_visibility(&mut conn, author);
+        suspend(&mut conn, author);
+        diesel::update(reviews::table.filter(reviews::user_id.eq(author)))
+            .set(reviews::hidden.eq(true))
+            .execute(&mut conn)
+            .unwrap();
+
+        assert_eq!(visible(&mut conn, Some(author), author), [1, 2, 3]);
+    }
+
+    #[test]
+    fn hidden_reviews_and_suspended_authors_are_left_out() {
+        let Some(mut conn) = testing::conn() else {
+            return;
+        };
+        let author = user(&mut conn, "author");
+        let suspended = user(&mut conn, "suspended");
+        let follower = user(&mut conn, "follower");
+        follow(&mut conn, follower, suspended, true);
+        review(&mut conn, author, 1, None, ReviewVisibility::Public);
+        review(&mut conn, author, 2, None, ReviewVisibility::Public);
+        review(&mut conn, suspended, 1, None, ReviewVisibility::Public);
+        suspend(&mut conn, suspended);
+        diesel::update(reviews::table.filter(reviews::user_id.eq(author)))
+            .filter(reviews::tmdb_id.eq(2))
+            .set(reviews::hidden.eq(true))
+            .execute(&mut conn)
+            .unwrap();
+
+        assert_eq!(visible(&mut conn, None, author), [1]);
+        assert!(visible(&mut conn, Some(follower), suspended).is_empty());
+    }
+
+    #[test]
+    fn blocks_hide_reviews_both_ways() {
+        let Some(mut conn) = testing::conn() else {
+            return;
+        };
+        let blocker = user(&mut conn, "blocker");
+        let blocked = user(&mut conn, "blocked");
+        review(&mut conn, blocker, 1, None, ReviewVisibility::Public);
+        review(&mut conn, blocked, 1, Some(1), ReviewVisibility::Public);
+        diesel::insert_into(blocks::table)
+            .values(NewBlock {
+                user_id: blocker,
+                blocked_id: blocked,
+            })
+            .execute(&mut conn)
+            .unwrap();
+
+        assert!(visible(&mut conn, Some(blocked), blocker).is_empty());
+        assert!(visible(&mut conn, Some(blocker), blocked).is_empty());
+    }
+}
@@ -1,16 +1,48 @@
 use crate::{
     errors::{DbError, ServiceError},
-    models::{AuthenticatedUser, NewUser, User},
-    pagination::{Paginate, PaginatedResults},
+    models::{AuthenticatedUser, NewUser, User, UserRole},
+    pagination::{per_page_limit, Paginate, PaginatedResults},
     schema::users,
     utils::hash_password,
+    validation::password_strength,
     PooledConn,
 };
 
-use diesel::{associations::HasTable, prelude::*};
+use diesel::{associations::HasTable, pg::Pg, prelude::*, sql_types::Bool};
 use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+use validator::Validate;
 
-#[derive(Deserialize)]
+pub type UserFilter = Box<dyn BoxableExpression<users::table, Pg, SqlType = Bool>>;
+
+/// Matches the profiles `viewer` is allowed to see, where `None` is an anonymous caller.
+///
+/// Private profiles are only visible to themselves and their accepted followers.
+/// Suspended profiles are only visible to themselves, and users can't see anyone who blocked them.
+pub fn profile_visible_to(viewer: Option<i32>) -> UserFilter {
+    use crate::schema::{blocks, follows, users::dsl::*};
+
+    let Some(viewer) = viewer else {
+        return Box::new(private.eq(false).and(suspended_at.is_null()));
+    };
+
+    let followed = follows::table
+        .filter(follows::follower_id.eq(viewer))
+        .filter(follows::accepted.eq(true))
+        .select(follows::followee_id);
+    let blocked_by = blocks::table
+        .filter(blocks::blocked_id.eq(viewer))
+        .select(blocks::user_id);
+
+    Box::new(
+        id.eq(viewer).or(suspended_at
+            .is_null()
+            .and(id.ne_all(blocked_by))
+            .and(private.eq(false).or(id.eq_any(followed)))),
+    )
+}
+
+#[derive(Deserialize, ToSchema)]
 pub enum SortBy {
     #[serde(rename = "id.asc")]
     IdAsc,
@@ -29,20 +61,30 @@ pub enum SortBy {
     #[serde(rename = "updated_at.desc")]
     UpdatedAtDesc,
 }
-#[derive(Deserialize)]
+#[derive(Deserialize, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct QueryParams {
+    #[validate(range(min = 1, max = 1000000))]
+    #[param(minimum = 1, maximum = 1000000)]
     pub page: Option<i64>,
+    /// Up to `pagination.max_per_page`
+    #[validate(custom = "per_page_limit")]
+    #[param(minimum = 1)]
     pub per_page: Option<i64>,
+    #[param(inline)]
     pub sort_by: Option<SortBy>,
 }
 
 pub fn get_all_users(
     conn: &mut PooledConn,
+    viewer: Option<i32>,
     params: QueryParams,
 ) -> Result<PaginatedResults<User>, DbError> {
     use crate::schema::users::dsl::*;
 
-    let mut query = users::table().into_boxed();
+    let mut query = users::table()
+        .into_boxed()
+        .filter(profile_visible_to(viewer));
 
     if let Some(sort_by) = params.sort_by {
         query = match sort_by {
@@ -66,14 +108,31 @@ pub fn get_all_users(
     Ok(results)
 }
 
-pub fn find_user_by_id(conn: &mut PooledConn, idx: i32) -> Result<Option<User>, DbError> {
+pub fn find_user_by_id(
+    conn: &mut PooledConn,
+    viewer: Option<i32>,
+    idx: i32,
+) -> Result<Option<User>, DbError> {
     use crate::schema::users::dsl::*;
 
-    let user = users.find(idx).first(conn).optional()?;
+    let user = users
+        .find(idx)
+        .filter(profile_visible_to(viewer))
+        .first(conn)
+        .optional()?;
 
     Ok(user)
 }
 
+/// Finds the authors of already visible content, so no profile filter.
+pub fn find_users_by_ids(conn: &mut PooledConn, ids: &[i32]) -> Result<Vec<User>, DbError> {
+    use crate::schema::users::dsl::*;
+
+    let found = users.filter(id.eq_any(ids)).load(conn)?;
+
+    Ok(found)
+}
+
 pub fn find_auth_user_by_id(
     conn: &mut PooledConn,
     idx: i32,
@@ -85,6 +144,25 @@ pub fn find_auth_user_by_id(
     Ok(user)
 }
 
+/// What's needed to authorize a request.
+#[derive(Debug, Queryable)]
+pub struct UserAccess {
+    pub role: UserRole,
+    pub suspended_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+pub fn find_user_access(conn: &mut PooledConn, idx: i32) -> Result<Option<UserAccess>, DbError> {
+    use crate::schema::users::dsl::*;
+
+    let access = users
+        .find(idx)
+        .select((role, suspended_at))
+        .first(conn)
+        .optional()?;
+
+    Ok(access)
+}
+
 pub fn find_auth_user_by_email(
     conn: &mut PooledConn,
     email_in: &str,
@@ -99,11 +177,16 @@ pub fn find_auth_user_by_email(
     Ok(user)
 }
 
-#[derive(Debug, Serialize, Deserialize, AsChangeset)]
+#[derive(Debug, Serialize, Deserialize, AsChangeset, Validate, ToSchema)]
 #[diesel(table_name = users)]
 pub struct UpdateUser {
+    #[validate(length(min = 1, max = 32))]
+    #[schema(min_length = 1, max_length = 32)]
     pub name: Option<String>,
+    #[validate(email, length(max = 254))]
+    #[schema(max_length = 254)]
     pub email: Option<String>,
+    pub private: Option<bool>,
 }
 
 pub fn update_auth_user_by_id(
@@ -113,8 +196,50 @@ pub fn update_auth_user_by_id(
 ) -> Result<AuthenticatedUser, DbError> {
     use crate::schema::users::dsl::*;
 
+    let make_public = update.private == Some(false);
+
+    conn.transaction(|conn| {
+        let user = diesel::update(users.find(idx))
+            .set(update)
+            .get_result::<AuthenticatedUser>(conn)?;
+
+        // Nobody is left waiting on a profile that no longer needs approval
+        if make_public {
+            use crate::schema::follows::dsl::{accepted, followee_id, follows};
+
+            diesel::update(follows.filter(followee_id.eq(idx)))
+                .set(accepted.eq(true))
+                .execute(conn)?;
+        }
+
+        Ok(user)
+    })
+}
+
+#[derive(Debug, Deserialize, Validate, ToSchema)]
+pub struct ModerateUser {
+    pub role: Option<UserRole>,
+    pub suspended: Option<bool>,
+}
+
+pub fn moderate_user(
+    conn: &mut PooledConn,
+    idx: i32,
+    moderation: ModerateUser,
+) -> Result<AuthenticatedUser, ServiceError> {
+    use crate::schema::users::dsl::*;
+
+    let user = users.find(idx).first::<AuthenticatedUser>(conn)?;
+
+    let role_v = moderation.role.unwrap_or(user.role);
+    let suspended_at_v = match moderation.suspended {
+        Some(true) => user.suspended_at.or_else(|| Some(chrono::Utc::now())),
+        Some(false) => None,
+        None => user.suspended_at,
+    };
+
     let user = diesel::update(users.find(idx))
-        .set(update)
+        .set((role.eq(role_v), suspended_at.eq(suspended_at_v)))
         .get_result::<AuthenticatedUser>(conn)?;
 
     Ok(user)
@@ -128,17 +253,28 @@ pub fn delete_user_by_id(conn: &mut PooledConn, idx: i32) -> Result<usize, DbErr
     Ok(deleted)
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Validate, ToSchema)]
 pub struct InputUser {
+    #[validate(length(min = 1, max = 32))]
+    #[schema(min_length = 1, max_length = 32)]
     pub name: String,
+    #[validate(email, length(max = 254))]
+    #[schema(max_length = 254)]
     pub email: String,
+    /// At least 8 characters, with a letter and a number or symbol
+    #[validate(custom = "password_strength", length(max = 128))]
+    #[schema(format = Password, min_length = 8, max_length = 128)]
     pub password: String,
 }
 
-pub fn create_user(conn: &mut PooledConn, user: InputUser) -> Result<User, ServiceError> {
+pub fn create_user(
+    conn: &mut PooledConn,
+    user: InputUser,
+    secret_key: &str,
+) -> Result<User, ServiceError> {
     use crate::schema::users::dsl::users;
 
-    let hash = &hash_password(&user.password)?;
+    let hash = &hash_password(&user.password, secret_key)?;
 
     let new_user = NewUser {
         name: &user.name,
@@ -152,3 +288,161 @@ pub fn create_user(conn: &mut PooledConn, user: InputUser) -> Result<User, Servi
 
     Ok(user)
 }
+
+/// Every user, including private and suspended ones, oldest first. Only for the admin CLI.
+pub fn list_all_users(
+    conn: &mut PooledConn,
+    suspended_only: bool,
+) -> Result<Vec<AuthenticatedUser>, DbError> {
+    use crate::schema::users::dsl::*;
+
+    let mut query = users.order(id.asc()).into_boxed();
+
+    if suspended_only {
+        query = query.filter(suspended_at.is_not_null());
+    }
+
+    let found = query.load(conn)?;
+
+    Ok(found)
+}
+
+pub fn reset_password(
+    conn: &mut PooledConn,
+    idx: i32,
+    password: &str,
+    secret_key: &str,
+) -> Result<AuthenticatedUser, ServiceError> {
+    use crate::schema::users::dsl::*;
+
+    let hash_v = hash_password(password, secret_key)?;
+
+    let user = diesel::update(users.find(idx))
+        .set(hash.eq(hash_v))
+        .get_result::<AuthenticatedUser>(conn)?;
+
+    Ok(user)
+}
+
+#[derive(Debug, Serialize)]
+pub struct UserCounts {
+    pub users: i64,
+    pub admins: i64,
+    pub suspended: i64,
+}
+
+pub fn count_users(conn: &mut PooledConn) -> Result<UserCounts, DbError> {
+    use crate::schema::users::dsl::*;
+
+    Ok(UserCounts {
+        users: users.count().get_result(conn)?,
+        admins: users
+            .filter(role.eq(UserRole::Admin))
+            .count()
+            .get_result(conn)?,
+        suspended: users
+            .filter(suspended_at.is_not_null())
+            .count()
+            .get_result(conn)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use diesel::prelude::*;
+
+    use super::profile_visible_to;
+    use crate::{
+        models::NewBlock,
+        schema::{blocks, users},
+        testing::{self, follow, make_private, suspend, user},
+        PooledConn,
+    };
+
+    fn visible(conn: &mut PooledConn, viewer: Option<i32>, ids: &[i32]) -> Vec<i32> {
+        users::table
+            .filter(profile_visible_to(viewer))
+            .filter(users::id.eq_any(ids))
+            .select(users::id)
+            .order(users::id)
+            .load(conn)
+            .unwrap()
+    }
+
+    #[test]
+    fn anonymous_callers_see_public_active_profiles() {
+        let Some(mut conn) = testing::conn() else {
+            return;
+        };
+        let public = user(&mut conn, "public");
+        let private = user(&mut conn, "private");
+        let suspended = user(&mut conn, "suspended");
+        make_private(&mut conn, private);
+        suspend(&mut conn, suspended);
+
+        assert_eq!(
+            visible(&mut conn, None, &[public, private, suspended]),
+            [public]
+        );
+    }
+
+    #[test]
+    fn private_profiles_are_visible_to_accepted_followers() {
+        let Some(mut conn) = testing::conn() else {
+            return;
+        };
+        let private = user(&mut conn, "private");
+        let accepted = user(&mut conn, "accepted");
+        let pending = user(&mut conn, "pending");
+        make_private(&mut conn, private);
+        follow(&mut conn, accepted, private, true);
+        follow(&mut conn, pending, private, false);
+
+        assert_eq!(visible(&mut conn, Some(accepted), &[private]), [private]);
+        assert!(visible(&mut conn, Some(pending), &[private]).is_empty());
+    }
+
+    #[test]
+    fn users_always_see_themselves() {
+        let Some(mut conn) = testing::conn() else {
+            return;
+        };
+        let me = user(&mut conn, "me");
+        make_private(&mut conn, me);
+        suspend(&mut conn, me);
+
+        assert_eq!(visible(&mut conn, Some(me), &[me]), [me]);
+    }
+
+    #[test]
+    fn suspended_profiles_are_hidden_from_followers() {
+        let Some(mut conn) = testing::conn() else {
+            return;
+        };
+        let suspended = user(&mut conn, "suspended");
+        let follower = user(&mut conn, "follower");
+        follow(&mut conn, follower, suspended, true);
+        suspend(&mut conn, suspended);
+
+        assert!(visible(&mut conn, Some(follower), &[suspended]).is_empty());
+    }
+
+    #[test]
+    fn blockers_are_hidden_from_who_they_blocked() {
+        let Some(mut conn) = testing::conn() else {
+            return;
+        };
+        let blocker = user(&mut conn, "blocker");
+        let blocked = user(&mut conn, "blocked");
+        diesel::insert_into(blocks::table)
+            .values(NewBlock {
+                user_id: blocker,
+                blocked_id: blocked,
+            })
+            .execute(&mut conn)
+            .unwrap();
+
+        assert!(visible(&mut conn, Some(blocked), &[blocker]).is_empty());
+        assert_eq!(visible(&mut conn, Some(blocker), &[blocked]), [blocked]);
+    }
+}
@@ -0,0 +1,323 @@
+// Caches TMDB responses in Redis, or in memory when Redis isn't available
+
+use std::{
+    collections::HashSet,
+    num::NonZeroUsize,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use lru::LruCache;
+use redis::{aio::ConnectionManager, AsyncCommands};
+use serde::{Deserialize, Serialize};
+
+use crate::errors::ServiceError;
+
+const KEY_PREFIX: &str = "tmdb:";
+// How long past its TTL an entry is still served while it's refreshed
+const STALE_FOR: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CacheBackend {
+    Redis,
+    Memory,
+}
+
+impl FromStr for CacheBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "redis" => Ok(CacheBackend::Redis),
+            "memory" => Ok(CacheBackend::Memory),
+            other => Err(format!("must be redis or memory, not {other}")),
+        }
+    }
+}
+
+/// The `[cache]` config section.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheConfig {
+    /// Memory is also the fallback when Redis can't be reached
+    pub backend: CacheBackend,
+    /// How many responses the memory cache holds
+    pub capacity: usize,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig {
+            backend: CacheBackend::Redis,
+            capacity: 1000,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Entry {
+    pub body: String,
+    fresh_until: i64,
+}
+
+impl Entry {
+    pub fn is_fresh(&self) -> bool {
+        chrono::Utc::now().timestamp() < self.fresh_until
+    }
+}
+
+type Memory = Arc<Mutex<LruCache<String, (Entry, i64)>>>;
+
+#[derive(Clone)]
+enum Backend {
+    Redis(ConnectionManager),
+    Memory(Memory),
+}
+
+/// Cheap to clone, every clone shares the same cache.
+#[derive(Clone)]
+pub struct ResponseCache {
+    backend: Backend,
+    refreshing: Arc<Mutex<HashSet<String>>>,
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Connects to Redis, reconnecting by itself afterwards. Shared by the response cache and the
+/// readiness check.
+pub async fn connect_redis(url: &str) -> redis::RedisResult<ConnectionManager> {
+    ConnectionManager::new(redis::Client::open(url)?).await
+}
+
+impl ResponseCache {
+    /// An in-process LRU cache holding up to `capacity` responses.
+    pub fn memory(capacity: usize) -> Self {
+        let capacity = NonZeroUsize::new(capacity).unwrap_or(NonZeroUsize::MIN);
+
+        ResponseCache {
+            backend: Backend::Memory(Arc::new(Mutex::new(LruCache::new(capacity)))),
+            refreshing: Default::default(),
+        }
+    }
+
+    /// Uses `redis` unless configured not to, falling back to memory without it.
+    pub fn new(config: &CacheConfig, redis: Option<ConnectionManager>) -> Self {
+        match redis {
+            Some(manager) if config.backend == CacheBackend::Redis => ResponseCache {
+                backend: Backend::Redis(manager),
+                refreshing: Default::default(),
+            },
+            None if config.backend == CacheBackend::Redis => {
+                tracing::warn!("Caching in memory, Redis isn't connected");
+                ResponseCache::memory(config.capacity)
+            }
+            _ => ResponseCache::memory(config.capacity),
+        }
+    }
+
+    /// Fresh or stale, `None` once it's too old to serve.
+    pub async fn get(&self, key: &str) -> Option<Entry> {
+        let key = format!("{KEY_PREFIX}{key}");
+
+        match &self.backend {
+            Backend::Redis(manager) => {
+                let mut conn = manager.clone();
+                let value: Option<String> = match conn.get(&key).await {
+                    Ok(value) => value,
+                    Err(e) => {
+                        tracing::warn!("Couldn't read {key} from Redis: {e}");
+                        None
+                    }
+                };
+
+                serde_json::from_str(&value?).ok()
+            }
+            Backend::Memory(memory) => {
+                let mut memory = lock(memory);
+                match memory.get(&key) {
+                    Some((entry, expires_at)) if chrono::Utc::now().timestamp() < *expires_at => {
+                        Some(entry.clone())
+                    }
+                    Some(_) => {
+                        memory.pop(&key);
+                        None
+                    }
+                    None => None,
+                }
+            }
+        }
+    }
+
+    /// Errors are only logged, caching is best effort.
+    pub async fn set(&self, key: &str, body: String, ttl: Duration) {
+        let key = format!("{KEY_PREFIX}{key}");
+        let now = chrono::Utc::now().timestamp();
+        let entry = Entry {
+            body,
+            fresh_until: now + ttl.as_secs() as i64,
+        };
+        let keep_for = ttl + STALE_FOR;
+
+        match &self.backend {
+            Backend::Redis(manager) => {
+                let Ok(value) = serde_json::to_string(&entry) else {
+                    return;
+                };
+                let mut conn = manager.clone();
+                let res: redis::RedisResult<()> =
+                    conn.set_ex(&key, value, keep_for.as_secs() as usize).await;
+                if let Err(e) = res {
+                    tracing::warn!("Couldn't write {key} to Redis: {e}");
+                }
+            }
+            Backend::Memory(memory) => {
+                lock(memory).put(key, (entry, now + keep_for.as_secs() as i64));
+            }
+        }
+    }
+
+    /// Removes every entry starting with `prefix`, or everything. Returns how many went.
+    pub async fn purge(&self, prefix: Option<&str>) -> Result<usize, ServiceError> {
+        let prefix = format!("{KEY_PREFIX}{}", prefix.unwrap_or_default());
+
+        match &self.backend {
+            Backend::Redis(manager) => {
+                let redis_error = ServiceError::internal;
+
+                // Escape glob characters so the prefix is matched literally
+                let pattern = prefix.chars().fold(String::new(), |mut pattern, c| {
+                    if matches!(c, '*' | '?' | '[' | ']' | '\\') {
+                        pattern.push('\\');
+                    }
+                    pattern.push(c);
+                    pattern
+                }) + "*";
+
+                let mut conn = manager.clone();
+                let mut keys: Vec<String> = Vec::new();
+                {
+                    let mut iter = conn
+                        .scan_match::<_, String>(pattern)
+                        .await
+                        .map_err(redis_error)?;
+                    while let Some(key) = iter.next_item().await {
+                        keys.push(key);
+                    }
+                }
+
+                for chunk in keys.chunks(500) {
+                    conn.del::<_, ()>(chunk).await.map_err(redis_error)?;
+                }
+
+                Ok(keys.len())
+            }
+            Backend::Memory(memory) => {
+                let mut memory = lock(memory);
+                let keys: Vec<String> = memory
+                    .iter()
+                    .map(|(key, _)| key)
+                    .filter(|key| key.starts_with(&prefix))
+                    .cloned()
+                    .collect();
+
+                for key in &keys {
+                    memory.pop(key);
+                }
+
+                Ok(keys.len())
+            }
+        }
+    }
+
+    /// Claims `key` for refreshing, false if it's already being refreshed.
+    pub fn start_refresh(&self, key: &str) -> bool {
+        lock(&self.refreshing).insert(key.to_string())
+    }
+
+    pub fn finish_refresh(&self, key: &str) {
+        lock(&self.refreshing).remove(key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{lock, Backend, Entry, ResponseCache, KEY_PREFIX};
+
+    const HOUR: Duration = Duration::from_secs(60 * 60);
+
+    #[actix_web::test]
+    async fn gets_what_was_set() {
+        let cache = ResponseCache::memory(10);
+        assert!(cache.get("/movie/1?").await.is_none());
+
+        cache.set("/movie/1?", "body".to_string(), HOUR).await;
+        let entry = cache.get("/movie/1?").await.unwrap();
+
+        assert_eq!(entry.body, "body");
+        assert!(entry.is_fresh());
+    }
+
+    #[actix_web::test]
+    async fn serves_stale_entries_until_they_expire() {
+        let cache = ResponseCache::memory(10);
+
+        cache.set("stale", "body".to_string(), Duration::ZERO).await;
+        let entry = cache.get("stale").await.unwrap();
+        assert!(!entry.is_fresh());
+
+        let Backend::Memory(memory) = &cache.backend else {
+            unreachable!()
+        };
+        let expired = Entry {
+            body: "body".to_string(),
+            fresh_until: 0,
+        };
+        lock(memory).put(format!("{KEY_PREFIX}expired"), (expired, 0));
+        assert!(cache.get("expired").await.is_none());
+    }
+
+    #[actix_web::test]
+    async fn evicts_the_least_recently_used() {
+        let cache = ResponseCache::memory(2);
+
+        for key in ["a", "b", "c"] {
+            cache.set(key, key.to_string(), HOUR).await;
+        }
+
+        assert!(cache.get("a").await.is_none());
+        assert!(cache.get("c").await.is_some());
+    }
+
+    #[actix_web::test]
+    async fn purges_by_prefix() {
+        let cache = ResponseCache::memory(10);
+        for key in ["/search/movie?query=a", "/search/tv?query=a", "/movie/1?"] {
+            cache.set(key, String::new(), HOUR).await;
+        }
+
+        assert_eq!(cache.purge(Some("/search")).await.unwrap(), 2);
+        assert!(cache.get("/search/tv?query=a").await.is_none());
+        assert!(cache.get("/movie/1?").await.is_some());
+
+        assert_eq!(cache.purge(None).await.unwrap(), 1);
+        assert!(cache.get("/movie/1?").await.is_none());
+    }
+
+    #[test]
+    fn refreshes_each_key_once_at_a_time() {
+        let cache = ResponseCache::memory(10);
+
+        assert!(cache.start_refresh("a"));
+        assert!(!cache.start_refresh("a"));
+        assert!(cache.start_refresh("b"));
+
+        cache.finish_refresh("a");
+        assert!(cache.start_refresh("a"));
+    }
+}
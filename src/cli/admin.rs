@@ -0,0 +1,205 @@
+// `review-api admin`, for what would otherwise take raw SQL, e.g. creating the first admin
+
+use std::io::BufRead;
+
+use diesel::Connection;
+use rand::{distributions::Alphanumeric, Rng};
+use validator::{Validate, ValidationErrors};
+
+use crate::{
+    actions::{
+        reports::count_open_reports,
+        reviews::{count_reviews, delete_reviews_by_user},
+        users::{
+            count_users, create_user, delete_user_by_id, list_all_users, moderate_user,
+            reset_password, InputUser, ModerateUser,
+        },
+    },
+    cli::Admin,
+    errors::ServiceError,
+    models::AuthenticatedUser,
+    validation::password_strength,
+    PooledConn,
+};
+
+const GENERATED_PASSWORD_LEN: usize = 20;
+
+fn generate_password() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(GENERATED_PASSWORD_LEN)
+        .map(char::from)
+        .collect()
+}
+
+/// The password piped in with `--password-stdin`, or a generated one to print.
+fn password(from_stdin: bool) -> Result<(String, bool), ServiceError> {
+    if !from_stdin {
+        return Ok((generate_password(), true));
+    }
+
+    let mut line = String::new();
+    std::io::stdin().lock().read_line(&mut line).map_err(|e| {
+        ServiceError::new(400, "invalid_password", format!("Couldn't read stdin: {e}"))
+    })?;
+    let password = line.trim_end_matches(['\r', '\n']);
+
+    if password.is_empty() {
+        return Err(ServiceError::new(
+            400,
+            "invalid_password",
+            "No password on stdin",
+        ));
+    }
+    Ok((password.to_string(), false))
+}
+
+// The API's 404 message doesn't say what wasn't found
+fn no_user(id: i32) -> impl FnOnce(ServiceError) -> ServiceError {
+    move |e| match e.code {
+        "not_found" | "user_not_found" => {
+            ServiceError::new(404, "user_not_found", format!("No user with id {id}"))
+        }
+        _ => e,
+    }
+}
+
+fn print_user(user: &AuthenticatedUser) {
+    let suspended = match user.suspended_at {
+        Some(at) => format!("suspended {}", at.format("%Y-%m-%d")),
+        None => String::new(),
+    };
+
+    println!(
+        "{:>6}  {:<24} {:<32} {:<5} {}",
+        user.id,
+        user.name,
+        user.email,
+        format!("{:?}", user.role),
+        suspended
+    );
+}
+
+fn moderate(
+    conn: &mut PooledConn,
+    id: i32,
+    moderation: ModerateUser,
+) -> Result<AuthenticatedUser, ServiceError> {
+    moderate_user(conn, id, moderation).map_err(no_user(id))
+}
+
+/// Runs `review-api admin`, printing what it did.
+pub fn run(conn: &mut PooledConn, action: Admin, secret_key: &str) -> Result<(), ServiceError> {
+    match action {
+        Admin::CreateUser {
+            name,
+            email,
+            password_stdin,
+            role,
+        } => {
+            let (password, generated) = password(password_stdin)?;
+
+            let input = InputUser {
+                name,
+                email,
+                password: password.clone(),
+            };
+            input.validate()?;
+
+            let user = conn.transaction(|conn| {
+                let user = create_user(conn, input, secret_key)?;
+                moderate(
+                    conn,
+                    user.id,
+                    ModerateUser {
+                        role: Some(role.into()),
+                        suspended: None,
+                    },
+                )
+            })?;
+
+            print_user(&user);
+            if generated {
+                println!("Password: {password}");
+            }
+        }
+        Admin::SetRole { id, role } => {
+            let user = moderate(
+                conn,
+                id,
+                ModerateUser {
+                    role: Some(role.into()),
+                    suspended: None,
+                },
+            )?;
+            print_user(&user);
+        }
+        Admin::ResetPassword { id, password_stdin } => {
+            let (password, generated) = password(password_stdin)?;
+
+            if let Err(e) = password_strength(&password) {
+                let mut errors = ValidationErrors::new();
+                errors.add("password", e);
+                return Err(errors.into());
+            }
+
+            let user = reset_password(conn, id, &password, secret_key).map_err(no_user(id))?;
+
+            print_user(&user);
+            if generated {
+                println!("Password: {password}");
+            }
+        }
+        Admin::ListUsers { suspended } => {
+            for user in list_all_users(conn, suspended)? {
+                print_user(&user);
+            }
+        }
+        Admin::Suspend { id } => {
+            let user = moderate(
+                conn,
+                id,
+                ModerateUser {
+                    role: None,
+                    suspended: Some(true),
+                },
+            )?;
+            print_user(&user);
+        }
+        Admin::Unsuspend { id } => {
+            let user = moderate(
+                conn,
+                id,
+                ModerateUser {
+                    role: None,
+                    suspended: Some(false),
+                },
+            )?;
+            print_user(&user);
+        }
+        Admin::DeleteUser { id } => match delete_user_by_id(conn, id)? {
+            0 => {
+                return Err(ServiceError::new(
+                    404,
+                    "user_not_found",
+                    format!("No user with id {id}"),
+                ))
+            }
+            _ => println!("Deleted user {id}"),
+        },
+        Admin::PurgeReviews { id } => {
+            let deleted = delete_reviews_by_user(conn, id)?;
+            println!("Deleted {deleted} reviews by user {id}");
+        }
+        Admin::Stats => {
+            let users = count_users(conn)?;
+            println!("users          {}", users.users);
+            println!("admins         {}", users.admins);
+            println!("suspended      {}", users.suspended);
+            println!("reviews        {}", count_reviews(conn)?);
+            println!("open reports   {}", count_open_reports(conn)?);
+        }
+    }
+
+    Ok(())
+}
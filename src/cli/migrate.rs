@@ -0,0 +1,40 @@
+// `review-api migrate`, for running migrations without starting the server
+
+use diesel::PgConnection;
+
+use crate::{
+    cli::Migrate,
+    errors::DbError,
+    migrations::{down, status, up},
+};
+
+/// Runs `review-api migrate`, printing what it did.
+pub fn run(conn: &mut PgConnection, action: Migrate) -> Result<(), DbError> {
+    match action {
+        Migrate::Up => {
+            let applied = up(conn)?;
+            if applied.is_empty() {
+                println!("Nothing to apply");
+            }
+            for version in applied {
+                println!("Applied {version}");
+            }
+        }
+        Migrate::Down { steps } => {
+            let reverted = down(conn, steps)?;
+            if reverted.is_empty() {
+                println!("Nothing to revert");
+            }
+            for version in reverted {
+                println!("Reverted {version}");
+            }
+        }
+        Migrate::Status => {
+            for (name, state) in status(conn)? {
+                println!("{state:<8} {name}");
+            }
+        }
+    }
+
+    Ok(())
+}
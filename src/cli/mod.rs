@@ -0,0 +1,118 @@
+// Command line arguments, no subcommand serves the API
+
+use clap::{Parser, Subcommand, ValueEnum};
+
+use crate::{config::Needs, models::UserRole};
+
+pub mod admin;
+pub mod migrate;
+
+#[derive(Debug, Parser)]
+#[command(version, about)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Serve the API, the default
+    Serve,
+    /// Apply, revert or list database migrations
+    Migrate {
+        #[command(subcommand)]
+        action: Migrate,
+    },
+    /// Manage users without going through the API
+    Admin {
+        #[command(subcommand)]
+        action: Admin,
+    },
+}
+
+impl Command {
+    /// What has to be configured to run it.
+    pub fn needs(&self) -> Needs {
+        match self {
+            Command::Serve => Needs::Server,
+            Command::Admin {
+                action: Admin::CreateUser { .. } | Admin::ResetPassword { .. },
+            } => Needs::Passwords,
+            Command::Migrate { .. } | Command::Admin { .. } => Needs::Database,
+        }
+    }
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Migrate {
+    /// Apply every pending migration
+    Up,
+    /// Revert the latest migrations
+    Down {
+        /// How many to revert
+        #[arg(long, default_value_t = 1)]
+        steps: usize,
+    },
+    /// List every migration and whether it's been applied
+    Status,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Admin {
+    /// Create a user, printing a generated password unless one is piped in
+    CreateUser {
+        #[arg(long)]
+        name: String,
+        #[arg(long)]
+        email: String,
+        /// Read the password from the first line of stdin, so it stays out of shell history
+        #[arg(long)]
+        password_stdin: bool,
+        #[arg(long, value_enum, default_value_t = Role::User)]
+        role: Role,
+    },
+    /// Make a user an admin, or back to a normal user
+    SetRole {
+        id: i32,
+        #[arg(value_enum)]
+        role: Role,
+    },
+    /// Set a new password, printing a generated one unless one is piped in
+    ResetPassword {
+        id: i32,
+        /// Read the password from the first line of stdin, so it stays out of shell history
+        #[arg(long)]
+        password_stdin: bool,
+    },
+    /// List every user, including private and suspended ones
+    ListUsers {
+        /// Only suspended users
+        #[arg(long)]
+        suspended: bool,
+    },
+    /// Suspend a user, hiding their profile and reviews
+    Suspend { id: i32 },
+    /// Lift a user's suspension
+    Unsuspend { id: i32 },
+    /// Delete a user and everything of theirs
+    DeleteUser { id: i32 },
+    /// Delete all of a user's reviews
+    PurgeReviews { id: i32 },
+    /// Count users, reviews and open reports
+    Stats,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum Role {
+    User,
+    Admin,
+}
+
+impl From<Role> for UserRole {
+    fn from(role: Role) -> Self {
+        match role {
+            Role::User => UserRole::User,
+            Role::Admin => UserRole::Admin,
+        }
+    }
+}
@@ -0,0 +1,497 @@
+// Everything configurable, read once at startup from `config.toml` and the environment
+
+use std::{fmt, str::FromStr};
+
+use serde::Deserialize;
+
+use crate::{
+    cache::CacheConfig, errors::ErrorsConfig, metrics::MetricsConfig, migrations::OnStart,
+    pagination::PaginationConfig, telemetry::LogConfig, tmdb::TmdbConfig,
+};
+
+const DEFAULT_FILE: &str = "config.toml";
+
+/// Shows up as `[redacted]` when debug printed, so configs can be logged.
+#[derive(Clone, Default, Deserialize)]
+#[serde(transparent)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("[redacted]")
+    }
+}
+
+impl FromStr for Secret {
+    type Err = std::convert::Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Secret(s.to_string()))
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub host: String,
+    pub port: u16,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            host: "0.0.0.0".to_string(),
+            port: 8080,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    pub url: Secret,
+    pub pool_size: u32,
+    /// What to do about pending migrations at startup
+    pub migrations: OnStart,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        DatabaseConfig {
+            url: Secret::default(),
+            pool_size: 10,
+            migrations: OnStart::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RedisConfig {
+    pub url: Secret,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    /// Peppers password hashes, changing it invalidates every password
+    pub secret_key: Secret,
+    /// How long until a login expires
+    pub login_deadline_secs: u64,
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        AuthConfig {
+            secret_key: Secret::default(),
+            login_deadline_secs: 24 * 60 * 60,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RecommendationsConfig {
+    /// 0 turns the batch off
+    pub interval_hours: u64,
+}
+
+impl Default for RecommendationsConfig {
+    fn default() -> Self {
+        RecommendationsConfig { interval_hours: 24 }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HealthConfig {
+    /// Also report whether TMDB is reachable, it doesn't affect readiness
+    pub check_tmdb: bool,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub log: LogConfig,
+    pub server: ServerConfig,
+    pub database: DatabaseConfig,
+    pub redis: RedisConfig,
+    pub auth: AuthConfig,
+    pub pagination: PaginationConfig,
+    pub tmdb: TmdbConfig,
+    pub cache: CacheConfig,
+    pub recommendations: RecommendationsConfig,
+    pub metrics: MetricsConfig,
+    pub health: HealthConfig,
+    pub errors: ErrorsConfig,
+}
+
+/// What the command being run needs configured, everything else can be left out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Needs {
+    /// Serving the API needs all of it
+    Server,
+    /// Only the database, e.g. for `migrate`
+    Database,
+    /// The database and the secret key passwords are hashed with
+    Passwords,
+}
+
+/// Every problem with the config, not just the first.
+#[derive(Debug)]
+pub struct ConfigError(Vec<String>);
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid config:")?;
+        for error in &self.0 {
+            write!(f, "\n  - {error}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+fn env<T>(errors: &mut Vec<String>, name: &str, value: &mut T)
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    let Ok(raw) = std::env::var(name) else {
+        return;
+    };
+
+    match raw.parse() {
+        Ok(parsed) => *value = parsed,
+        Err(e) => errors.push(format!("{name} is invalid: {e}")),
+    }
+}
+
+fn env_opt<T>(errors: &mut Vec<String>, name: &str, value: &mut Option<T>)
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    let Ok(raw) = std::env::var(name) else {
+        return;
+    };
+
+    match raw.parse() {
+        Ok(parsed) => *value = Some(parsed),
+        Err(e) => errors.push(format!("{name} is invalid: {e}")),
+    }
+}
+
+impl Config {
+    /// Reads `CONFIG_FILE`, or `config.toml` if there is one, then lets env vars override it.
+    /// Only what `needs` covers has to be set.
+    pub fn load(needs: Needs) -> Result<Self, ConfigError> {
+        let mut errors = Vec::new();
+
+        let (path, required) = match std::env::var("CONFIG_FILE") {
+            Ok(path) => (path, true),
+            Err(_) => (DEFAULT_FILE.to_string(), false),
+        };
+
+        let mut config = match std::fs::read_to_string(&path) {
+            Ok(contents) => toml::from_str(&contents).unwrap_or_else(|e| {
+                errors.push(format!("{path} is invalid: {e}"));
+                Config::default()
+            }),
+            Err(e) if required => {
+                errors.push(format!("Couldn't read {path}: {e}"));
+                Config::default()
+            }
+            Err(_) => Config::default(),
+        };
+
+        config.override_from_env(&mut errors);
+        config.validate(needs, &mut errors);
+
+        if errors.is_empty() {
+            Ok(config)
+        } else {
+            Err(ConfigError(errors))
+        }
+    }
+
+    fn override_from_env(&mut self, errors: &mut Vec<String>) {
+        env(errors, "LOG_FORMAT", &mut self.log.format);
+        env(errors, "RUST_LOG", &mut self.log.filter);
+        env(errors, "HOST", &mut self.server.host);
+        env(errors, "PORT", &mut self.server.port);
+        env(errors, "DATABASE_URL", &mut self.database.url);
+        env(errors, "DATABASE_POOL_SIZE", &mut self.database.pool_size);
+        env(errors, "DATABASE_MIGRATIONS", &mut self.database.migrations);
+        env(errors, "REDIS_URL", &mut self.redis.url);
+        env(errors, "SECRET_KEY", &mut self.auth.secret_key);
+        env(
+            errors,
+            "LOGIN_DEADLINE_SECS",
+            &mut self.auth.login_deadline_secs,
+        );
+        env(
+            errors,
+            "DEFAULT_PER_PAGE",
+            &mut self.pagination.default_per_page,
+        );
+        env(errors, "MAX_PER_PAGE", &mut self.pagination.max_per_page);
+        env(errors, "TMDB_BASE_URL", &mut self.tmdb.base_url);
+        env_opt(errors, "TMDB_API_KEY", &mut self.tmdb.api_key);
+        env(
+            errors,
+            "TMDB_CONNECT_TIMEOUT_SECS",
+            &mut self.tmdb.connect_timeout_secs,
+        );
+        env(errors, "TMDB_TIMEOUT_SECS", &mut self.tmdb.timeout_secs);
+        env(errors, "TMDB_MAX_RETRIES", &mut self.tmdb.max_retries);
+        env(
+            errors,
+            "TMDB_RETRY_BACKOFF_MS",
+            &mut self.tmdb.retry_backoff_ms,
+        );
+        env(errors, "TMDB_RATE_LIMIT", &mut self.tmdb.rate_limit);
+        env(errors, "TMDB_VALIDATION", &mut self.tmdb.validation);
+        env(errors, "CACHE_BACKEND", &mut self.cache.backend);
+        env(errors, "CACHE_CAPACITY", &mut self.cache.capacity);
+        env(
+            errors,
+            "RECOMMENDATIONS_INTERVAL_HOURS",
+            &mut self.recommendations.interval_hours,
+        );
+        env_opt(errors, "METRICS_PORT", &mut self.metrics.port);
+        env_opt(errors, "METRICS_TOKEN", &mut self.metrics.token);
+        env(errors, "HEALTH_CHECK_TMDB", &mut self.health.check_tmdb);
+        env(errors, "PLAYFUL_ERRORS", &mut self.errors.playful);
+    }
+
+    fn validate(&self, needs: Needs, errors: &mut Vec<String>) {
+        let mut check = |ok: bool, error: &str| {
+            if !ok {
+                errors.push(error.to_string());
+            }
+        };
+
+        check(
+            tracing_subscriber::EnvFilter::try_new(&self.log.filter).is_ok(),
+            "log.filter (RUST_LOG) isn't a valid filter",
+        );
+        check(
+            !self.database.url.expose().is_empty(),
+            "database.url (DATABASE_URL) is required",
+        );
+        check(
+            self.database.pool_size > 0,
+            "database.pool_size (DATABASE_POOL_SIZE) must be at least 1",
+        );
+
+        if needs != Needs::Database {
+            check(
+                !self.auth.secret_key.expose().is_empty(),
+                "auth.secret_key (SECRET_KEY) is required",
+            );
+        }
+        if needs != Needs::Server {
+            return;
+        }
+
+        check(
+            !self.redis.url.expose().is_empty(),
+            "redis.url (REDIS_URL) is required",
+        );
+        check(
+            self.auth.login_deadline_secs > 0,
+            "auth.login_deadline_secs (LOGIN_DEADLINE_SECS) must be at least 1",
+        );
+        check(
+            self.pagination.max_per_page > 0,
+            "pagination.max_per_page (MAX_PER_PAGE) must be at least 1",
+        );
+        check(
+            (1..=self.pagination.max_per_page).contains(&self.pagination.default_per_page),
+            "pagination.default_per_page (DEFAULT_PER_PAGE) must be between 1 and max_per_page",
+        );
+        check(
+            self.tmdb.base_url.starts_with("http://") || self.tmdb.base_url.starts_with("https://"),
+            "tmdb.base_url (TMDB_BASE_URL) must be an http or https URL",
+        );
+        check(
+            self.tmdb.timeout_secs > 0,
+            "tmdb.timeout_secs (TMDB_TIMEOUT_SECS) must be at least 1",
+        );
+        check(
+            self.tmdb.connect_timeout_secs > 0,
+            "tmdb.connect_timeout_secs (TMDB_CONNECT_TIMEOUT_SECS) must be at least 1",
+        );
+        check(
+            self.metrics.port != Some(self.server.port),
+            "metrics.port (METRICS_PORT) must be different to server.port",
+        );
+        check(
+            self.cache.capacity > 0,
+            "cache.capacity (CACHE_CAPACITY) must be at least 1",
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::{Config, ConfigError, Needs};
+    use crate::{media::Validation, migrations::OnStart};
+
+    // Env vars are shared by every test, so only one of these sets them at a time
+    static ENV: Mutex<()> = Mutex::new(());
+
+    /// Overrides `config` from `vars`, returning the errors.
+    fn override_with(config: &mut Config, vars: &[(&str, &str)]) -> Vec<String> {
+        let _env = ENV.lock().unwrap_or_else(|e| e.into_inner());
+        for (name, value) in vars {
+            std::env::set_var(name, value);
+        }
+
+        let mut errors = Vec::new();
+        config.override_from_env(&mut errors);
+
+        for (name, _) in vars {
+            std::env::remove_var(name);
+        }
+        errors
+    }
+
+    fn validated(config: &Config, needs: Needs) -> Vec<String> {
+        let mut errors = Vec::new();
+        config.validate(needs, &mut errors);
+        errors
+    }
+
+    #[test]
+    fn env_vars_override_the_file() {
+        let mut config: Config = toml::from_str(
+            r#"
+            [server]
+            port = 1
+            [tmdb]
+            validation = "off"
+            "#,
+        )
+        .unwrap();
+
+        let errors = override_with(
+            &mut config,
+            &[
+                ("PORT", "2"),
+                ("TMDB_API_KEY", "key"),
+                ("DATABASE_MIGRATIONS", "apply"),
+            ],
+        );
+
+        assert!(errors.is_empty(), "{errors:?}");
+        assert_eq!(config.server.port, 2);
+        assert_eq!(config.tmdb.api_key.unwrap().expose(), "key");
+        assert_eq!(config.database.migrations, OnStart::Apply);
+        // Left alone without an env var
+        assert_eq!(config.tmdb.validation, Validation::Off);
+    }
+
+    #[test]
+    fn every_invalid_env_var_is_reported() {
+        let mut config = Config::default();
+
+        let errors = override_with(
+            &mut config,
+            &[
+                ("PORT", "eighty"),
+                ("DATABASE_POOL_SIZE", "-1"),
+                ("TMDB_VALIDATION", "sometimes"),
+            ],
+        );
+
+        assert_eq!(errors.len(), 3, "{errors:?}");
+        assert!(errors[0].starts_with("PORT is invalid"));
+        assert!(errors[1].starts_with("DATABASE_POOL_SIZE is invalid"));
+        assert_eq!(
+            errors[2],
+            "TMDB_VALIDATION is invalid: must be strict, lenient or off, not sometimes"
+        );
+        assert_eq!(config.server.port, 8080);
+    }
+
+    #[test]
+    fn only_what_the_command_needs_is_required() {
+        let config = Config::default();
+
+        assert_eq!(
+            validated(&config, Needs::Database),
+            vec!["database.url (DATABASE_URL) is required"]
+        );
+        assert_eq!(
+            validated(&config, Needs::Passwords),
+            vec![
+                "database.url (DATABASE_URL) is required",
+                "auth.secret_key (SECRET_KEY) is required",
+            ]
+        );
+        assert_eq!(
+            validated(&config, Needs::Server),
+            vec![
+                "database.url (DATABASE_URL) is required",
+                "auth.secret_key (SECRET_KEY) is required",
+                "redis.url (REDIS_URL) is required",
+            ]
+        );
+    }
+
+    #[test]
+    fn every_invalid_setting_is_reported() {
+        let mut config: Config = toml::from_str(
+            r#"
+            [server]
+            port = 9000
+            [database]
+            url = "postgres://localhost/db"
+            pool_size = 0
+            [redis]
+            url = "redis://localhost"
+            [auth]
+            secret_key = "secret"
+            [pagination]
+            default_per_page = 50
+            max_per_page = 10
+            [tmdb]
+            base_url = "api.themoviedb.org"
+            [metrics]
+            port = 9000
+            "#,
+        )
+        .unwrap();
+        config.log.filter = "=".to_string();
+
+        let errors = validated(&config, Needs::Server);
+        let error = ConfigError(errors).to_string();
+
+        assert_eq!(
+            error,
+            "Invalid config:
+  - log.filter (RUST_LOG) isn't a valid filter
+  - database.pool_size (DATABASE_POOL_SIZE) must be at least 1
+  - pagination.default_per_page (DEFAULT_PER_PAGE) must be between 1 and max_per_page
+  - tmdb.base_url (TMDB_BASE_URL) must be an http or https URL
+  - metrics.port (METRICS_PORT) must be different to server.port"
+        );
+    }
+
+    #[test]
+    fn unknown_settings_are_rejected() {
+        let e = toml::from_str::<Config>("[server]\nprot = 1").unwrap_err();
+        assert!(e.to_string().contains("unknown field `prot`"), "{e}");
+    }
+}
@@ -1 +1,13 @@
 // pub const CONNECTION_POOL_ERROR: &str = "couldn't get DB connection from pool";
+
+// Exports of long watch histories can be a few MB
+pub const MAX_IMPORT_BYTES: usize = 10 * 1024 * 1024;
+
+// Enough for the longest review text
+pub const MAX_JSON_BYTES: usize = 64 * 1024;
+pub const MAX_QUERY_BYTES: usize = 2 * 1024;
+
+// The API at the root is v1 without the prefix, deprecated since 2026-10-19 and kept until the
+// sunset so older clients have time to move
+pub const LEGACY_DEPRECATION: &str = "@1792368000";
+pub const LEGACY_SUNSET: &str = "Mon, 19 Apr 2027 00:00:00 GMT";
@@ -1,43 +1,154 @@
-use std::fmt::Display;
+use std::{borrow::Cow, fmt::Display, sync::OnceLock};
 
-use actix_web::{error::BlockingError, http::StatusCode, HttpResponse, ResponseError};
+use actix_web::{
+    error::BlockingError,
+    http::{header::RETRY_AFTER, StatusCode},
+    HttpResponse, ResponseError,
+};
 use awc::error::{JsonPayloadError, SendRequestError};
-use serde_json::json;
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::telemetry::request_id;
 
 pub type DbError = Box<dyn std::error::Error + Send + Sync>;
 
+const PROBLEM_JSON: &str = "application/problem+json";
+const INTERNAL_ERROR: &str = "internal_error";
+
+/// The `[errors]` config section.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ErrorsConfig {
+    /// Prefixes `detail` with a joke, like before error codes
+    pub playful: bool,
+}
+
+static CONFIG: OnceLock<ErrorsConfig> = OnceLock::new();
+
+/// Sets how errors are shown to clients, once at startup.
+pub fn set_config(config: ErrorsConfig) {
+    CONFIG.set(config).ok();
+}
+
+/// What was wrong with one field of a request.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct FieldError {
+    pub field: String,
+    #[schema(value_type = String)]
+    pub code: Cow<'static, str>,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new<C, T>(field: &str, code: C, message: T) -> Self
+    where
+        C: Into<Cow<'static, str>>,
+        T: Into<String>,
+    {
+        FieldError {
+            field: field.to_string(),
+            code: code.into(),
+            message: message.into(),
+        }
+    }
+}
+
+/// The body of every error response.
+#[derive(Serialize, ToSchema)]
+pub struct Problem<'a> {
+    /// `urn:review-api:problem:` and the code
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// The status' reason phrase
+    pub title: &'a str,
+    pub status: u16,
+    pub detail: String,
+    pub code: &'a str,
+    /// Every field that's wrong, for `validation_failed`
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    pub errors: &'a [FieldError],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+/// Responds as an RFC 7807 problem, where `code` is stable for clients to match on and
+/// `message` is for people.
 #[derive(Debug)]
 pub struct ServiceError {
     pub status: u16,
+    pub code: &'static str,
     pub message: String,
+    /// Seconds, sent as a `Retry-After` header
+    pub retry_after: Option<u64>,
+    pub fields: Vec<FieldError>,
 }
 
 impl ServiceError {
-    pub fn new<T: Into<String>>(status: u16, message: T) -> Self {
+    pub fn new<T: Into<String>>(status: u16, code: &'static str, message: T) -> Self {
         ServiceError {
             status,
+            code,
             message: message.into(),
+            retry_after: None,
+            fields: Vec::new(),
         }
     }
-    pub fn pls(status: u16) -> Self {
+    /// A 500 whose message is logged but not shown to the client.
+    pub fn internal<T: Display>(error: T) -> Self {
+        ServiceError::new(500, INTERNAL_ERROR, error.to_string())
+    }
+    /// A 422 listing every field that's wrong.
+    pub fn validation(fields: Vec<FieldError>) -> Self {
         ServiceError {
-            status,
-            message: "L + Ratio".into(),
+            fields,
+            ..ServiceError::new(422, "validation_failed", "Some fields are invalid")
+        }
+    }
+    pub fn with_retry_after(mut self, seconds: u64) -> Self {
+        self.retry_after = Some(seconds);
+        self
+    }
+    /// Says what wasn't found, for errors from queries that only know a row was missing.
+    pub fn not_found_as<T: Into<String>>(self, code: &'static str, message: T) -> Self {
+        match self.code {
+            "not_found" => ServiceError::new(404, code, message),
+            _ => self,
+        }
+    }
+
+    fn detail(&self) -> String {
+        if self.code == INTERNAL_ERROR {
+            return "Something went wrong on our end".to_string();
+        }
+
+        let playful = CONFIG.get().is_some_and(|config| config.playful);
+        if !playful {
+            return self.message.clone();
+        }
+
+        match self.status {
+            400 | 422 => format!("it's not me, it's you: {}", self.message),
+            401 | 403 => format!("✋👮 stop right there: {}", self.message),
+            404 => format!("found it. jk: {}", self.message),
+            _ => format!("👉👈: {}", self.message),
         }
     }
 }
 
 impl Display for ServiceError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self.status {
-            400 => write!(f, "it's not me, it's you: {}", self.message),
-            401 => write!(f, "✋👮 stop right there: {}", self.message),
-            404 => write!(f, "found it. jk: {}", self.message),
-            _ => write!(f, "👉👈: {}", self.message),
+        write!(f, "{}", self.message)?;
+        for field in &self.fields {
+            write!(f, "\n  - {}: {}", field.field, field.message)?;
         }
+        Ok(())
     }
 }
 
+impl std::error::Error for ServiceError {}
+
 impl ResponseError for ServiceError {
     fn status_code(&self) -> actix_web::http::StatusCode {
         match StatusCode::from_u16(self.status) {
@@ -47,60 +158,98 @@ impl ResponseError for ServiceError {
     }
 
     fn error_response(&self) -> HttpResponse {
-        HttpResponse::build(self.status_code()).json(json!({ "message": self.to_string() }))
+        let status = self.status_code();
+        let mut res = HttpResponse::build(status);
+        res.content_type(PROBLEM_JSON);
+
+        if let Some(retry_after) = self.retry_after {
+            res.insert_header((RETRY_AFTER, retry_after));
+        }
+
+        if self.code == INTERNAL_ERROR {
+            tracing::error!("{}", self.message);
+        }
+
+        res.json(Problem {
+            kind: format!("urn:review-api:problem:{}", self.code),
+            title: status.canonical_reason().unwrap_or("Error"),
+            status: status.as_u16(),
+            detail: self.detail(),
+            code: self.code,
+            errors: &self.fields,
+            // So users can quote it when reporting a problem
+            request_id: request_id(),
+        })
     }
 }
 
 impl From<BlockingError> for ServiceError {
     fn from(e: BlockingError) -> Self {
-        ServiceError::new(500, e.to_string())
+        ServiceError::internal(e)
     }
 }
 
 impl From<actix_web::Error> for ServiceError {
     fn from(e: actix_web::Error) -> Self {
-        ServiceError::new(500, e.to_string())
+        ServiceError::internal(e)
     }
 }
 
 impl From<r2d2::Error> for ServiceError {
     fn from(e: r2d2::Error) -> Self {
-        ServiceError::new(500, e.to_string())
+        ServiceError::internal(e)
     }
 }
 
 impl From<argon2::Error> for ServiceError {
     fn from(e: argon2::Error) -> Self {
-        ServiceError::new(500, e.to_string())
+        ServiceError::internal(e)
     }
 }
 
 impl From<SendRequestError> for ServiceError {
     fn from(e: SendRequestError) -> Self {
-        ServiceError::new(500, e.to_string())
+        ServiceError::internal(e)
     }
 }
 impl From<JsonPayloadError> for ServiceError {
     fn from(e: JsonPayloadError) -> Self {
-        ServiceError::new(500, e.to_string())
+        ServiceError::internal(e)
     }
 }
 
 impl From<DbError> for ServiceError {
     fn from(e: DbError) -> Self {
-        ServiceError::new(500, e.to_string())
+        // Actions box these, but not found and conflicts are still the client's problem
+        let e = match e.downcast::<DieselError>() {
+            Ok(e) => return (*e).into(),
+            Err(e) => e,
+        };
+        match e.downcast::<ServiceError>() {
+            Ok(e) => *e,
+            Err(e) => ServiceError::internal(e),
+        }
     }
 }
 
-impl From<diesel::result::Error> for ServiceError {
-    fn from(error: diesel::result::Error) -> Self {
+impl From<DieselError> for ServiceError {
+    fn from(error: DieselError) -> Self {
         match error {
-            diesel::result::Error::NotFound => ServiceError::pls(404),
-            diesel::result::Error::DatabaseError(
-                diesel::result::DatabaseErrorKind::UniqueViolation,
-                _,
-            ) => ServiceError::new(400, error.to_string()),
-            err => ServiceError::new(500, format!("other db error: {}", err)),
+            DieselError::NotFound => ServiceError::new(404, "not_found", "Not found"),
+            DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, info) => {
+                match info.constraint_name() {
+                    Some("users_email_key") => {
+                        ServiceError::new(409, "email_taken", "That email is already in use")
+                    }
+                    Some("reviews_pkey") => ServiceError::new(
+                        409,
+                        "review_exists",
+                        "There's already a review for that title",
+                    ),
+                    _ => ServiceError::new(409, "conflict", "That already exists"),
+                }
+            }
+            err => ServiceError::internal(format!("other db error: {}", err)),
         }
     }
 }
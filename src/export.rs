@@ -0,0 +1,391 @@
+// Streams all of a user's reviews in a few formats, one batch at a time
+
+use actix_web::web::Bytes;
+use futures_util::{stream, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use utoipa::ToSchema;
+
+use crate::{
+    actions::reviews::{get_reviews_after, ReviewKey},
+    errors::ServiceError,
+    models::{MediaCategory, Review, ReviewVisibility, User, WatchStatus},
+    utils::block,
+    Pool,
+};
+
+// Reviews loaded from the db at a time
+const EXPORT_BATCH_SIZE: i64 = 500;
+
+// Bump if the json format changes in a way imports need to handle
+const EXPORT_VERSION: i32 = 1;
+
+#[derive(Deserialize, Debug, Clone, Copy, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    Json,
+    Csv,
+    Letterboxd,
+}
+
+impl ExportFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Json => "application/json",
+            ExportFormat::Csv | ExportFormat::Letterboxd => "text/csv",
+        }
+    }
+
+    pub fn file_name(&self) -> &'static str {
+        match self {
+            ExportFormat::Json => "reviews.json",
+            ExportFormat::Csv => "reviews.csv",
+            ExportFormat::Letterboxd => "letterboxd.csv",
+        }
+    }
+
+    fn header(&self, user: &User) -> Result<Bytes, ServiceError> {
+        let header = match self {
+            ExportFormat::Json => {
+                // Everything up to the opening bracket of the reviews array
+                let mut header = json!({
+                    "version": EXPORT_VERSION,
+                    "user": user,
+                })
+                .to_string();
+                header.pop();
+                header + ",\"reviews\":["
+            }
+            ExportFormat::Csv => {
+                "tmdb_id,category,season,status,text,fun_before,fun_during,fun_after,visibility,created_at,updated_at\n".into()
+            }
+            ExportFormat::Letterboxd => "tmdbID,WatchedDate,Review\n".into(),
+        };
+
+        Ok(Bytes::from(header))
+    }
+
+    fn footer(&self) -> Bytes {
+        match self {
+            ExportFormat::Json => Bytes::from_static(b"]}"),
+            ExportFormat::Csv | ExportFormat::Letterboxd => Bytes::new(),
+        }
+    }
+
+    fn batch(&self, reviews: &[Review], first: bool) -> Result<Bytes, ServiceError> {
+        match self {
+            ExportFormat::Json => {
+                let mut out = Vec::new();
+                for (i, review) in reviews.iter().enumerate() {
+                    if !first || i > 0 {
+                        out.push(b',');
+                    }
+                    serde_json::to_writer(&mut out, review).map_err(ServiceError::internal)?;
+                }
+                Ok(Bytes::from(out))
+            }
+            ExportFormat::Csv => {
+                let rows = reviews.iter().map(CsvRow::from);
+                write_csv(rows)
+            }
+            ExportFormat::Letterboxd => {
+                // Letterboxd only knows about films, and treats every row as watched
+                let rows = reviews
+                    .iter()
+                    .filter(|review| {
+                        review.category == MediaCategory::Film
+                            && review.status == WatchStatus::Completed
+                    })
+                    .map(LetterboxdRow::from);
+                write_csv(rows)
+            }
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct CsvRow<'a> {
+    tmdb_id: i32,
+    category: MediaCategory,
+    season: Option<i32>,
+    status: WatchStatus,
+    text: &'a str,
+    fun_before: bool,
+    fun_during: bool,
+    fun_after: bool,
+    visibility: ReviewVisibility,
+    created_at: String,
+    updated_at: String,
+}
+
+impl<'a> From<&'a Review> for CsvRow<'a> {
+    fn from(review: &'a Review) -> Self {
+        CsvRow {
+            tmdb_id: review.tmdb_id,
+            category: review.category,
+            season: (review.season >= 0).then_some(review.season),
+            status: review.status,
+            text: &review.text,
+            fun_before: review.fun_before,
+            fun_during: review.fun_during,
+            fun_after: review.fun_after,
+            visibility: review.visibility,
+            created_at: review.created_at.to_rfc3339(),
+            updated_at: review.updated_at.to_rfc3339(),
+        }
+    }
+}
+
+// https://letterboxd.com/about/importing-data/
+#[derive(Serialize)]
+struct LetterboxdRow<'a> {
+    #[serde(rename = "tmdbID")]
+    tmdb_id: i32,
+    #[serde(rename = "WatchedDate")]
+    watched_date: String,
+    #[serde(rename = "Review")]
+    review: &'a str,
+}
+
+impl<'a> From<&'a Review> for LetterboxdRow<'a> {
+    fn from(review: &'a Review) -> Self {
+        LetterboxdRow {
+            tmdb_id: review.tmdb_id,
+            watched_date: review.updated_at.format("%Y-%m-%d").to_string(),
+            review: &review.text,
+        }
+    }
+}
+
+fn write_csv<T: Serialize>(rows: impl Iterator<Item = T>) -> Result<Bytes, ServiceError> {
+    let mut writer = csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_writer(Vec::new());
+
+    for row in rows {
+        writer.serialize(row).map_err(ServiceError::internal)?;
+    }
+
+    let out = writer.into_inner().map_err(ServiceError::internal)?;
+
+    Ok(Bytes::from(out))
+}
+
+struct Cursor {
+    after: Option<ReviewKey>,
+    first: bool,
+}
+
+/// Streams every review by `user` without holding more than one batch in memory.
+pub fn export_reviews(
+    pool: Pool,
+    user: User,
+    format: ExportFormat,
+) -> Result<impl Stream<Item = Result<Bytes, ServiceError>>, ServiceError> {
+    let header = format.header(&user)?;
+    let footer = format.footer();
+    let user_id = user.id;
+
+    let start = Some(Cursor {
+        after: None,
+        first: true,
+    });
+
+    let batches = stream::unfold(start, move |cursor| {
+        let pool = pool.clone();
+        async move {
+            let cursor = cursor?;
+            let after = cursor.after;
+
+            let batch = block(move || {
+                let mut conn = pool.get()?;
+                get_reviews_after(&mut conn, user_id, after, EXPORT_BATCH_SIZE)
+            })
+            .await;
+
+            let batch = match batch {
+                Ok(Ok(batch)) => batch,
+                Ok(Err(e)) => return Some((Err(e.into()), None)),
+                Err(e) => return Some((Err(e.into()), None)),
+            };
+
+            // An empty batch means everything's been sent
+            let last = batch.last()?;
+
+            let next = (batch.len() as i64 == EXPORT_BATCH_SIZE).then_some(Cursor {
+                after: Some((last.tmdb_id, last.category, last.season)),
+                first: false,
+            });
+
+            Some((format.batch(&batch, cursor.first), next))
+        }
+    });
+
+    Ok(stream::once(async { Ok(header) })
+        .chain(batches)
+        .chain(stream::once(async { Ok(footer) })))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, Utc};
+
+    use super::ExportFormat;
+    use crate::{
+        import,
+        models::{
+            ImportSource, MediaCategory, Review, ReviewVisibility, User, UserRole, WatchStatus,
+        },
+    };
+
+    fn at(time: &str) -> DateTime<Utc> {
+        time.parse().unwrap()
+    }
+
+    fn user() -> User {
+        User {
+            id: 1,
+            name: "someone".to_string(),
+            email: "someone@example.com".to_string(),
+            hash: "hash".to_string(),
+            created_at: at("2022-01-01T00:00:00Z"),
+            updated_at: at("2022-01-01T00:00:00Z"),
+            private: false,
+            role: UserRole::User,
+            suspended_at: None,
+        }
+    }
+
+    // Films have no season, stored as -1
+    fn review(tmdb_id: i32, category: MediaCategory, season: i32, status: WatchStatus) -> Review {
+        Review {
+            user_id: 1,
+            tmdb_id,
+            category,
+            status,
+            text: format!("Review of {tmdb_id}, with \"quotes\", commas\nand lines"),
+            fun_before: tmdb_id % 2 == 0,
+            fun_during: true,
+            fun_after: false,
+            created_at: at("2022-01-02T03:04:05Z"),
+            updated_at: at("2022-02-03T04:05:06Z"),
+            season,
+            visibility: ReviewVisibility::Followers,
+            hidden: false,
+        }
+    }
+
+    // Everything export_reviews would stream, with the reviews split into batches
+    fn export(format: ExportFormat, batches: &[&[Review]]) -> Vec<u8> {
+        let mut out = format.header(&user()).unwrap().to_vec();
+        for (i, batch) in batches.iter().enumerate() {
+            out.extend_from_slice(&format.batch(batch, i == 0).unwrap());
+        }
+        out.extend_from_slice(&format.footer());
+        out
+    }
+
+    #[test]
+    fn json_exports_import_back_the_same() {
+        let reviews = [
+            review(949, MediaCategory::Film, -1, WatchStatus::Completed),
+            review(70523, MediaCategory::Show, 0, WatchStatus::Watching),
+            review(70523, MediaCategory::Show, 2, WatchStatus::Dropped),
+        ];
+
+        let body = export(ExportFormat::Json, &[&reviews[..2], &reviews[2..]]);
+        let entries = import::parse(ImportSource::ReviewApi, &body, None).unwrap();
+
+        assert_eq!(entries.len(), reviews.len());
+        for (entry, review) in entries.iter().zip(&reviews) {
+            assert_eq!(entry.tmdb_id, Some(review.tmdb_id));
+            assert_eq!(entry.category, review.category);
+            assert_eq!(entry.season, (review.season >= 0).then_some(review.season));
+            assert_eq!(entry.status, review.status);
+            assert_eq!(entry.text, review.text);
+            assert_eq!(
+                (entry.fun_before, entry.fun_during, entry.fun_after),
+                (review.fun_before, review.fun_during, review.fun_after)
+            );
+            assert_eq!(entry.visibility, review.visibility);
+        }
+    }
+
+    #[test]
+    fn json_batches_frame_one_document() {
+        let reviews = [
+            review(1, MediaCategory::Film, -1, WatchStatus::Completed),
+            review(2, MediaCategory::Film, -1, WatchStatus::Completed),
+            review(3, MediaCategory::Film, -1, WatchStatus::Completed),
+        ];
+
+        for batches in [
+            vec![],
+            vec![&reviews[..]],
+            vec![&reviews[..1], &reviews[1..]],
+            vec![&reviews[..1], &reviews[1..2], &reviews[2..]],
+        ] {
+            let body = export(ExportFormat::Json, &batches);
+            let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            let ids: Vec<_> = json["reviews"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|review| review["tmdb_id"].as_i64().unwrap())
+                .collect();
+
+            assert_eq!(json["version"], 1);
+            assert_eq!(json["user"]["name"], "someone");
+            assert!(json["user"].get("email").is_none());
+            assert_eq!(ids.len(), batches.iter().map(|b| b.len()).sum::<usize>());
+            assert!(ids.iter().zip(1..).all(|(id, n)| *id == n));
+        }
+    }
+
+    #[test]
+    fn csv_leaves_film_seasons_empty() {
+        let reviews = [
+            review(949, MediaCategory::Film, -1, WatchStatus::Completed),
+            review(70523, MediaCategory::Show, 0, WatchStatus::Watching),
+        ];
+
+        let body = export(ExportFormat::Csv, &[&reviews]);
+        let mut reader = csv::Reader::from_reader(body.as_slice());
+        let headers = reader.headers().unwrap().clone();
+        let rows: Vec<csv::StringRecord> = reader.records().map(Result::unwrap).collect();
+        let field = |row: &csv::StringRecord, name: &str| {
+            let i = headers.iter().position(|header| header == name).unwrap();
+            row[i].to_string()
+        };
+
+        assert_eq!(rows.len(), 2);
+        assert_eq!(field(&rows[0], "season"), "");
+        assert_eq!(field(&rows[1], "season"), "0");
+        assert_eq!(field(&rows[1], "category"), "Show");
+        assert_eq!(field(&rows[1], "status"), "Watching");
+        assert_eq!(field(&rows[0], "text"), reviews[0].text);
+        assert_eq!(field(&rows[0], "created_at"), "2022-01-02T03:04:05+00:00");
+    }
+
+    #[test]
+    fn letterboxd_only_gets_completed_films() {
+        let reviews = [
+            review(1, MediaCategory::Film, -1, WatchStatus::Completed),
+            review(2, MediaCategory::Film, -1, WatchStatus::PlanToWatch),
+            review(3, MediaCategory::Show, 1, WatchStatus::Completed),
+        ];
+
+        let body = export(ExportFormat::Letterboxd, &[&reviews]);
+        let mut reader = csv::Reader::from_reader(body.as_slice());
+        let rows: Vec<csv::StringRecord> = reader.records().map(Result::unwrap).collect();
+
+        assert_eq!(
+            reader.headers().unwrap(),
+            vec!["tmdbID", "WatchedDate", "Review"]
+        );
+        assert_eq!(rows.len(), 1);
+        assert_eq!(&rows[0][0], "1");
+        assert_eq!(&rows[0][1], "2022-02-03");
+        assert_eq!(&rows[0][2], reviews[0].text);
+    }
+}
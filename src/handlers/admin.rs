@@ -0,0 +1,251 @@
+use actix_web::{web, HttpResponse};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+use validator::Validate;
+
+use crate::{
+    actions::{
+        reports::{get_all_reports, resolve_report, ReportsQuery, ResolveReport},
+        reviews::set_review_hidden,
+        users::{moderate_user, ModerateUser},
+    },
+    cache::ResponseCache,
+    errors::ServiceError,
+    handlers::auth::Admin,
+    models::MediaCategory,
+    utils::block,
+    validation::{ValidJson, ValidQuery},
+    Pool,
+};
+
+/// The report queue, oldest first.
+#[utoipa::path(
+    get,
+    path = "/admin/reports",
+    tag = "admin",
+    params(ReportsQuery),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "A page of reports", body = PaginatedReports),
+        (status = 403, description = "`forbidden`", body = Problem),
+        (status = 422, description = "`validation_failed`", body = Problem),
+        (status = 422, description = "`validation_failed`", body = Problem),
+        (status = 422, description = "`validation_failed`", body = Problem),
+    ),
+)]
+pub async fn get_reports(
+    pool: web::Data<Pool>,
+    _admin: Admin,
+    query: ValidQuery<ReportsQuery>,
+) -> Result<HttpResponse, ServiceError> {
+    let reports = block(move || {
+        let mut conn = pool.get()?;
+        get_all_reports(&mut conn, query.into_inner())
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(reports))
+}
+
+/// Resolves or dismisses a report, optionally acting on it.
+#[utoipa::path(
+    patch,
+    path = "/admin/reports/{id}",
+    tag = "admin",
+    params(("id" = i32, Path, description = "Report id")),
+    request_body = ResolveReport,
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "The report", body = Report),
+        (status = 400, description = "`not_a_review_report`, for hiding a report about a user", body = Problem),
+        (status = 403, description = "`forbidden`", body = Problem),
+        (status = 404, description = "`report_not_found`", body = Problem),
+    ),
+)]
+pub async fn patch_reports_id(
+    pool: web::Data<Pool>,
+    admin: Admin,
+    path_id: web::Path<i32>,
+    resolution: ValidJson<ResolveReport>,
+) -> Result<HttpResponse, ServiceError> {
+    let report = block(move || {
+        let mut conn = pool.get()?;
+        resolve_report(
+            &mut conn,
+            path_id.into_inner(),
+            admin.0.into(),
+            resolution.into_inner(),
+        )
+    })
+    .await?
+    .map_err(|e| e.not_found_as("report_not_found", "No such report"))?;
+
+    Ok(HttpResponse::Ok().json(report))
+}
+
+/// Changes someone's role or suspends them.
+#[utoipa::path(
+    patch,
+    path = "/admin/users/{id}",
+    tag = "admin",
+    params(("id" = i32, Path, description = "User id")),
+    request_body = ModerateUser,
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "The user", body = AuthenticatedUser),
+        (status = 403, description = "`forbidden`", body = Problem),
+        (status = 404, description = "`user_not_found`", body = Problem),
+    ),
+)]
+pub async fn patch_users_id(
+    pool: web::Data<Pool>,
+    _admin: Admin,
+    path_id: web::Path<i32>,
+    moderation: ValidJson<ModerateUser>,
+) -> Result<HttpResponse, ServiceError> {
+    let user = block(move || {
+        let mut conn = pool.get()?;
+        moderate_user(&mut conn, path_id.into_inner(), moderation.into_inner())
+    })
+    .await?
+    .map_err(|e| e.not_found_as("user_not_found", "No such user"))?;
+
+    Ok(HttpResponse::Ok().json(user))
+}
+
+#[derive(Deserialize, Validate, ToSchema)]
+pub struct ModerateReview {
+    hidden: bool,
+}
+
+async fn moderate_review(
+    pool: web::Data<Pool>,
+    (user_id, category, tmdb_id): (i32, String, i32),
+    season: Option<i32>,
+    moderation: ModerateReview,
+) -> Result<HttpResponse, ServiceError> {
+    let Ok(category) = MediaCategory::try_from(category) else {
+        return Err(ServiceError::new(
+            400,
+            "invalid_category",
+            "Unrecognized media category",
+        ));
+    };
+
+    let review = block(move || {
+        let mut conn = pool.get()?;
+        set_review_hidden(
+            &mut conn,
+            user_id,
+            tmdb_id,
+            category,
+            season,
+            moderation.hidden,
+        )
+    })
+    .await?
+    .map_err(|e| e.not_found_as("review_not_found", "No such review"))?;
+
+    Ok(HttpResponse::Ok().json(review))
+}
+
+/// Hides or unhides someone's review of a film.
+#[utoipa::path(
+    patch,
+    path = "/admin/reviews/{user_id}/{category}/{tmdb_id}",
+    tag = "admin",
+    params(
+        ("user_id" = i32, Path, description = "Who wrote the review"),
+        ("category" = MediaCategory, Path, description = "`Film` or `Show`"),
+        ("tmdb_id" = i32, Path, description = "TMDB id"),
+    ),
+    request_body = ModerateReview,
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "The review", body = Review),
+        (status = 400, description = "`invalid_category`", body = Problem),
+        (status = 403, description = "`forbidden`", body = Problem),
+        (status = 404, description = "`review_not_found`", body = Problem),
+    ),
+)]
+pub async fn patch_reviews(
+    pool: web::Data<Pool>,
+    _admin: Admin,
+    path: web::Path<(i32, String, i32)>,
+    moderation: ValidJson<ModerateReview>,
+) -> Result<HttpResponse, ServiceError> {
+    moderate_review(pool, path.into_inner(), None, moderation.into_inner()).await
+}
+
+/// Hides or unhides someone's review of a season.
+#[utoipa::path(
+    patch,
+    path = "/admin/reviews/{user_id}/{category}/{tmdb_id}/{season}",
+    tag = "admin",
+    params(
+        ("user_id" = i32, Path, description = "Who wrote the review"),
+        ("category" = MediaCategory, Path, description = "`Film` or `Show`"),
+        ("tmdb_id" = i32, Path, description = "TMDB id"),
+        ("season" = i32, Path, description = "Season number, 0 for specials"),
+    ),
+    request_body = ModerateReview,
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "The review", body = Review),
+        (status = 400, description = "`invalid_category`", body = Problem),
+        (status = 403, description = "`forbidden`", body = Problem),
+        (status = 404, description = "`review_not_found`", body = Problem),
+    ),
+)]
+pub async fn patch_reviews_season(
+    pool: web::Data<Pool>,
+    _admin: Admin,
+    path: web::Path<(i32, String, i32, i32)>,
+    moderation: ValidJson<ModerateReview>,
+) -> Result<HttpResponse, ServiceError> {
+    let (user_id, category, tmdb_id, season) = path.into_inner();
+
+    moderate_review(
+        pool,
+        (user_id, category, tmdb_id),
+        Some(season),
+        moderation.into_inner(),
+    )
+    .await
+}
+
+#[derive(Deserialize, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PurgeQuery {
+    /// Only TMDB paths starting with this, e.g. `/search` or `/movie/505642`
+    #[validate(length(min = 1, max = 200))]
+    #[param(min_length = 1, max_length = 200)]
+    prefix: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Purged {
+    pub purged: usize,
+}
+
+/// Purges cached TMDB responses.
+#[utoipa::path(
+    delete,
+    path = "/admin/cache",
+    tag = "admin",
+    params(PurgeQuery),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "How many entries were removed", body = Purged),
+        (status = 403, description = "`forbidden`", body = Problem),
+    ),
+)]
+pub async fn delete_cache(
+    cache: web::Data<ResponseCache>,
+    _admin: Admin,
+    query: ValidQuery<PurgeQuery>,
+) -> Result<HttpResponse, ServiceError> {
+    let purged = cache.purge(query.prefix.as_deref()).await?;
+
+    Ok(HttpResponse::Ok().json(Purged { purged }))
+}
@@ -1,97 +1,229 @@
-use std::future::{self, Ready};
-
-use crate::actions::users::{find_auth_user_by_email, find_auth_user_by_id};
-
-use crate::{errors::ServiceError, utils::verify_password, Pool};
+use std::{future::Future, pin::Pin};
+
+use crate::actions::users::{find_auth_user_by_email, find_auth_user_by_id, find_user_access};
+
+use crate::models::UserRole;
+use crate::{
+    config::Config,
+    errors::ServiceError,
+    metrics,
+    utils::{block, verify_password},
+    validation::ValidJson,
+    Pool,
+};
 use actix_identity::Identity;
 
-use actix_web::{
-    delete, get, post, web, FromRequest, HttpMessage, HttpRequest, HttpResponse, Responder,
-};
+use actix_web::{web, FromRequest, HttpMessage, HttpRequest, HttpResponse, Responder};
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use validator::Validate;
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Validate, ToSchema)]
 pub struct AuthData {
+    #[validate(length(min = 1, max = 254))]
+    #[schema(min_length = 1, max_length = 254)]
     pub email: String,
+    // Only so huge passwords aren't hashed, the policy is checked when they're set
+    #[validate(length(min = 1, max = 128))]
+    #[schema(format = Password, min_length = 1, max_length = 128)]
     pub password: String,
 }
 
 #[derive(Debug, Serialize, Clone, Copy)]
-pub struct UserId(i32);
+pub struct UserId {
+    id: i32,
+    role: UserRole,
+}
 
-impl From<i32> for UserId {
-    fn from(n: i32) -> Self {
-        UserId(n)
+impl UserId {
+    pub fn is_admin(&self) -> bool {
+        self.role == UserRole::Admin
     }
 }
+
 impl From<UserId> for i32 {
     fn from(user_id: UserId) -> Self {
-        user_id.0
+        user_id.id
     }
 }
 
 impl FromRequest for UserId {
     type Error = ServiceError;
-    type Future = Ready<Result<UserId, ServiceError>>;
+    type Future = Pin<Box<dyn Future<Output = Result<UserId, ServiceError>>>>;
 
     fn from_request(
         req: &actix_web::HttpRequest,
         payload: &mut actix_web::dev::Payload,
     ) -> Self::Future {
-        if let Ok(identity) = Identity::from_request(req, payload).into_inner() {
-            let user_id = identity.id().unwrap().parse::<i32>().unwrap();
+        let id = Identity::from_request(req, payload)
+            .into_inner()
+            .ok()
+            .and_then(|identity| identity.id().ok())
+            .and_then(|id| id.parse::<i32>().ok());
+        let pool = req.app_data::<web::Data<Pool>>().cloned();
+
+        Box::pin(async move {
+            let (Some(id), Some(pool)) = (id, pool) else {
+                return Err(ServiceError::new(401, "unauthenticated", "Log in first"));
+            };
+
+            // Checked on every request so suspensions take effect immediately
+            let access = block(move || {
+                let mut conn = pool.get()?;
+                find_user_access(&mut conn, id)
+            })
+            .await??;
+
+            let Some(access) = access else {
+                return Err(ServiceError::new(401, "unauthenticated", "Log in first"));
+            };
+
+            if access.suspended_at.is_some() {
+                return Err(ServiceError::new(
+                    403,
+                    "account_suspended",
+                    "This account is suspended",
+                ));
+            }
+
+            Ok(UserId {
+                id,
+                role: access.role,
+            })
+        })
+    }
+}
 
-            return future::ready(Ok(UserId(user_id)));
-        }
+/// Only extracts successfully for admins.
+#[derive(Debug, Serialize, Clone, Copy)]
+pub struct Admin(pub UserId);
+
+impl FromRequest for Admin {
+    type Error = ServiceError;
+    type Future = Pin<Box<dyn Future<Output = Result<Admin, ServiceError>>>>;
+
+    fn from_request(
+        req: &actix_web::HttpRequest,
+        payload: &mut actix_web::dev::Payload,
+    ) -> Self::Future {
+        let user_id = UserId::from_request(req, payload);
 
-        future::ready(Err(ServiceError::pls(401)))
+        Box::pin(async move {
+            let user_id = user_id.await?;
+
+            if !user_id.is_admin() {
+                return Err(ServiceError::new(
+                    403,
+                    "forbidden",
+                    "Only admins can do that",
+                ));
+            }
+
+            Ok(Admin(user_id))
+        })
     }
 }
 
-#[delete("")]
+/// Logs out.
+#[utoipa::path(
+    delete,
+    path = "/auth",
+    tag = "auth",
+    security(("session" = [])),
+    responses(
+        (status = 204, description = "Logged out"),
+        (status = 401, description = "Wasn't logged in"),
+    ),
+)]
 pub async fn logout(id: Identity) -> impl Responder {
     id.logout();
     HttpResponse::NoContent()
 }
 
-#[post("")]
+/// Logs in, setting the session cookie.
+#[utoipa::path(
+    post,
+    path = "/auth",
+    tag = "auth",
+    request_body = AuthData,
+    responses(
+        (status = 200, description = "Logged in", body = AuthenticatedUser,
+            headers(("Set-Cookie" = String, description = "The session, as `id`"))),
+        (status = 401, description = "`invalid_credentials`", body = Problem),
+        (status = 403, description = "`account_suspended`", body = Problem),
+        (status = 422, description = "`validation_failed`", body = Problem),
+    ),
+)]
 pub async fn login(
     request: HttpRequest,
-    auth_data: web::Json<AuthData>,
+    auth_data: ValidJson<AuthData>,
     pool: web::Data<Pool>,
+    config: web::Data<Config>,
 ) -> Result<HttpResponse, ServiceError> {
-    let user = web::block(move || {
+    let user = block(move || {
         let mut conn = pool.get()?;
         let potential = find_auth_user_by_email(&mut conn, &auth_data.email)?;
 
         let Some(user) = potential else {
-            return Err(ServiceError::pls(404));
+            return Err(ServiceError::new(
+                401,
+                "invalid_credentials",
+                "Wrong email or password",
+            ));
         };
-        let verified = verify_password(&auth_data.password, &user.hash)?;
+        let verified = verify_password(
+            &auth_data.password,
+            &user.hash,
+            config.auth.secret_key.expose(),
+        )?;
 
         if !verified {
-            return Err(ServiceError::pls(401));
+            return Err(ServiceError::new(
+                401,
+                "invalid_credentials",
+                "Wrong email or password",
+            ));
+        }
+
+        if user.suspended_at.is_some() {
+            return Err(ServiceError::new(
+                403,
+                "account_suspended",
+                "This account is suspended",
+            ));
         }
 
         Ok(user)
     })
-    .await??;
+    .await?;
+    metrics::observe_login(&user);
+    let user = user?;
 
     Identity::login(&request.extensions(), user.id.to_string()).unwrap();
 
     Ok(HttpResponse::Ok().json(user))
 }
 
-#[get("")]
+/// The logged in user, with their email.
+#[utoipa::path(
+    get,
+    path = "/auth",
+    tag = "auth",
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "The logged in user", body = AuthenticatedUser),
+        (status = 401, description = "`unauthenticated`", body = Problem),
+    ),
+)]
 pub async fn me(pool: web::Data<Pool>, user_id: UserId) -> Result<HttpResponse, ServiceError> {
-    let user = web::block(move || {
+    let user = block(move || {
         let mut conn = pool.get()?;
         find_auth_user_by_id(&mut conn, i32::from(user_id))
     })
     .await??;
 
     let Some(user) = user else {
-        return Err(ServiceError::pls(404));
+        return Err(ServiceError::new(404, "user_not_found", "No such user"));
     };
     Ok(HttpResponse::Ok().json(user))
 }
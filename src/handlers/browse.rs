@@ -0,0 +1,367 @@
+use actix_web::{
+    http::header::{CacheControl, CacheDirective},
+    web, HttpResponse,
+};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+use validator::Validate;
+
+use crate::{
+    actions::reviews::{get_review_stats_batch, WithStats},
+    errors::{FieldError, ServiceError},
+    handlers::auth::UserId,
+    media,
+    models::{MediaCategory, NewMedia},
+    tmdb::{
+        DiscoverFilters, DiscoverSort, FilmsWithStats, Movie, Results, Show, ShowsWithStats,
+        TimeWindow, TmdbClient,
+    },
+    utils::block,
+    validation::ValidQuery,
+    Pool,
+};
+
+// Trending is updated daily by TMDB, the rest change more slowly
+const TRENDING_MAX_AGE: u32 = 60 * 60;
+const BROWSE_MAX_AGE: u32 = 6 * 60 * 60;
+
+#[derive(Deserialize, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct TrendingQuery {
+    #[param(inline)]
+    category: MediaCategory,
+    /// `week` by default
+    #[param(inline)]
+    window: Option<TimeWindow>,
+    // TMDB doesn't go past page 500
+    #[validate(range(min = 1, max = 500))]
+    #[param(minimum = 1, maximum = 500)]
+    page: Option<i32>,
+    /// e.g. `en-US`
+    #[validate(length(min = 2, max = 10))]
+    #[param(min_length = 2, max_length = 10)]
+    lang: Option<String>,
+    /// `stats` to attach review stats to each title
+    include: Option<String>,
+}
+
+#[derive(Deserialize, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PopularQuery {
+    #[param(inline)]
+    category: MediaCategory,
+    // TMDB doesn't go past page 500
+    #[validate(range(min = 1, max = 500))]
+    #[param(minimum = 1, maximum = 500)]
+    page: Option<i32>,
+    /// e.g. `en-US`
+    #[validate(length(min = 2, max = 10))]
+    #[param(min_length = 2, max_length = 10)]
+    lang: Option<String>,
+    /// `stats` to attach review stats to each title
+    include: Option<String>,
+}
+
+#[derive(Deserialize, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DiscoverQuery {
+    #[param(inline)]
+    category: MediaCategory,
+    // TMDB doesn't go past page 500
+    #[validate(range(min = 1, max = 500))]
+    #[param(minimum = 1, maximum = 500)]
+    page: Option<i32>,
+    /// e.g. `en-US`
+    #[validate(length(min = 2, max = 10))]
+    #[param(min_length = 2, max_length = 10)]
+    lang: Option<String>,
+    /// `stats` to attach review stats to each title
+    include: Option<String>,
+    /// Comma separated TMDB genre ids, any of them match
+    #[validate(length(max = 100))]
+    #[param(max_length = 100)]
+    genre: Option<String>,
+    #[validate(range(min = 1800, max = 2200))]
+    #[param(minimum = 1800, maximum = 2200)]
+    year_from: Option<i32>,
+    /// Can't be before `year_from`
+    #[validate(range(min = 1800, max = 2200))]
+    #[param(minimum = 1800, maximum = 2200)]
+    year_to: Option<i32>,
+    /// ISO 639-1 original language
+    #[validate(length(min = 2, max = 2))]
+    #[param(min_length = 2, max_length = 2)]
+    language: Option<String>,
+    #[param(inline)]
+    sort_by: Option<DiscoverSort>,
+}
+
+impl DiscoverQuery {
+    // Validate can't compare fields, so this is checked once the rest has passed
+    fn check_years(&self) -> Result<(), ServiceError> {
+        match (self.year_from, self.year_to) {
+            (Some(from), Some(to)) if to < from => {
+                Err(ServiceError::validation(vec![FieldError::new(
+                    "year_to",
+                    "before_year_from",
+                    format!("Can't be before year_from, {from}"),
+                )]))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Films or shows, whichever category was asked for.
+#[derive(Serialize, ToSchema)]
+#[serde(untagged)]
+pub enum Browsed {
+    Films(FilmsWithStats),
+    Shows(ShowsWithStats),
+}
+
+impl From<FilmsWithStats> for Browsed {
+    fn from(films: FilmsWithStats) -> Self {
+        Browsed::Films(films)
+    }
+}
+
+impl From<ShowsWithStats> for Browsed {
+    fn from(shows: ShowsWithStats) -> Self {
+        Browsed::Shows(shows)
+    }
+}
+
+fn include_stats(include: Option<&str>) -> Result<bool, ServiceError> {
+    match include {
+        None | Some("") => Ok(false),
+        Some("stats") => Ok(true),
+        Some(other) => Err(ServiceError::new(
+            400,
+            "invalid_include",
+            format!("Can't include {other}, try stats"),
+        )),
+    }
+}
+
+/// Caches the titles, attaches stats if asked, and lets clients cache the response.
+async fn respond<T>(
+    pool: web::Data<Pool>,
+    viewer: Option<UserId>,
+    lang: Option<&str>,
+    with_stats: bool,
+    body: Results<T>,
+    max_age: u32,
+) -> Result<HttpResponse, ServiceError>
+where
+    NewMedia: for<'a> From<&'a T>,
+    Browsed: From<Results<WithStats<T>>>,
+{
+    let cacheable: Vec<NewMedia> = body.results.iter().map(NewMedia::from).collect();
+    let keys: Vec<_> = cacheable.iter().map(|m| (m.tmdb_id, m.category)).collect();
+    media::cache_search_results(&pool, lang, cacheable);
+
+    let viewer = viewer.map(i32::from);
+
+    let mut stats = if with_stats {
+        let keys = keys.clone();
+        block(move || {
+            let mut conn = pool.get()?;
+            get_review_stats_batch(&mut conn, viewer, &keys)
+        })
+        .await??
+    } else {
+        Default::default()
+    };
+
+    let results = body
+        .results
+        .into_iter()
+        .zip(keys)
+        .map(|(item, key)| WithStats {
+            item,
+            stats: with_stats.then(|| stats.remove(&key).unwrap_or_default()),
+        })
+        .collect();
+
+    // Stats depend on who's asking
+    let visibility = match (with_stats, viewer) {
+        (true, Some(_)) => CacheDirective::Private,
+        _ => CacheDirective::Public,
+    };
+
+    Ok(HttpResponse::Ok()
+        .insert_header(CacheControl(vec![
+            visibility,
+            CacheDirective::MaxAge(max_age),
+        ]))
+        .json(Browsed::from(Results {
+            results,
+            page: body.page,
+            total_pages: body.total_pages,
+            total_results: body.total_results,
+        })))
+}
+
+/// What's trending on TMDB today or this week.
+#[utoipa::path(
+    get,
+    path = "/browse/trending",
+    tag = "browse",
+    params(TrendingQuery),
+    security((), ("session" = [])),
+    responses(
+        (status = 200, description = "A page of titles, cacheable for an hour", body = Browsed),
+        (status = 400, description = "`invalid_include`, `invalid_query` for a missing category, or `rejected_by_tmdb`", body = Problem),
+        (status = 422, description = "`validation_failed`", body = Problem),
+        (status = 502, description = "`tmdb_unavailable` or `tmdb_bad_response`", body = Problem),
+        (status = 503, description = "`tmdb_rate_limited`", body = Problem),
+        (status = 504, description = "`tmdb_timeout`", body = Problem),
+    ),
+)]
+pub async fn get_trending(
+    pool: web::Data<Pool>,
+    tmdb: web::Data<TmdbClient>,
+    viewer: Option<UserId>,
+    query: ValidQuery<TrendingQuery>,
+) -> Result<HttpResponse, ServiceError> {
+    let with_stats = include_stats(query.include.as_deref())?;
+    let window = query.window.unwrap_or(TimeWindow::Week);
+    let lang = query.lang.as_deref();
+
+    match query.category {
+        MediaCategory::Film => {
+            let body = tmdb
+                .trending::<Movie>(query.category, window, query.page, lang)
+                .await?;
+            respond(pool, viewer, lang, with_stats, body, TRENDING_MAX_AGE).await
+        }
+        MediaCategory::Show => {
+            let body = tmdb
+                .trending::<Show>(query.category, window, query.page, lang)
+                .await?;
+            respond(pool, viewer, lang, with_stats, body, TRENDING_MAX_AGE).await
+        }
+    }
+}
+
+/// What's popular on TMDB.
+#[utoipa::path(
+    get,
+    path = "/browse/popular",
+    tag = "browse",
+    params(PopularQuery),
+    security((), ("session" = [])),
+    responses(
+        (status = 200, description = "A page of titles, cacheable for 6 hours", body = Browsed),
+        (status = 400, description = "`invalid_include`, `invalid_query` for a missing category, or `rejected_by_tmdb`", body = Problem),
+        (status = 422, description = "`validation_failed`", body = Problem),
+        (status = 502, description = "`tmdb_unavailable` or `tmdb_bad_response`", body = Problem),
+        (status = 503, description = "`tmdb_rate_limited`", body = Problem),
+        (status = 504, description = "`tmdb_timeout`", body = Problem),
+    ),
+)]
+pub async fn get_popular(
+    pool: web::Data<Pool>,
+    tmdb: web::Data<TmdbClient>,
+    viewer: Option<UserId>,
+    query: ValidQuery<PopularQuery>,
+) -> Result<HttpResponse, ServiceError> {
+    let with_stats = include_stats(query.include.as_deref())?;
+    let lang = query.lang.as_deref();
+
+    match query.category {
+        MediaCategory::Film => {
+            let body = tmdb
+                .popular::<Movie>(query.category, query.page, lang)
+                .await?;
+            respond(pool, viewer, lang, with_stats, body, BROWSE_MAX_AGE).await
+        }
+        MediaCategory::Show => {
+            let body = tmdb
+                .popular::<Show>(query.category, query.page, lang)
+                .await?;
+            respond(pool, viewer, lang, with_stats, body, BROWSE_MAX_AGE).await
+        }
+    }
+}
+
+/// Titles matching filters, from TMDB's discover.
+#[utoipa::path(
+    get,
+    path = "/browse/discover",
+    tag = "browse",
+    params(DiscoverQuery),
+    security((), ("session" = [])),
+    responses(
+        (status = 200, description = "A page of titles, cacheable for 6 hours", body = Browsed),
+        (status = 400, description = "`invalid_include`, `invalid_query` for a missing category, or `rejected_by_tmdb`", body = Problem),
+        (status = 422, description = "`validation_failed`", body = Problem),
+        (status = 502, description = "`tmdb_unavailable` or `tmdb_bad_response`", body = Problem),
+        (status = 503, description = "`tmdb_rate_limited`", body = Problem),
+        (status = 504, description = "`tmdb_timeout`", body = Problem),
+    ),
+)]
+pub async fn get_discover(
+    pool: web::Data<Pool>,
+    tmdb: web::Data<TmdbClient>,
+    viewer: Option<UserId>,
+    query: ValidQuery<DiscoverQuery>,
+) -> Result<HttpResponse, ServiceError> {
+    query.check_years()?;
+    let with_stats = include_stats(query.include.as_deref())?;
+    let lang = query.lang.as_deref();
+
+    let filters = DiscoverFilters {
+        genre: query.genre.clone(),
+        year_from: query.year_from,
+        year_to: query.year_to,
+        language: query.language.clone(),
+        sort_by: query.sort_by,
+    };
+
+    match query.category {
+        MediaCategory::Film => {
+            let body = tmdb
+                .discover::<Movie>(query.category, &filters, query.page, lang)
+                .await?;
+            respond(pool, viewer, lang, with_stats, body, BROWSE_MAX_AGE).await
+        }
+        MediaCategory::Show => {
+            let body = tmdb
+                .discover::<Show>(query.category, &filters, query.page, lang)
+                .await?;
+            respond(pool, viewer, lang, with_stats, body, BROWSE_MAX_AGE).await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use validator::Validate;
+
+    use super::DiscoverQuery;
+
+    fn query(query: &str) -> DiscoverQuery {
+        serde_urlencoded::from_str(query).unwrap()
+    }
+
+    #[test]
+    fn pages_and_years_are_checked_before_asking_tmdb() {
+        assert!(query("category=Film&page=1").validate().is_ok());
+        assert!(query("category=Film&page=0").validate().is_err());
+        assert!(query("category=Film&page=501").validate().is_err());
+        assert!(query("category=Film&language=eng").validate().is_err());
+
+        assert!(query("category=Film&year_from=1990&year_to=1990")
+            .check_years()
+            .is_ok());
+        assert!(query("category=Film&year_from=1990").check_years().is_ok());
+
+        let e = query("category=Film&year_from=2000&year_to=1990")
+            .check_years()
+            .unwrap_err();
+        assert_eq!((e.status, e.code), (422, "validation_failed"));
+        assert_eq!(e.fields[0].field, "year_to");
+    }
+}
@@ -0,0 +1,108 @@
+use actix_web::{web, HttpResponse};
+use serde::Deserialize;
+use utoipa::IntoParams;
+use validator::Validate;
+
+use crate::{
+    actions::imports::{create_import_job, find_import_job},
+    errors::ServiceError,
+    handlers::auth::UserId,
+    import,
+    models::{ImportSource, WatchStatus},
+    tmdb::TmdbClient,
+    utils::block,
+    validation::ValidQuery,
+    Pool,
+};
+
+#[derive(Deserialize, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ImportQuery {
+    /// Match titles without saving anything
+    dry_run: Option<bool>,
+    /// Overrides the status of every entry, e.g. for a Letterboxd `watchlist.csv`
+    #[param(inline)]
+    status: Option<WatchStatus>,
+}
+
+/// Starts importing reviews from another site's export, in the background.
+#[utoipa::path(
+    post,
+    path = "/import/{source}",
+    tag = "import",
+    params(("source" = ImportSource, Path, description = "Where the export is from"), ImportQuery),
+    request_body(
+        content = String,
+        description = "The export file as is, CSV for Letterboxd, JSON for Trakt and `ReviewApi`, XML for MyAnimeList",
+        content_type = "text/plain",
+    ),
+    security(("session" = [])),
+    responses(
+        (status = 202, description = "The job, poll it for progress", body = ImportJob),
+        (status = 400, description = "`invalid_import`", body = Problem),
+        (status = 413, description = "`payload_too_large`", body = Problem),
+    ),
+)]
+pub async fn post_import(
+    pool: web::Data<Pool>,
+    tmdb: web::Data<TmdbClient>,
+    user_id: UserId,
+    source: web::Path<ImportSource>,
+    query: ValidQuery<ImportQuery>,
+    body: web::Bytes,
+) -> Result<HttpResponse, ServiceError> {
+    let source = source.into_inner();
+    let dry_run = query.dry_run.unwrap_or(false);
+    let user_id = i32::from(user_id);
+
+    let entries = import::parse(source, &body, query.status)?;
+    let total = entries.len() as i32;
+
+    let job_pool = pool.clone();
+    let job = block(move || {
+        let mut conn = job_pool.get()?;
+        create_import_job(&mut conn, user_id, source, dry_run, total)
+    })
+    .await??;
+
+    actix_web::rt::spawn(import::run_import(
+        pool.get_ref().clone(),
+        tmdb,
+        job.id,
+        user_id,
+        dry_run,
+        entries,
+    ));
+
+    Ok(HttpResponse::Accepted().json(job))
+}
+
+/// How one of your imports is going.
+#[utoipa::path(
+    get,
+    path = "/import/{id}",
+    tag = "import",
+    params(("id" = i32, Path, description = "Job id")),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "The job", body = ImportJob),
+        (status = 404, description = "`import_not_found`", body = Problem),
+    ),
+)]
+pub async fn get_import(
+    pool: web::Data<Pool>,
+    user_id: UserId,
+    id: web::Path<i32>,
+) -> Result<HttpResponse, ServiceError> {
+    let job = block(move || {
+        let mut conn = pool.get()?;
+        find_import_job(&mut conn, user_id.into(), id.into_inner())
+    })
+    .await??;
+
+    let Some(job) = job else {
+        return Err(ServiceError::new(404, "import_not_found", "No such import"));
+    };
+
+    Ok(HttpResponse::Ok().json(job))
+}
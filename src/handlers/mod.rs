@@ -1,4 +1,18 @@
+pub mod admin;
 pub mod auth;
+pub mod browse;
+pub mod imports;
+pub mod monitoring;
 pub mod reviews;
 pub mod search;
+pub mod titles;
 pub mod users;
+
+use serde::Serialize;
+use utoipa::ToSchema;
+
+/// How many rows a delete removed, 0 when there was nothing to delete.
+#[derive(Serialize, ToSchema)]
+pub struct Deleted {
+    pub deleted: usize,
+}
@@ -0,0 +1,173 @@
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    time::{Duration, Instant},
+};
+
+use actix_web::{
+    get, http::header::AUTHORIZATION, rt::time::timeout, web, HttpRequest, HttpResponse,
+};
+use diesel::{sql_query, RunQueryDsl};
+use redis::aio::ConnectionManager;
+use serde::Serialize;
+use serde_json::json;
+use subtle::ConstantTimeEq;
+
+use crate::{
+    config::Config, errors::ServiceError, metrics, migrations, tmdb::TmdbClient, utils::block, Pool,
+};
+
+// Long enough for a slow dependency, short enough for the platform's check timeout
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Serialize)]
+struct Check {
+    ok: bool,
+    latency_ms: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+enum Failure {
+    /// Only logged, it can have hostnames and credentials in it
+    Unreachable(String),
+    Pending(usize),
+}
+
+async fn check<F>(name: &str, f: F) -> Check
+where
+    F: Future<Output = Result<(), Failure>>,
+{
+    let started = Instant::now();
+    let error = match timeout(CHECK_TIMEOUT, f).await {
+        Ok(Ok(())) => None,
+        Ok(Err(Failure::Unreachable(e))) => {
+            tracing::warn!("Readiness check for {name} failed: {e}");
+            Some("unreachable".to_string())
+        }
+        Ok(Err(Failure::Pending(pending))) => Some(format!("{pending} pending")),
+        Err(_) => Some("timed out".to_string()),
+    };
+
+    Check {
+        ok: error.is_none(),
+        latency_ms: started.elapsed().as_secs_f64() * 1000.0,
+        error,
+    }
+}
+
+fn unreachable(e: impl ToString) -> Failure {
+    Failure::Unreachable(e.to_string())
+}
+
+async fn check_postgres(pool: Pool) -> Result<(), Failure> {
+    block(move || {
+        let mut conn = pool.get().map_err(unreachable)?;
+        sql_query("SELECT 1")
+            .execute(&mut conn)
+            .map_err(unreachable)
+    })
+    .await
+    .map_err(unreachable)?
+    .map(|_| ())
+}
+
+async fn check_migrations(pool: Pool) -> Result<(), Failure> {
+    let pending = block(move || {
+        let mut conn = pool.get().map_err(unreachable)?;
+        migrations::pending(&mut conn)
+            .map(|pending| pending.len())
+            .map_err(unreachable)
+    })
+    .await
+    .map_err(unreachable)??;
+
+    match pending {
+        0 => Ok(()),
+        pending => Err(Failure::Pending(pending)),
+    }
+}
+
+// The session store doesn't expose its connection, so this uses the one the cache shares
+async fn check_redis(redis: Option<ConnectionManager>) -> Result<(), Failure> {
+    let mut conn = redis.ok_or_else(|| unreachable("not connected since startup"))?;
+
+    redis::cmd("PING")
+        .query_async::<_, String>(&mut conn)
+        .await
+        .map(|_| ())
+        .map_err(unreachable)
+}
+
+#[get("/live")]
+pub async fn get_live() -> HttpResponse {
+    HttpResponse::Ok().json(json!({ "ok": true }))
+}
+
+#[get("/ready")]
+pub async fn get_ready(
+    pool: web::Data<Pool>,
+    redis: web::Data<Option<ConnectionManager>>,
+    config: web::Data<Config>,
+    tmdb: web::Data<TmdbClient>,
+) -> HttpResponse {
+    let (postgres, migrations, redis) = futures_util::join!(
+        check("postgres", check_postgres(pool.get_ref().clone())),
+        check("migrations", check_migrations(pool.get_ref().clone())),
+        check("redis", check_redis(redis.get_ref().clone())),
+    );
+    let ready = postgres.ok && migrations.ok && redis.ok;
+
+    let mut checks = BTreeMap::from([
+        ("postgres", postgres),
+        ("migrations", migrations),
+        ("redis", redis),
+    ]);
+
+    if config.health.check_tmdb {
+        let tmdb = check("tmdb", async {
+            tmdb.ping().await.map_err(|e| unreachable(e.message))
+        })
+        .await;
+        checks.insert("tmdb", tmdb);
+    }
+
+    let mut res = if ready {
+        HttpResponse::Ok()
+    } else {
+        HttpResponse::ServiceUnavailable()
+    };
+
+    res.json(json!({ "ready": ready, "checks": checks }))
+}
+
+#[get("/metrics")]
+pub async fn get_metrics(
+    request: HttpRequest,
+    pool: web::Data<Pool>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, ServiceError> {
+    if let Some(token) = &config.metrics.token {
+        let authorized = request
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            // Constant time, so the token can't be guessed a byte at a time
+            .is_some_and(|given| bool::from(given.as_bytes().ct_eq(token.expose().as_bytes())));
+
+        if !authorized {
+            return Err(ServiceError::new(
+                401,
+                "unauthenticated",
+                "Missing or wrong metrics token",
+            ));
+        }
+    }
+
+    let body = metrics::render(&pool)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(prometheus::TEXT_FORMAT)
+        .body(body))
+}
@@ -1,112 +1,367 @@
-use actix_web::{get, post, web, HttpRequest, HttpResponse};
-use serde_json::json;
+use actix_web::{web, HttpResponse};
 
 use crate::{
-    actions::reviews::{
-        create_review_for_user, delete_review, get_all_reviews, update_review, InputReview,
-        ReviewsQuery,
+    actions::{
+        reports::{report_review, InputReport},
+        reviews::{
+            create_review_for_user, delete_review, get_all_reviews, include_details, update_review,
+            Include, InputReview, ReviewsQuery,
+        },
     },
     errors::ServiceError,
-    handlers::auth::UserId,
+    handlers::{auth::UserId, Deleted},
+    media,
     models::{EditReview, MediaCategory},
+    tmdb::TmdbClient,
+    utils::block,
+    validation::{ValidJson, ValidQuery},
     Pool,
 };
 
-#[get("")]
+/// Lists the reviews you can see, most recently updated first by default.
+#[utoipa::path(
+    get,
+    path = "/reviews",
+    tag = "reviews",
+    params(ReviewsQuery),
+    security((), ("session" = [])),
+    responses(
+        (status = 200, description = "A page of reviews", body = PaginatedReviews),
+        (status = 400, description = "`invalid_include`", body = Problem),
+        (status = 422, description = "`validation_failed`", body = Problem),
+    ),
+)]
 pub async fn get_reviews(
     pool: web::Data<Pool>,
-    query: web::Query<ReviewsQuery>,
+    tmdb: web::Data<TmdbClient>,
+    viewer: Option<UserId>,
+    query: ValidQuery<ReviewsQuery>,
 ) -> Result<HttpResponse, ServiceError> {
-    let reviews = web::block(move || {
-        let mut conn = pool.get()?;
-        get_all_reviews(&mut conn, query.into_inner())
+    let include = Include::parse(query.include.as_deref())?;
+
+    let conn_pool = pool.clone();
+    let reviews = block(move || {
+        let mut conn = conn_pool.get()?;
+        let reviews = get_all_reviews(&mut conn, viewer.map(i32::from), query.into_inner())?;
+        include_details(&mut conn, reviews, include)
     })
     .await??;
 
+    // Titles reviewed before the media cache existed get cached for next time
+    for review in &reviews.results {
+        if let Some(None) = review.media {
+            media::prefetch_media(&pool, &tmdb, review.review.tmdb_id, review.review.category);
+        }
+    }
+
     Ok(HttpResponse::Ok().json(reviews))
 }
 
-#[post("")]
+/// Reviews a film, or a season of a show, after checking it exists on TMDB.
+#[utoipa::path(
+    post,
+    path = "/reviews",
+    tag = "reviews",
+    request_body = InputReview,
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "The new review", body = Review),
+        (status = 409, description = "`review_exists`", body = Problem),
+        (status = 422, description = "`validation_failed`, including titles TMDB doesn't have", body = Problem),
+        (status = 502, description = "`tmdb_unavailable`, when the title couldn't be checked", body = Problem),
+    ),
+)]
 pub async fn post_reviews(
     pool: web::Data<Pool>,
+    tmdb: web::Data<TmdbClient>,
     user_id: UserId,
-    input_review: web::Json<InputReview>,
+    input_review: ValidJson<InputReview>,
 ) -> Result<HttpResponse, ServiceError> {
-    let review = web::block(move || {
-        let mut conn = pool.get()?;
+    media::validate_review(
+        &pool,
+        &tmdb,
+        input_review.tmdb_id,
+        input_review.category,
+        input_review.season,
+    )
+    .await?;
+
+    let conn_pool = pool.clone();
+    let review = block(move || {
+        let mut conn = conn_pool.get()?;
         create_review_for_user(&mut conn, i32::from(user_id), input_review.into_inner())
     })
     .await??;
 
+    media::prefetch_media(&pool, &tmdb, review.tmdb_id, review.category);
+
+    Ok(HttpResponse::Ok().json(review))
+}
+
+fn parse_category(category: String) -> Result<MediaCategory, ServiceError> {
+    MediaCategory::try_from(category)
+        .map_err(|_| ServiceError::new(400, "invalid_category", "Unrecognized media category"))
+}
+
+async fn patch_review(
+    pool: web::Data<Pool>,
+    user_id: UserId,
+    category: String,
+    tmdb_id: i32,
+    season: Option<i32>,
+    item: EditReview,
+) -> Result<HttpResponse, ServiceError> {
+    let category = parse_category(category)?;
+
+    let review = block(move || {
+        let mut conn = pool.get()?;
+        update_review(&mut conn, user_id.into(), tmdb_id, category, season, item)
+    })
+    .await?
+    .map_err(|e| ServiceError::from(e).not_found_as("review_not_found", "No such review"))?;
+
     Ok(HttpResponse::Ok().json(review))
 }
 
-// Both defined in main.rs, macro doesn't allow multiple
-// #[patch("/{category}/{id}/{season}")]
-// #[patch("/{category}/{id}")]
+/// Edits your review of a film.
+#[utoipa::path(
+    patch,
+    path = "/reviews/{category}/{tmdb_id}",
+    tag = "reviews",
+    params(
+        ("category" = MediaCategory, Path, description = "`Film` or `Show`"),
+        ("tmdb_id" = i32, Path, description = "TMDB id"),
+    ),
+    request_body = EditReview,
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "The updated review", body = Review),
+        (status = 400, description = "`invalid_category`", body = Problem),
+        (status = 404, description = "`review_not_found`", body = Problem),
+        (status = 422, description = "`validation_failed`", body = Problem),
+    ),
+)]
 pub async fn patch_reviews(
-    req: HttpRequest,
     pool: web::Data<Pool>,
     user_id: UserId,
     path: web::Path<(String, i32)>,
-    item: web::Json<EditReview>,
+    item: ValidJson<EditReview>,
 ) -> Result<HttpResponse, ServiceError> {
     let (category, tmdb_id) = path.into_inner();
 
-    let category = MediaCategory::try_from(category);
+    patch_review(pool, user_id, category, tmdb_id, None, item.into_inner()).await
+}
+
+/// Edits your review of a season.
+#[utoipa::path(
+    patch,
+    path = "/reviews/{category}/{tmdb_id}/{season}",
+    tag = "reviews",
+    params(
+        ("category" = MediaCategory, Path, description = "`Film` or `Show`"),
+        ("tmdb_id" = i32, Path, description = "TMDB id"),
+        ("season" = i32, Path, description = "Season number, 0 for specials"),
+    ),
+    request_body = EditReview,
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "The updated review", body = Review),
+        (status = 400, description = "`invalid_category`", body = Problem),
+        (status = 404, description = "`review_not_found`", body = Problem),
+        (status = 422, description = "`validation_failed`", body = Problem),
+    ),
+)]
+pub async fn patch_reviews_season(
+    pool: web::Data<Pool>,
+    user_id: UserId,
+    path: web::Path<(String, i32, i32)>,
+    item: ValidJson<EditReview>,
+) -> Result<HttpResponse, ServiceError> {
+    let (category, tmdb_id, season) = path.into_inner();
 
-    let season = match req.match_info().get("season") {
-        Some(str) => str.parse().ok(),
-        None => None,
-    };
+    patch_review(
+        pool,
+        user_id,
+        category,
+        tmdb_id,
+        Some(season),
+        item.into_inner(),
+    )
+    .await
+}
 
-    let Ok(category) = category else {
-        return Err(ServiceError::new(400, "Unrecognized media category"));
-    };
+async fn remove_review(
+    pool: web::Data<Pool>,
+    user_id: UserId,
+    category: String,
+    tmdb_id: i32,
+    season: Option<i32>,
+) -> Result<HttpResponse, ServiceError> {
+    let category = parse_category(category)?;
 
-    let review = web::block(move || {
+    let deleted = block(move || {
         let mut conn = pool.get()?;
-        update_review(
-            &mut conn,
-            user_id.into(),
-            tmdb_id,
-            category,
-            season,
-            item.into_inner(),
-        )
+        delete_review(&mut conn, user_id.into(), tmdb_id, category, season)
     })
     .await??;
 
-    Ok(HttpResponse::Ok().json(review))
+    Ok(HttpResponse::Ok().json(Deleted { deleted }))
 }
 
-// Both defined in main.rs, macro doesn't allow multiple
-// #[delete("/{category}/{id}/{season}")]
-// #[delete("/{category}/{id}")]
+/// Deletes your review of a film.
+#[utoipa::path(
+    delete,
+    path = "/reviews/{category}/{tmdb_id}",
+    tag = "reviews",
+    params(
+        ("category" = MediaCategory, Path, description = "`Film` or `Show`"),
+        ("tmdb_id" = i32, Path, description = "TMDB id"),
+    ),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Whether a review was deleted", body = Deleted),
+        (status = 400, description = "`invalid_category`", body = Problem),
+    ),
+)]
 pub async fn delete_reviews(
-    req: HttpRequest,
     pool: web::Data<Pool>,
     user_id: UserId,
     path: web::Path<(String, i32)>,
 ) -> Result<HttpResponse, ServiceError> {
     let (category, tmdb_id) = path.into_inner();
 
-    let season = match req.match_info().get("season") {
-        Some(str) => str.parse().ok(),
-        None => None,
-    };
+    remove_review(pool, user_id, category, tmdb_id, None).await
+}
+
+/// Deletes your review of a season.
+#[utoipa::path(
+    delete,
+    path = "/reviews/{category}/{tmdb_id}/{season}",
+    tag = "reviews",
+    params(
+        ("category" = MediaCategory, Path, description = "`Film` or `Show`"),
+        ("tmdb_id" = i32, Path, description = "TMDB id"),
+        ("season" = i32, Path, description = "Season number, 0 for specials"),
+    ),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Whether a review was deleted", body = Deleted),
+        (status = 400, description = "`invalid_category`", body = Problem),
+    ),
+)]
+pub async fn delete_reviews_season(
+    pool: web::Data<Pool>,
+    user_id: UserId,
+    path: web::Path<(String, i32, i32)>,
+) -> Result<HttpResponse, ServiceError> {
+    let (category, tmdb_id, season) = path.into_inner();
 
-    let category = MediaCategory::try_from(category);
+    remove_review(pool, user_id, category, tmdb_id, Some(season)).await
+}
 
-    let Ok(category) = category else {
-        return Err(ServiceError::new(400, "Unrecognized media category"));
-    };
+async fn report(
+    pool: web::Data<Pool>,
+    user_id: UserId,
+    author_id: i32,
+    category: String,
+    tmdb_id: i32,
+    season: Option<i32>,
+    input_report: InputReport,
+) -> Result<HttpResponse, ServiceError> {
+    let category = parse_category(category)?;
 
-    let deleted = web::block(move || {
+    let report = block(move || {
         let mut conn = pool.get()?;
-        delete_review(&mut conn, user_id.into(), tmdb_id, category, season)
+        report_review(
+            &mut conn,
+            user_id.into(),
+            author_id,
+            tmdb_id,
+            category,
+            season,
+            input_report,
+        )
     })
     .await??;
 
-    Ok(HttpResponse::Ok().json(json!({ "deleted": deleted })))
+    Ok(HttpResponse::Ok().json(report))
+}
+
+/// Reports someone's review of a film to the admins.
+#[utoipa::path(
+    post,
+    path = "/reviews/{user_id}/{category}/{tmdb_id}/report",
+    tag = "reviews",
+    params(
+        ("user_id" = i32, Path, description = "Who wrote the review"),
+        ("category" = MediaCategory, Path, description = "`Film` or `Show`"),
+        ("tmdb_id" = i32, Path, description = "TMDB id"),
+    ),
+    request_body = InputReport,
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "The report", body = Report),
+        (status = 400, description = "`invalid_category`", body = Problem),
+        (status = 404, description = "`not_found`, including reviews you can't see", body = Problem),
+        (status = 422, description = "`validation_failed`", body = Problem),
+    ),
+)]
+pub async fn post_reviews_report(
+    pool: web::Data<Pool>,
+    user_id: UserId,
+    path: web::Path<(i32, String, i32)>,
+    input_report: ValidJson<InputReport>,
+) -> Result<HttpResponse, ServiceError> {
+    let (author_id, category, tmdb_id) = path.into_inner();
+
+    report(
+        pool,
+        user_id,
+        author_id,
+        category,
+        tmdb_id,
+        None,
+        input_report.into_inner(),
+    )
+    .await
+}
+
+/// Reports someone's review of a season to the admins.
+#[utoipa::path(
+    post,
+    path = "/reviews/{user_id}/{category}/{tmdb_id}/{season}/report",
+    tag = "reviews",
+    params(
+        ("user_id" = i32, Path, description = "Who wrote the review"),
+        ("category" = MediaCategory, Path, description = "`Film` or `Show`"),
+        ("tmdb_id" = i32, Path, description = "TMDB id"),
+        ("season" = i32, Path, description = "Season number, 0 for specials"),
+    ),
+    request_body = InputReport,
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "The report", body = Report),
+        (status = 400, description = "`invalid_category`", body = Problem),
+        (status = 404, description = "`not_found`, including reviews you can't see", body = Problem),
+        (status = 422, description = "`validation_failed`", body = Problem),
+    ),
+)]
+pub async fn post_reviews_season_report(
+    pool: web::Data<Pool>,
+    user_id: UserId,
+    path: web::Path<(i32, String, i32, i32)>,
+    input_report: ValidJson<InputReport>,
+) -> Result<HttpResponse, ServiceError> {
+    let (author_id, category, tmdb_id, season) = path.into_inner();
+
+    report(
+        pool,
+        user_id,
+        author_id,
+        category,
+        tmdb_id,
+        Some(season),
+        input_report.into_inner(),
+    )
+    .await
 }
@@ -1,120 +1,220 @@
-use actix_web::{get, web, HttpResponse};
-use awc;
-use serde::{Deserialize, Serialize};
+use actix_web::{web, HttpResponse};
+use serde::Serialize;
+use utoipa::ToSchema;
 
+use crate::actions::reviews::find_reviewed;
 use crate::errors::ServiceError;
-
-lazy_static::lazy_static! {
-  pub static ref TMDB_API_KEY: String = std::env::var("TMDB_API_KEY").unwrap();
-}
-
-const SEARCH_FILM_BASE: &str = "https://api.themoviedb.org/3/search/movie?";
-const SEARCH_SHOW_BASE: &str = "https://api.themoviedb.org/3/search/tv?";
-const API_PARAM: &str = "api_key";
-const QUERY_PARAM: &str = "query";
-const PAGE_PARAM: &str = "page";
-const LANG_PARAM: &str = "language";
-const FILM_YEAR_PARAM: &str = "year"; // primary_release_year is an alternative?
-const SHOW_YEAR_PARAM: &str = "first_air_date_year";
-
-#[derive(Deserialize)]
-pub struct SearchInfo {
-    query: String,
-    page: Option<i32>,
-    lang: Option<String>,
-    year: Option<i32>,
-}
-
-#[derive(Deserialize, Serialize, Debug)]
-pub struct Movie {
+use crate::handlers::auth::UserId;
+use crate::media;
+use crate::models::{MediaCategory, NewMedia};
+use crate::tmdb::{Movie, MultiResult, Results, SearchInfo, Show, TmdbClient};
+use crate::utils::block;
+use crate::validation::ValidQuery;
+use crate::Pool;
+
+#[derive(Serialize, ToSchema)]
+pub struct Summary {
     id: i32,
     title: String,
-    original_title: String,
-    original_language: String,
-    release_date: String,
-    overview: String,
-    poster_path: Option<String>,
+    original_title: Option<String>,
+    date: Option<String>,
+    overview: Option<String>,
+    /// Poster, or profile picture for people
+    image_path: Option<String>,
+    // Only for films and shows, when logged in
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reviewed: Option<bool>,
 }
 
-#[derive(Deserialize, Serialize, Debug)]
-pub struct Show {
-    id: i32,
-    name: String,
-    original_name: String,
-    original_language: String,
-    first_air_date: String,
-    overview: String,
-    poster_path: Option<String>,
+#[derive(Serialize, ToSchema)]
+#[serde(tag = "media_type")]
+pub enum SearchResult {
+    Film(Summary),
+    Show(Summary),
+    Person(Summary),
 }
 
-#[derive(Deserialize, Serialize, Debug)]
-pub struct Results<T> {
-    results: Vec<T>,
-    page: i32,
-    total_pages: i32,
-    total_results: i32,
+fn non_empty(value: String) -> Option<String> {
+    (!value.is_empty()).then_some(value)
 }
 
-#[get("/Film")]
-pub async fn search_movies(info: web::Query<SearchInfo>) -> Result<HttpResponse, ServiceError> {
-    let client = awc::Client::default();
-
-    let mut params = vec![
-        (API_PARAM, TMDB_API_KEY.to_string()),
-        (QUERY_PARAM, info.query.to_string()),
-    ];
-
-    if let Some(page) = &info.page {
-        params.push((PAGE_PARAM, page.to_string()));
-    }
-    if let Some(lang) = &info.lang {
-        params.push((LANG_PARAM, lang.to_string()));
-    }
-    if let Some(year) = &info.year {
-        params.push((FILM_YEAR_PARAM, year.to_string()));
+impl From<MultiResult> for SearchResult {
+    fn from(result: MultiResult) -> Self {
+        match result {
+            MultiResult::Movie(movie) => SearchResult::Film(Summary {
+                id: movie.id,
+                title: movie.title,
+                original_title: Some(movie.original_title),
+                date: non_empty(movie.release_date),
+                overview: non_empty(movie.overview),
+                image_path: movie.poster_path,
+                reviewed: None,
+            }),
+            MultiResult::Tv(show) => SearchResult::Show(Summary {
+                id: show.id,
+                title: show.name,
+                original_title: Some(show.original_name),
+                date: non_empty(show.first_air_date),
+                overview: non_empty(show.overview),
+                image_path: show.poster_path,
+                reviewed: None,
+            }),
+            MultiResult::Person(person) => SearchResult::Person(Summary {
+                id: person.id,
+                title: person.name,
+                original_title: None,
+                date: None,
+                overview: person.known_for_department,
+                image_path: person.profile_path,
+                reviewed: None,
+            }),
+        }
     }
+}
 
-    let Ok(path_query) = serde_urlencoded::to_string(params) else {
-        return Err(ServiceError::new(400, "Invalid search params"));
-    };
+/// Searches films, shows and people at once.
+#[utoipa::path(
+    get,
+    path = "/search",
+    tag = "search",
+    params(SearchInfo),
+    security((), ("session" = [])),
+    responses(
+        (status = 200, description = "A page of results, with whether you've reviewed each title when logged in", body = SearchResults),
+        (status = 414, description = "`query_too_long`", body = Problem),
+        (status = 422, description = "`validation_failed`", body = Problem),
+        (status = 502, description = "`tmdb_unavailable` or `tmdb_bad_response`", body = Problem),
+        (status = 503, description = "`tmdb_rate_limited`", body = Problem),
+        (status = 504, description = "`tmdb_timeout`", body = Problem),
+    ),
+)]
+pub async fn search_all(
+    pool: web::Data<Pool>,
+    tmdb: web::Data<TmdbClient>,
+    user_id: Option<UserId>,
+    info: ValidQuery<SearchInfo>,
+) -> Result<HttpResponse, ServiceError> {
+    let body = tmdb.search_multi(&info).await?;
+
+    let cacheable = body
+        .results
+        .iter()
+        .filter_map(|result| match result {
+            MultiResult::Movie(movie) => Some(NewMedia::from(movie)),
+            MultiResult::Tv(show) => Some(NewMedia::from(show)),
+            MultiResult::Person(_) => None,
+        })
+        .collect();
+    media::cache_search_results(&pool, info.lang.as_deref(), cacheable);
+
+    let mut results: Vec<SearchResult> = body.results.into_iter().map(SearchResult::from).collect();
+
+    if let Some(user_id) = user_id {
+        let keys: Vec<_> = results
+            .iter()
+            .filter_map(|result| match result {
+                SearchResult::Film(film) => Some((film.id, MediaCategory::Film)),
+                SearchResult::Show(show) => Some((show.id, MediaCategory::Show)),
+                SearchResult::Person(_) => None,
+            })
+            .collect();
+
+        let reviewed = block(move || {
+            let mut conn = pool.get()?;
+            find_reviewed(&mut conn, i32::from(user_id), &keys)
+        })
+        .await??;
+
+        for result in &mut results {
+            match result {
+                SearchResult::Film(film) => {
+                    film.reviewed = Some(reviewed.contains(&(film.id, MediaCategory::Film)))
+                }
+                SearchResult::Show(show) => {
+                    show.reviewed = Some(reviewed.contains(&(show.id, MediaCategory::Show)))
+                }
+                SearchResult::Person(_) => {}
+            }
+        }
+    }
 
-    let req = client.get(SEARCH_FILM_BASE.to_owned() + &path_query);
+    Ok(HttpResponse::Ok().json(Results {
+        results,
+        page: body.page,
+        total_pages: body.total_pages,
+        total_results: body.total_results,
+    }))
+}
 
-    let mut res = req.send().await?;
+/// Searches films, falling back to titles seen before when TMDB is down.
+#[utoipa::path(
+    get,
+    path = "/search/Film",
+    tag = "search",
+    params(SearchInfo),
+    responses(
+        (status = 200, description = "A page of films", body = FilmResults),
+        (status = 414, description = "`query_too_long`", body = Problem),
+        (status = 422, description = "`validation_failed`", body = Problem),
+        (status = 502, description = "`tmdb_unavailable` or `tmdb_bad_response`, when nothing cached matches", body = Problem),
+        (status = 503, description = "`tmdb_rate_limited`", body = Problem),
+        (status = 504, description = "`tmdb_timeout`", body = Problem),
+    ),
+)]
+pub async fn search_movies(
+    pool: web::Data<Pool>,
+    tmdb: web::Data<TmdbClient>,
+    info: ValidQuery<SearchInfo>,
+) -> Result<HttpResponse, ServiceError> {
+    let body = match tmdb.search_movies(&info).await {
+        Ok(body) => body,
+        Err(e) => match media::search_cached::<Movie>(&pool, MediaCategory::Film, &info).await? {
+            Some(cached) => return Ok(HttpResponse::Ok().json(cached)),
+            None => return Err(e),
+        },
+    };
 
-    let body = res.json::<Results<Movie>>().await?;
+    media::cache_search_results(&pool, info.lang.as_deref(), cacheable(&body));
 
     Ok(HttpResponse::Ok().json(body))
 }
 
-#[get("/Show")]
-pub async fn search_shows(info: web::Query<SearchInfo>) -> Result<HttpResponse, ServiceError> {
-    let client = awc::Client::default();
-
-    let mut params = vec![
-        (API_PARAM, TMDB_API_KEY.to_string()),
-        (QUERY_PARAM, info.query.to_string()),
-    ];
-
-    if let Some(page) = &info.page {
-        params.push((PAGE_PARAM, page.to_string()));
-    }
-    if let Some(lang) = &info.lang {
-        params.push((LANG_PARAM, lang.to_string()));
-    }
-    if let Some(year) = &info.year {
-        params.push((SHOW_YEAR_PARAM, year.to_string()));
-    }
-
-    let Ok(path_query) = serde_urlencoded::to_string(params) else {
-        return Err(ServiceError::new(400, "Invalid search params"));
+/// Searches shows, falling back to titles seen before when TMDB is down.
+#[utoipa::path(
+    get,
+    path = "/search/Show",
+    tag = "search",
+    params(SearchInfo),
+    responses(
+        (status = 200, description = "A page of shows", body = ShowResults),
+        (status = 414, description = "`query_too_long`", body = Problem),
+        (status = 422, description = "`validation_failed`", body = Problem),
+        (status = 502, description = "`tmdb_unavailable` or `tmdb_bad_response`, when nothing cached matches", body = Problem),
+        (status = 503, description = "`tmdb_rate_limited`", body = Problem),
+        (status = 504, description = "`tmdb_timeout`", body = Problem),
+    ),
+)]
+pub async fn search_shows(
+    pool: web::Data<Pool>,
+    tmdb: web::Data<TmdbClient>,
+    info: ValidQuery<SearchInfo>,
+) -> Result<HttpResponse, ServiceError> {
+    let body = match tmdb.search_shows(&info).await {
+        Ok(body) => body,
+        Err(e) => match media::search_cached::<Show>(&pool, MediaCategory::Show, &info).await? {
+            Some(cached) => return Ok(HttpResponse::Ok().json(cached)),
+            None => return Err(e),
+        },
     };
 
-    let req = client.get(SEARCH_SHOW_BASE.to_owned() + &path_query);
-
-    let mut res = req.send().await?;
-
-    let body = res.json::<Results<Show>>().await?;
+    media::cache_search_results(&pool, info.lang.as_deref(), cacheable(&body));
 
     Ok(HttpResponse::Ok().json(body))
 }
+
+fn cacheable<'a, T>(body: &'a Results<T>) -> Vec<NewMedia>
+where
+    NewMedia: From<&'a T>,
+{
+    body.results.iter().map(NewMedia::from).collect()
+}
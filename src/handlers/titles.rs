@@ -0,0 +1,154 @@
+use actix_web::{web, HttpResponse};
+use serde::Deserialize;
+use utoipa::IntoParams;
+use validator::Validate;
+
+use crate::{
+    actions::reviews::{get_review_stats, ReviewStats, WithStats},
+    errors::ServiceError,
+    handlers::auth::UserId,
+    media,
+    models::{MediaCategory, NewMedia},
+    tmdb::TmdbClient,
+    utils::block,
+    validation::ValidQuery,
+    Pool,
+};
+
+#[derive(Deserialize, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct LangQuery {
+    /// e.g. `en-US`
+    #[validate(length(min = 2, max = 10))]
+    #[param(min_length = 2, max_length = 10)]
+    lang: Option<String>,
+}
+
+async fn stats(
+    pool: web::Data<Pool>,
+    viewer: Option<UserId>,
+    tmdb_id: i32,
+    category: MediaCategory,
+    season: Option<i32>,
+) -> Result<ReviewStats, ServiceError> {
+    let stats = block(move || {
+        let mut conn = pool.get()?;
+        get_review_stats(&mut conn, viewer.map(i32::from), tmdb_id, category, season)
+    })
+    .await??;
+
+    Ok(stats)
+}
+
+/// A film's details from TMDB, with stats from the reviews you can see.
+#[utoipa::path(
+    get,
+    path = "/media/Film/{id}",
+    tag = "media",
+    params(("id" = i32, Path, description = "TMDB id"), LangQuery),
+    security((), ("session" = [])),
+    responses(
+        (status = 200, description = "The film", body = FilmDetailsWithStats),
+        (status = 404, description = "`title_not_found`", body = Problem),
+        (status = 422, description = "`validation_failed`", body = Problem),
+        (status = 422, description = "`validation_failed`", body = Problem),
+        (status = 422, description = "`validation_failed`", body = Problem),
+        (status = 502, description = "`tmdb_unavailable` or `tmdb_bad_response`", body = Problem),
+        (status = 503, description = "`tmdb_rate_limited`", body = Problem),
+        (status = 504, description = "`tmdb_timeout`", body = Problem),
+    ),
+)]
+pub async fn get_film(
+    pool: web::Data<Pool>,
+    tmdb: web::Data<TmdbClient>,
+    viewer: Option<UserId>,
+    path: web::Path<i32>,
+    query: ValidQuery<LangQuery>,
+) -> Result<HttpResponse, ServiceError> {
+    let tmdb_id = path.into_inner();
+
+    let details = tmdb.get_movie(tmdb_id, query.lang.as_deref()).await?;
+    media::cache_details(&pool, query.lang.as_deref(), NewMedia::from(&details));
+
+    let stats = stats(pool, viewer, tmdb_id, MediaCategory::Film, None).await?;
+
+    Ok(HttpResponse::Ok().json(WithStats {
+        item: details,
+        stats: Some(stats),
+    }))
+}
+
+/// A show's details from TMDB, with stats from the reviews you can see.
+#[utoipa::path(
+    get,
+    path = "/media/Show/{id}",
+    tag = "media",
+    params(("id" = i32, Path, description = "TMDB id"), LangQuery),
+    security((), ("session" = [])),
+    responses(
+        (status = 200, description = "The show", body = ShowDetailsWithStats),
+        (status = 404, description = "`title_not_found`", body = Problem),
+        (status = 502, description = "`tmdb_unavailable` or `tmdb_bad_response`", body = Problem),
+        (status = 503, description = "`tmdb_rate_limited`", body = Problem),
+        (status = 504, description = "`tmdb_timeout`", body = Problem),
+    ),
+)]
+pub async fn get_show(
+    pool: web::Data<Pool>,
+    tmdb: web::Data<TmdbClient>,
+    viewer: Option<UserId>,
+    path: web::Path<i32>,
+    query: ValidQuery<LangQuery>,
+) -> Result<HttpResponse, ServiceError> {
+    let tmdb_id = path.into_inner();
+
+    let details = tmdb.get_show(tmdb_id, query.lang.as_deref()).await?;
+    media::cache_details(&pool, query.lang.as_deref(), NewMedia::from(&details));
+
+    let stats = stats(pool, viewer, tmdb_id, MediaCategory::Show, None).await?;
+
+    Ok(HttpResponse::Ok().json(WithStats {
+        item: details,
+        stats: Some(stats),
+    }))
+}
+
+/// A season's episodes from TMDB, with stats from the reviews of that season you can see.
+#[utoipa::path(
+    get,
+    path = "/media/Show/{id}/seasons/{season}",
+    tag = "media",
+    params(
+        ("id" = i32, Path, description = "TMDB id"),
+        ("season" = i32, Path, description = "Season number, 0 for specials"),
+        LangQuery,
+    ),
+    security((), ("session" = [])),
+    responses(
+        (status = 200, description = "The season", body = SeasonWithStats),
+        (status = 404, description = "`title_not_found`", body = Problem),
+        (status = 502, description = "`tmdb_unavailable` or `tmdb_bad_response`", body = Problem),
+        (status = 503, description = "`tmdb_rate_limited`", body = Problem),
+        (status = 504, description = "`tmdb_timeout`", body = Problem),
+    ),
+)]
+pub async fn get_show_season(
+    pool: web::Data<Pool>,
+    tmdb: web::Data<TmdbClient>,
+    viewer: Option<UserId>,
+    path: web::Path<(i32, i32)>,
+    query: ValidQuery<LangQuery>,
+) -> Result<HttpResponse, ServiceError> {
+    let (tmdb_id, season) = path.into_inner();
+
+    let details = tmdb
+        .get_season(tmdb_id, season, query.lang.as_deref())
+        .await?;
+
+    let stats = stats(pool, viewer, tmdb_id, MediaCategory::Show, Some(season)).await?;
+
+    Ok(HttpResponse::Ok().json(WithStats {
+        item: details,
+        stats: Some(stats),
+    }))
+}
@@ -1,86 +1,150 @@
+use crate::actions::blocks::{
+    block_user, get_blocks, get_mutes, mute_user, unblock_user, unmute_user, BlocksQuery,
+};
+use crate::actions::follows::{
+    accept_follower, follow_user, get_followers, unfollow_user, FollowsQuery,
+};
+use crate::actions::recommendations::get_recommendations;
+use crate::actions::reports::{report_user, InputReport};
 use crate::actions::users::{
     create_user, delete_user_by_id, find_user_by_id, get_all_users, update_auth_user_by_id,
     InputUser, QueryParams, UpdateUser,
 };
-use crate::handlers::auth::UserId;
+use crate::handlers::{auth::UserId, Deleted};
 
+use crate::config::Config;
 use crate::errors::ServiceError;
+use crate::export::{export_reviews, ExportFormat};
+use crate::pagination::PageQuery;
+use crate::utils::block;
+use crate::validation::{ValidJson, ValidQuery};
 use crate::Pool;
 
-use actix_web::{delete, get, patch, post, web, HttpResponse};
-use serde_json::json;
+use actix_web::http::header::{ContentDisposition, DispositionParam, DispositionType};
+use actix_web::{web, HttpResponse};
+use serde::Deserialize;
+use utoipa::IntoParams;
+use validator::Validate;
 
-#[get("")]
+/// Lists the profiles you can see.
+#[utoipa::path(
+    get,
+    path = "/users",
+    tag = "users",
+    params(QueryParams),
+    security((), ("session" = [])),
+    responses((status = 200, description = "A page of users", body = PaginatedUsers)),
+)]
 pub async fn get_users(
     pool: web::Data<Pool>,
-    query: web::Query<QueryParams>,
+    viewer: Option<UserId>,
+    query: ValidQuery<QueryParams>,
 ) -> Result<HttpResponse, ServiceError> {
-    let users = web::block(move || {
+    let users = block(move || {
         let mut conn = pool.get()?;
-        get_all_users(&mut conn, query.into_inner())
+        get_all_users(&mut conn, viewer.map(i32::from), query.into_inner())
     })
     .await??;
 
     Ok(HttpResponse::Ok().json(users))
 }
 
-#[get("{id}")]
+/// A profile, if you can see it.
+#[utoipa::path(
+    get,
+    path = "/users/{id}",
+    tag = "users",
+    params(("id" = i32, Path, description = "User id")),
+    security((), ("session" = [])),
+    responses(
+        (status = 200, description = "The user", body = User),
+        (status = 404, description = "`user_not_found`, including profiles you can't see", body = Problem),
+        (status = 422, description = "`validation_failed`", body = Problem),
+        (status = 422, description = "`validation_failed`", body = Problem),
+        (status = 422, description = "`validation_failed`", body = Problem),
+        (status = 422, description = "`validation_failed`", body = Problem),
+    ),
+)]
 pub async fn get_users_id(
     pool: web::Data<Pool>,
+    viewer: Option<UserId>,
     id: web::Path<i32>,
 ) -> Result<HttpResponse, ServiceError> {
-    let user = web::block(move || {
+    let user = block(move || {
         let mut conn = pool.get()?;
-        find_user_by_id(&mut conn, id.into_inner())
+        find_user_by_id(&mut conn, viewer.map(i32::from), id.into_inner())
     })
     .await??;
 
     let Some(user) = user else {
-        return Err(ServiceError::pls(404));
+        return Err(ServiceError::new(404, "user_not_found", "No such user"));
     };
 
     Ok(HttpResponse::Ok().json(user))
 }
 
-#[patch("{id}")]
+/// Updates your own profile, or anyone's as an admin.
+#[utoipa::path(
+    patch,
+    path = "/users/{id}",
+    tag = "users",
+    params(("id" = i32, Path, description = "User id")),
+    request_body = UpdateUser,
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "The updated user", body = AuthenticatedUser),
+        (status = 403, description = "`forbidden`", body = Problem),
+        (status = 404, description = "`user_not_found`", body = Problem),
+        (status = 409, description = "`email_taken`", body = Problem),
+        (status = 422, description = "`validation_failed`", body = Problem),
+    ),
+)]
 pub async fn patch_users_id(
     pool: web::Data<Pool>,
     path_id: web::Path<i32>,
     user_id: UserId,
-    update: web::Json<UpdateUser>,
+    update: ValidJson<UpdateUser>,
 ) -> Result<HttpResponse, ServiceError> {
-    // Temp until implement privileged users
-    let user_id = i32::from(user_id);
     let path_id = path_id.into_inner();
 
-    if user_id != path_id && user_id != 1 {
-        return Err(ServiceError::pls(401));
+    if i32::from(user_id) != path_id && !user_id.is_admin() {
+        return Err(ServiceError::new(403, "forbidden", "That's not yours"));
     }
 
-    let user = web::block(move || {
+    let user = block(move || {
         let mut conn = pool.get()?;
         update_auth_user_by_id(&mut conn, path_id, update.into_inner())
     })
-    .await??;
+    .await?
+    .map_err(|e| ServiceError::from(e).not_found_as("user_not_found", "No such user"))?;
 
     Ok(HttpResponse::Ok().json(user))
 }
 
-#[delete("{id}")]
+/// Deletes your own account, or anyone's as an admin.
+#[utoipa::path(
+    delete,
+    path = "/users/{id}",
+    tag = "users",
+    params(("id" = i32, Path, description = "User id")),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Whether the user was deleted", body = Deleted),
+        (status = 403, description = "`forbidden`", body = Problem),
+    ),
+)]
 pub async fn delete_users_id(
     pool: web::Data<Pool>,
     path_id: web::Path<i32>,
     user_id: UserId,
 ) -> Result<HttpResponse, ServiceError> {
-    // Temp until implement privileged users
-    let user_id = i32::from(user_id);
     let path_id = path_id.into_inner();
 
-    if user_id != path_id && user_id != 1 {
-        return Err(ServiceError::pls(401));
+    if i32::from(user_id) != path_id && !user_id.is_admin() {
+        return Err(ServiceError::new(403, "forbidden", "That's not yours"));
     }
 
-    let deleted = web::block(move || {
+    let deleted = block(move || {
         let mut conn = pool.get()?;
         delete_user_by_id(&mut conn, path_id)
     })
@@ -89,19 +153,483 @@ pub async fn delete_users_id(
     // TODO deleting users doesn't remove session data or associated reviews
     // rn auth only protect user's routes, but needs to be addressed
 
-    Ok(HttpResponse::Ok().json(json!({ "deleted": deleted })))
+    Ok(HttpResponse::Ok().json(Deleted { deleted }))
 }
 
-#[post("")]
+/// Signs up.
+#[utoipa::path(
+    post,
+    path = "/users",
+    tag = "users",
+    request_body = InputUser,
+    responses(
+        (status = 200, description = "The new user", body = User),
+        (status = 409, description = "`email_taken`", body = Problem),
+        (status = 422, description = "`validation_failed`", body = Problem),
+    ),
+)]
 pub async fn post_users(
     pool: web::Data<Pool>,
-    user: web::Json<InputUser>,
+    config: web::Data<Config>,
+    user: ValidJson<InputUser>,
 ) -> Result<HttpResponse, ServiceError> {
-    let user = web::block(move || {
+    let user = block(move || {
         let mut conn = pool.get()?;
-        create_user(&mut conn, user.into_inner())
+        create_user(
+            &mut conn,
+            user.into_inner(),
+            config.auth.secret_key.expose(),
+        )
     })
     .await??;
 
     Ok(HttpResponse::Ok().json(user))
 }
+
+/// Follows a user, or asks to if their profile is private.
+#[utoipa::path(
+    post,
+    path = "/users/{id}/follow",
+    tag = "users",
+    params(("id" = i32, Path, description = "User id")),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "The follow, not yet `accepted` for private profiles", body = Follow),
+        (status = 400, description = "`self_follow`", body = Problem),
+        (status = 404, description = "`user_not_found` or `not_found`", body = Problem),
+    ),
+)]
+pub async fn post_users_id_follow(
+    pool: web::Data<Pool>,
+    path_id: web::Path<i32>,
+    user_id: UserId,
+) -> Result<HttpResponse, ServiceError> {
+    let follow = block(move || {
+        let mut conn = pool.get()?;
+        follow_user(&mut conn, user_id.into(), path_id.into_inner())
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(follow))
+}
+
+/// Unfollows a user, or cancels a request to.
+#[utoipa::path(
+    delete,
+    path = "/users/{id}/follow",
+    tag = "users",
+    params(("id" = i32, Path, description = "User id")),
+    security(("session" = [])),
+    responses((status = 200, description = "Whether a follow was removed", body = Deleted)),
+)]
+pub async fn delete_users_id_follow(
+    pool: web::Data<Pool>,
+    path_id: web::Path<i32>,
+    user_id: UserId,
+) -> Result<HttpResponse, ServiceError> {
+    let deleted = block(move || {
+        let mut conn = pool.get()?;
+        unfollow_user(&mut conn, user_id.into(), path_id.into_inner())
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(Deleted { deleted }))
+}
+
+/// A user's followers, and their pending requests for the user themselves.
+#[utoipa::path(
+    get,
+    path = "/users/{id}/followers",
+    tag = "users",
+    params(("id" = i32, Path, description = "User id"), FollowsQuery),
+    security((), ("session" = [])),
+    responses(
+        (status = 200, description = "A page of follows", body = PaginatedFollows),
+        (status = 404, description = "`user_not_found`", body = Problem),
+    ),
+)]
+pub async fn get_users_id_followers(
+    pool: web::Data<Pool>,
+    path_id: web::Path<i32>,
+    viewer: Option<UserId>,
+    query: ValidQuery<FollowsQuery>,
+) -> Result<HttpResponse, ServiceError> {
+    let viewer = viewer.map(i32::from);
+    let path_id = path_id.into_inner();
+
+    let followers = block(move || {
+        let mut conn = pool.get()?;
+        if find_user_by_id(&mut conn, viewer, path_id)?.is_none() {
+            return Err(ServiceError::new(404, "user_not_found", "No such user"));
+        }
+        Ok(get_followers(
+            &mut conn,
+            viewer,
+            path_id,
+            query.into_inner(),
+        )?)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(followers))
+}
+
+/// Accepts a request to follow your private profile.
+#[utoipa::path(
+    put,
+    path = "/users/{id}/followers/{follower_id}",
+    tag = "users",
+    params(("id" = i32, Path, description = "User id"), ("follower_id" = i32, Path, description = "Who asked to follow")),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "The accepted follow", body = Follow),
+        (status = 403, description = "`forbidden`", body = Problem),
+        (status = 404, description = "`not_found`, there's no such request", body = Problem),
+    ),
+)]
+pub async fn put_users_id_followers(
+    pool: web::Data<Pool>,
+    path: web::Path<(i32, i32)>,
+    user_id: UserId,
+) -> Result<HttpResponse, ServiceError> {
+    let (path_id, follower_id) = path.into_inner();
+
+    if i32::from(user_id) != path_id {
+        return Err(ServiceError::new(403, "forbidden", "That's not yours"));
+    }
+
+    let follow = block(move || {
+        let mut conn = pool.get()?;
+        accept_follower(&mut conn, path_id, follower_id)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(follow))
+}
+
+/// Removes a follower, or declines their request.
+#[utoipa::path(
+    delete,
+    path = "/users/{id}/followers/{follower_id}",
+    tag = "users",
+    params(("id" = i32, Path, description = "User id"), ("follower_id" = i32, Path, description = "Who asked to follow")),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Whether a follow was removed", body = Deleted),
+        (status = 403, description = "`forbidden`", body = Problem),
+    ),
+)]
+pub async fn delete_users_id_followers(
+    pool: web::Data<Pool>,
+    path: web::Path<(i32, i32)>,
+    user_id: UserId,
+) -> Result<HttpResponse, ServiceError> {
+    let (path_id, follower_id) = path.into_inner();
+
+    if i32::from(user_id) != path_id {
+        return Err(ServiceError::new(403, "forbidden", "That's not yours"));
+    }
+
+    let deleted = block(move || {
+        let mut conn = pool.get()?;
+        unfollow_user(&mut conn, follower_id, path_id)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(Deleted { deleted }))
+}
+
+/// Reports a user to the admins.
+#[utoipa::path(
+    post,
+    path = "/users/{id}/report",
+    tag = "users",
+    params(("id" = i32, Path, description = "User id")),
+    request_body = InputReport,
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "The report", body = Report),
+        (status = 400, description = "`self_report`", body = Problem),
+        (status = 404, description = "`user_not_found` or `not_found`", body = Problem),
+        (status = 422, description = "`validation_failed`", body = Problem),
+    ),
+)]
+pub async fn post_users_id_report(
+    pool: web::Data<Pool>,
+    path_id: web::Path<i32>,
+    user_id: UserId,
+    input_report: ValidJson<InputReport>,
+) -> Result<HttpResponse, ServiceError> {
+    let report = block(move || {
+        let mut conn = pool.get()?;
+        report_user(
+            &mut conn,
+            user_id.into(),
+            path_id.into_inner(),
+            input_report.into_inner(),
+        )
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(report))
+}
+
+/// Blocks a user, removing any follows between you.
+#[utoipa::path(
+    post,
+    path = "/users/{id}/block",
+    tag = "users",
+    params(("id" = i32, Path, description = "User id")),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "The block", body = Block),
+        (status = 400, description = "`self_block`", body = Problem),
+        (status = 404, description = "`user_not_found`", body = Problem),
+    ),
+)]
+pub async fn post_users_id_block(
+    pool: web::Data<Pool>,
+    path_id: web::Path<i32>,
+    user_id: UserId,
+) -> Result<HttpResponse, ServiceError> {
+    let block = block(move || {
+        let mut conn = pool.get()?;
+        block_user(&mut conn, user_id.into(), path_id.into_inner())
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(block))
+}
+
+/// Unblocks a user.
+#[utoipa::path(
+    delete,
+    path = "/users/{id}/block",
+    tag = "users",
+    params(("id" = i32, Path, description = "User id")),
+    security(("session" = [])),
+    responses((status = 200, description = "Whether a block was removed", body = Deleted)),
+)]
+pub async fn delete_users_id_block(
+    pool: web::Data<Pool>,
+    path_id: web::Path<i32>,
+    user_id: UserId,
+) -> Result<HttpResponse, ServiceError> {
+    let deleted = block(move || {
+        let mut conn = pool.get()?;
+        unblock_user(&mut conn, user_id.into(), path_id.into_inner())
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(Deleted { deleted }))
+}
+
+/// Who you've blocked.
+#[utoipa::path(
+    get,
+    path = "/users/{id}/blocks",
+    tag = "users",
+    params(("id" = i32, Path, description = "User id"), BlocksQuery),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "A page of blocks", body = PaginatedBlocks),
+        (status = 403, description = "`forbidden`", body = Problem),
+    ),
+)]
+pub async fn get_users_id_blocks(
+    pool: web::Data<Pool>,
+    path_id: web::Path<i32>,
+    user_id: UserId,
+    query: ValidQuery<BlocksQuery>,
+) -> Result<HttpResponse, ServiceError> {
+    let path_id = path_id.into_inner();
+
+    if i32::from(user_id) != path_id {
+        return Err(ServiceError::new(403, "forbidden", "That's not yours"));
+    }
+
+    let blocks = block(move || {
+        let mut conn = pool.get()?;
+        get_blocks(&mut conn, path_id, query.into_inner())
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(blocks))
+}
+
+/// Mutes a user, hiding their reviews from your lists.
+#[utoipa::path(
+    post,
+    path = "/users/{id}/mute",
+    tag = "users",
+    params(("id" = i32, Path, description = "User id")),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "The mute", body = Mute),
+        (status = 400, description = "`self_mute`", body = Problem),
+        (status = 404, description = "`user_not_found`", body = Problem),
+    ),
+)]
+pub async fn post_users_id_mute(
+    pool: web::Data<Pool>,
+    path_id: web::Path<i32>,
+    user_id: UserId,
+) -> Result<HttpResponse, ServiceError> {
+    let mute = block(move || {
+        let mut conn = pool.get()?;
+        mute_user(&mut conn, user_id.into(), path_id.into_inner())
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(mute))
+}
+
+/// Unmutes a user.
+#[utoipa::path(
+    delete,
+    path = "/users/{id}/mute",
+    tag = "users",
+    params(("id" = i32, Path, description = "User id")),
+    security(("session" = [])),
+    responses((status = 200, description = "Whether a mute was removed", body = Deleted)),
+)]
+pub async fn delete_users_id_mute(
+    pool: web::Data<Pool>,
+    path_id: web::Path<i32>,
+    user_id: UserId,
+) -> Result<HttpResponse, ServiceError> {
+    let deleted = block(move || {
+        let mut conn = pool.get()?;
+        unmute_user(&mut conn, user_id.into(), path_id.into_inner())
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(Deleted { deleted }))
+}
+
+/// Who you've muted.
+#[utoipa::path(
+    get,
+    path = "/users/{id}/mutes",
+    tag = "users",
+    params(("id" = i32, Path, description = "User id"), BlocksQuery),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "A page of mutes", body = PaginatedMutes),
+        (status = 403, description = "`forbidden`", body = Problem),
+    ),
+)]
+pub async fn get_users_id_mutes(
+    pool: web::Data<Pool>,
+    path_id: web::Path<i32>,
+    user_id: UserId,
+    query: ValidQuery<BlocksQuery>,
+) -> Result<HttpResponse, ServiceError> {
+    let path_id = path_id.into_inner();
+
+    if i32::from(user_id) != path_id {
+        return Err(ServiceError::new(403, "forbidden", "That's not yours"));
+    }
+
+    let mutes = block(move || {
+        let mut conn = pool.get()?;
+        get_mutes(&mut conn, path_id, query.into_inner())
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(mutes))
+}
+
+#[derive(Deserialize, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ExportQuery {
+    /// `json` by default, `letterboxd` can be imported there
+    #[param(inline)]
+    format: Option<ExportFormat>,
+}
+
+/// Downloads all of your reviews, or anyone's as an admin.
+#[utoipa::path(
+    get,
+    path = "/users/{id}/export",
+    tag = "users",
+    params(("id" = i32, Path, description = "User id"), ExportQuery),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "An attachment, JSON for `json` and CSV for the rest",
+            content_type = ["application/json", "text/csv"]),
+        (status = 403, description = "`forbidden`", body = Problem),
+        (status = 404, description = "`user_not_found`", body = Problem),
+    ),
+)]
+pub async fn get_users_id_export(
+    pool: web::Data<Pool>,
+    path_id: web::Path<i32>,
+    user_id: UserId,
+    query: ValidQuery<ExportQuery>,
+) -> Result<HttpResponse, ServiceError> {
+    let path_id = path_id.into_inner();
+
+    if i32::from(user_id) != path_id && !user_id.is_admin() {
+        return Err(ServiceError::new(403, "forbidden", "That's not yours"));
+    }
+
+    let format = query.format.unwrap_or(ExportFormat::Json);
+
+    let user_pool = pool.clone();
+    let user = block(move || {
+        let mut conn = user_pool.get()?;
+        // Looked up as the user themselves, since only they or an admin can get here
+        find_user_by_id(&mut conn, Some(path_id), path_id)
+    })
+    .await??;
+
+    let Some(user) = user else {
+        return Err(ServiceError::new(404, "user_not_found", "No such user"));
+    };
+
+    let body = export_reviews(pool.get_ref().clone(), user, format)?;
+
+    let disposition = ContentDisposition {
+        disposition: DispositionType::Attachment,
+        parameters: vec![DispositionParam::Filename(format.file_name().into())],
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type(format.content_type())
+        .insert_header(disposition)
+        .streaming(body))
+}
+
+/// Titles you might like, from reviews like yours and TMDB's similar titles.
+#[utoipa::path(
+    get,
+    path = "/users/{id}/recommendations",
+    tag = "users",
+    params(("id" = i32, Path, description = "User id"), PageQuery),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "A page of recommendations, best first", body = PaginatedRecommendations),
+        (status = 403, description = "`forbidden`", body = Problem),
+    ),
+)]
+pub async fn get_users_id_recommendations(
+    pool: web::Data<Pool>,
+    path_id: web::Path<i32>,
+    user_id: UserId,
+    query: ValidQuery<PageQuery>,
+) -> Result<HttpResponse, ServiceError> {
+    let path_id = path_id.into_inner();
+
+    if i32::from(user_id) != path_id && !user_id.is_admin() {
+        return Err(ServiceError::new(403, "forbidden", "That's not yours"));
+    }
+
+    let recommendations = block(move || {
+        let mut conn = pool.get()?;
+        get_recommendations(&mut conn, path_id, query.page, query.per_page)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(recommendations))
+}
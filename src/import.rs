@@ -0,0 +1,744 @@
+// Watch history exports from other services, and the background job importing them as reviews
+
+use std::{
+    collections::HashSet,
+    time::{Duration, Instant},
+};
+
+use actix_web::web;
+use serde::Deserialize;
+use validator::Validate;
+
+use crate::{
+    actions::{
+        imports::{import_review, update_import_job, ImportProgress},
+        reviews::InputReview,
+    },
+    errors::ServiceError,
+    media,
+    models::{ImportSource, JobStatus, MediaCategory, ReviewVisibility, WatchStatus},
+    tmdb::{SearchInfo, TmdbClient},
+    utils::block,
+    Pool,
+};
+
+// How many entries are processed between progress updates
+const PROGRESS_INTERVAL: i32 = 25;
+
+// Progress is also saved this often, however slowly entries go, so running jobs never look
+// stale
+const PROGRESS_EVERY: Duration = Duration::from_secs(30);
+
+/// How long a job can go without saving progress before it's assumed to have died with its
+/// instance. Well above `PROGRESS_EVERY` plus the slowest TMDB lookup with retries.
+pub const STALE_AFTER_MINUTES: i64 = 5;
+
+#[derive(Debug)]
+pub struct ImportEntry {
+    pub tmdb_id: Option<i32>,
+    pub title: String,
+    pub year: Option<i32>,
+    pub category: MediaCategory,
+    pub season: Option<i32>,
+    pub status: WatchStatus,
+    pub text: String,
+    pub fun_before: bool,
+    pub fun_during: bool,
+    pub fun_after: bool,
+    pub visibility: ReviewVisibility,
+}
+
+impl ImportEntry {
+    // How unmatched entries are reported back
+    fn label(&self) -> String {
+        let mut label = self.title.clone();
+        if let Some(year) = self.year {
+            label += &format!(" ({})", year);
+        }
+        if let Some(season) = self.season {
+            label += &format!(" season {}", season);
+        }
+        label
+    }
+}
+
+/// Parses an export into entries, dropping duplicates. `status` overrides the status
+/// of every entry, e.g. for a Letterboxd `watchlist.csv`.
+pub fn parse(
+    source: ImportSource,
+    body: &[u8],
+    status: Option<WatchStatus>,
+) -> Result<Vec<ImportEntry>, ServiceError> {
+    let mut entries = match source {
+        ImportSource::Letterboxd => parse_letterboxd(body)?,
+        ImportSource::Trakt => parse_trakt(body)?,
+        ImportSource::MyAnimeList => parse_mal(body)?,
+        ImportSource::ReviewApi => parse_review_api(body)?,
+    };
+
+    if let Some(status) = status {
+        for entry in entries.iter_mut() {
+            entry.status = status;
+        }
+    }
+
+    let mut seen = HashSet::new();
+    entries.retain(|entry| {
+        seen.insert((
+            entry.tmdb_id,
+            entry.title.to_lowercase(),
+            entry.year,
+            entry.category,
+            entry.season,
+        ))
+    });
+
+    Ok(entries)
+}
+
+// Any of the csv files in a Letterboxd export: watched.csv, diary.csv, ratings.csv, reviews.csv...
+#[derive(Deserialize)]
+struct LetterboxdRow {
+    #[serde(rename = "Name")]
+    name: String,
+    #[serde(rename = "Year")]
+    year: Option<i32>,
+    #[serde(rename = "Review", default)]
+    review: Option<String>,
+}
+
+fn parse_letterboxd(body: &[u8]) -> Result<Vec<ImportEntry>, ServiceError> {
+    let mut reader = csv::Reader::from_reader(body);
+
+    reader
+        .deserialize::<LetterboxdRow>()
+        .map(|row| {
+            let row = row.map_err(|e| {
+                ServiceError::new(400, "invalid_import", format!("Invalid csv: {}", e))
+            })?;
+            Ok(ImportEntry {
+                tmdb_id: None,
+                title: row.name,
+                year: row.year,
+                category: MediaCategory::Film,
+                season: None,
+                status: WatchStatus::Completed,
+                text: row.review.unwrap_or_default(),
+                fun_before: false,
+                fun_during: false,
+                fun_after: false,
+                visibility: ReviewVisibility::Public,
+            })
+        })
+        .collect()
+}
+
+// Entries of watched-movies.json, watched-shows.json, watchlist.json or history.json
+#[derive(Deserialize)]
+struct TraktEntry {
+    movie: Option<TraktMedia>,
+    show: Option<TraktMedia>,
+    #[serde(default)]
+    seasons: Vec<TraktSeason>,
+    season: Option<TraktSeason>,
+    episode: Option<TraktEpisode>,
+    // Only set for lists like the watchlist
+    listed_at: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct TraktMedia {
+    title: String,
+    year: Option<i32>,
+    ids: TraktIds,
+}
+
+#[derive(Deserialize)]
+struct TraktIds {
+    tmdb: Option<i32>,
+}
+
+#[derive(Deserialize)]
+struct TraktSeason {
+    number: i32,
+}
+
+#[derive(Deserialize)]
+struct TraktEpisode {
+    season: i32,
+}
+
+fn parse_trakt(body: &[u8]) -> Result<Vec<ImportEntry>, ServiceError> {
+    let trakt_entries = serde_json::from_slice::<Vec<TraktEntry>>(body)
+        .map_err(|e| ServiceError::new(400, "invalid_import", format!("Invalid json: {}", e)))?;
+
+    let mut entries = Vec::new();
+
+    for trakt_entry in trakt_entries {
+        let status = if trakt_entry.listed_at.is_some() {
+            WatchStatus::PlanToWatch
+        } else {
+            WatchStatus::Completed
+        };
+
+        if let Some(movie) = trakt_entry.movie {
+            entries.push(ImportEntry {
+                tmdb_id: movie.ids.tmdb,
+                title: movie.title,
+                year: movie.year,
+                category: MediaCategory::Film,
+                season: None,
+                status,
+                text: String::new(),
+                fun_before: false,
+                fun_during: false,
+                fun_after: false,
+                visibility: ReviewVisibility::Public,
+            });
+            continue;
+        }
+
+        let Some(show) = trakt_entry.show else {
+            continue;
+        };
+
+        // A single watched episode doesn't mean the season is completed
+        let (seasons, status) = if let Some(episode) = trakt_entry.episode {
+            (vec![episode.season], WatchStatus::Watching)
+        } else if let Some(season) = trakt_entry.season {
+            (vec![season.number], status)
+        } else if !trakt_entry.seasons.is_empty() {
+            let seasons = trakt_entry.seasons.iter().map(|s| s.number).collect();
+            (seasons, status)
+        } else {
+            (vec![1], status)
+        };
+
+        for season in seasons {
+            entries.push(ImportEntry {
+                tmdb_id: show.ids.tmdb,
+                title: show.title.clone(),
+                year: show.year,
+                category: MediaCategory::Show,
+                season: Some(season),
+                status,
+                text: String::new(),
+                fun_before: false,
+                fun_during: false,
+                fun_after: false,
+                visibility: ReviewVisibility::Public,
+            });
+        }
+    }
+
+    Ok(entries)
+}
+
+#[derive(Deserialize)]
+struct MalExport {
+    #[serde(default)]
+    anime: Vec<MalAnime>,
+}
+
+#[derive(Deserialize)]
+struct MalAnime {
+    series_title: String,
+    #[serde(default)]
+    series_type: String,
+    my_status: String,
+    #[serde(default)]
+    my_comments: String,
+}
+
+fn parse_mal(body: &[u8]) -> Result<Vec<ImportEntry>, ServiceError> {
+    let Ok(body) = std::str::from_utf8(body) else {
+        return Err(ServiceError::new(
+            400,
+            "invalid_import",
+            "Invalid xml: not utf-8",
+        ));
+    };
+
+    let export = quick_xml::de::from_str::<MalExport>(body)
+        .map_err(|e| ServiceError::new(400, "invalid_import", format!("Invalid xml: {}", e)))?;
+
+    export
+        .anime
+        .into_iter()
+        .map(|anime| {
+            let status = match anime.my_status.as_str() {
+                "Completed" => WatchStatus::Completed,
+                "Watching" | "On-Hold" => WatchStatus::Watching,
+                "Dropped" => WatchStatus::Dropped,
+                "Plan to Watch" => WatchStatus::PlanToWatch,
+                other => {
+                    return Err(ServiceError::new(
+                        400,
+                        "invalid_import",
+                        format!("Unrecognized MyAnimeList status: {}", other),
+                    ))
+                }
+            };
+
+            let (category, season) = match anime.series_type.as_str() {
+                "Movie" => (MediaCategory::Film, None),
+                _ => (MediaCategory::Show, Some(1)),
+            };
+
+            Ok(ImportEntry {
+                tmdb_id: None,
+                title: anime.series_title,
+                year: None,
+                category,
+                season,
+                status,
+                text: anime.my_comments,
+                fun_before: false,
+                fun_during: false,
+                fun_after: false,
+                visibility: ReviewVisibility::Public,
+            })
+        })
+        .collect()
+}
+
+// The json export from GET /users/{id}/export
+#[derive(Deserialize)]
+struct ReviewApiExport {
+    reviews: Vec<ReviewApiReview>,
+}
+
+#[derive(Deserialize)]
+struct ReviewApiReview {
+    tmdb_id: i32,
+    category: MediaCategory,
+    season: Option<i32>,
+    status: WatchStatus,
+    #[serde(default)]
+    text: String,
+    #[serde(default)]
+    fun_before: bool,
+    #[serde(default)]
+    fun_during: bool,
+    #[serde(default)]
+    fun_after: bool,
+    visibility: Option<ReviewVisibility>,
+}
+
+fn parse_review_api(body: &[u8]) -> Result<Vec<ImportEntry>, ServiceError> {
+    let export = serde_json::from_slice::<ReviewApiExport>(body)
+        .map_err(|e| ServiceError::new(400, "invalid_import", format!("Invalid json: {}", e)))?;
+
+    let entries = export
+        .reviews
+        .into_iter()
+        .map(|review| ImportEntry {
+            tmdb_id: Some(review.tmdb_id),
+            title: review.tmdb_id.to_string(),
+            year: None,
+            category: review.category,
+            season: review.season,
+            status: review.status,
+            text: review.text,
+            fun_before: review.fun_before,
+            fun_during: review.fun_during,
+            fun_after: review.fun_after,
+            visibility: review.visibility.unwrap_or(ReviewVisibility::Public),
+        })
+        .collect();
+
+    Ok(entries)
+}
+
+/// Imports `entries` for the job's user, recording progress on the job as it goes.
+/// Meant to be spawned in the background.
+pub async fn run_import(
+    pool: Pool,
+    tmdb: web::Data<TmdbClient>,
+    job_id: i32,
+    user_id: i32,
+    dry_run: bool,
+    entries: Vec<ImportEntry>,
+) {
+    if let Err(e) = import_entries(&pool, &tmdb, job_id, user_id, dry_run, entries).await {
+        let progress = ImportProgress {
+            status: Some(JobStatus::Failed),
+            error: Some(e.to_string()),
+            ..Default::default()
+        };
+        let _ = block(move || {
+            let mut conn = pool.get()?;
+            update_import_job(&mut conn, job_id, progress)
+        })
+        .await;
+    }
+}
+
+async fn import_entries(
+    pool: &Pool,
+    tmdb: &TmdbClient,
+    job_id: i32,
+    user_id: i32,
+    dry_run: bool,
+    entries: Vec<ImportEntry>,
+) -> Result<(), ServiceError> {
+    save_progress(
+        pool,
+        job_id,
+        ImportProgress {
+            status: Some(JobStatus::Running),
+            ..Default::default()
+        },
+    )
+    .await?;
+
+    let mut processed = 0;
+    let mut imported = 0;
+    let mut skipped = 0;
+    let mut unmatched = Vec::new();
+    let mut saved_at = Instant::now();
+
+    for entry in entries {
+        let tmdb_id = match entry.tmdb_id {
+            Some(tmdb_id) => Some(tmdb_id),
+            None => resolve_tmdb_id(tmdb, &entry).await,
+        };
+
+        let rejected = match tmdb_id {
+            Some(tmdb_id) => check_entry(pool, tmdb, tmdb_id, &entry).await.err(),
+            None => None,
+        };
+
+        match (tmdb_id, rejected) {
+            (None, _) => unmatched.push(entry.label()),
+            (Some(_), Some(reason)) => unmatched.push(format!("{}: {reason}", entry.label())),
+            (Some(_), None) if dry_run => imported += 1,
+            (Some(tmdb_id), None) => {
+                let pool = pool.clone();
+                let inserted = block(move || {
+                    let mut conn = pool.get()?;
+                    import_review(&mut conn, user_id, tmdb_id, &entry)
+                })
+                .await??;
+
+                if inserted {
+                    imported += 1;
+                } else {
+                    skipped += 1;
+                }
+            }
+        }
+
+        processed += 1;
+
+        if processed % PROGRESS_INTERVAL == 0 || saved_at.elapsed() >= PROGRESS_EVERY {
+            let progress = ImportProgress {
+                processed: Some(processed),
+                imported: Some(imported),
+                skipped: Some(skipped),
+                unmatched: Some(unmatched.clone()),
+                ..Default::default()
+            };
+            save_progress(pool, job_id, progress).await?;
+            saved_at = Instant::now();
+        }
+    }
+
+    let progress = ImportProgress {
+        status: Some(JobStatus::Completed),
+        processed: Some(processed),
+        imported: Some(imported),
+        skipped: Some(skipped),
+        unmatched: Some(unmatched),
+        ..Default::default()
+    };
+    save_progress(pool, job_id, progress).await
+}
+
+async fn save_progress(
+    pool: &Pool,
+    job_id: i32,
+    progress: ImportProgress,
+) -> Result<(), ServiceError> {
+    let pool = pool.clone();
+    block(move || {
+        let mut conn = pool.get()?;
+        update_import_job(&mut conn, job_id, progress)
+    })
+    .await??;
+
+    Ok(())
+}
+
+// Why an entry can't be imported, checked like a review posted to `POST /reviews`
+async fn check_entry(
+    pool: &Pool,
+    tmdb: &TmdbClient,
+    tmdb_id: i32,
+    entry: &ImportEntry,
+) -> Result<(), String> {
+    let input = InputReview {
+        tmdb_id,
+        category: entry.category,
+        status: entry.status,
+        season: entry.season,
+        visibility: Some(entry.visibility),
+    };
+
+    let checked = match input.validate() {
+        Ok(()) => media::validate_review(pool, tmdb, tmdb_id, entry.category, entry.season).await,
+        Err(e) => Err(e.into()),
+    };
+
+    checked.map_err(|e| match e.fields.first() {
+        Some(field) => field.message.clone(),
+        None => "Couldn't be checked on TMDB".to_string(),
+    })
+}
+
+// Best guess is the top search result. Failed lookups count as unmatched rather than
+// failing the whole import.
+async fn resolve_tmdb_id(tmdb: &TmdbClient, entry: &ImportEntry) -> Option<i32> {
+    let info = SearchInfo {
+        query: entry.title.clone(),
+        page: None,
+        lang: None,
+        year: entry.year,
+    };
+
+    match entry.category {
+        MediaCategory::Film => tmdb
+            .search_movies(&info)
+            .await
+            .ok()
+            .and_then(|results| results.results.first().map(|movie| movie.id)),
+        MediaCategory::Show => tmdb
+            .search_shows(&info)
+            .await
+            .ok()
+            .and_then(|results| results.results.first().map(|show| show.id)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, ImportEntry};
+    use crate::models::{ImportSource, MediaCategory, ReviewVisibility, WatchStatus};
+
+    use MediaCategory::{Film, Show};
+    use WatchStatus::{Completed, Dropped, PlanToWatch, Watching};
+
+    // What matters about an entry, to compare against
+    type Summary<'a> = (
+        Option<i32>,
+        &'a str,
+        Option<i32>,
+        MediaCategory,
+        Option<i32>,
+        WatchStatus,
+    );
+
+    fn summaries(entries: &[ImportEntry]) -> Vec<Summary<'_>> {
+        entries
+            .iter()
+            .map(|entry| {
+                (
+                    entry.tmdb_id,
+                    entry.title.as_str(),
+                    entry.year,
+                    entry.category,
+                    entry.season,
+                    entry.status,
+                )
+            })
+            .collect()
+    }
+
+    fn parsed(source: ImportSource, body: &str) -> Vec<ImportEntry> {
+        parse(source, body.as_bytes(), None).unwrap()
+    }
+
+    fn error_code(source: ImportSource, body: &[u8]) -> (u16, &'static str) {
+        let error = parse(source, body, None).unwrap_err();
+        (error.status, error.code)
+    }
+
+    #[test]
+    fn letterboxd_rows_are_completed_films() {
+        let body = "Date,Name,Year,Letterboxd URI,Rating,Review\n\
+                    2022-01-02,Heat,1995,https://boxd.it/1,4.5,Great\n\
+                    2022-01-03,Alien,,https://boxd.it/2,,\n";
+
+        let entries = parsed(ImportSource::Letterboxd, body);
+
+        assert_eq!(
+            summaries(&entries),
+            [
+                (None, "Heat", Some(1995), Film, None, Completed),
+                (None, "Alien", None, Film, None, Completed),
+            ]
+        );
+        assert_eq!(entries[0].text, "Great");
+        assert_eq!(entries[1].text, "");
+    }
+
+    #[test]
+    fn letterboxd_files_without_reviews_parse() {
+        let body = "Date,Name,Year,Letterboxd URI\n2022-01-02,Heat,1995,https://boxd.it/1\n";
+
+        assert_eq!(
+            summaries(&parsed(ImportSource::Letterboxd, body)),
+            [(None, "Heat", Some(1995), Film, None, Completed)]
+        );
+    }
+
+    #[test]
+    fn trakt_movies_and_watchlists() {
+        let body = r#"[
+            {"watched_at": "2022-01-02T00:00:00.000Z", "movie": {"title": "Heat", "year": 1995, "ids": {"tmdb": 949}}},
+            {"listed_at": "2022-01-02T00:00:00.000Z", "movie": {"title": "Alien", "year": 1979, "ids": {"tmdb": null}}}
+        ]"#;
+
+        assert_eq!(
+            summaries(&parsed(ImportSource::Trakt, body)),
+            [
+                (Some(949), "Heat", Some(1995), Film, None, Completed),
+                (None, "Alien", Some(1979), Film, None, PlanToWatch),
+            ]
+        );
+    }
+
+    #[test]
+    fn trakt_shows_become_one_entry_per_season() {
+        let show = r#""show": {"title": "Dark", "year": 2017, "ids": {"tmdb": 70523}}"#;
+        let body = format!(
+            r#"[
+                {{{show}, "seasons": [{{"number": 1}}, {{"number": 2}}]}},
+                {{{show}, "season": {{"number": 3}}}},
+                {{{show}, "episode": {{"season": 4, "number": 2}}}},
+                {{"show": {{"title": "Lost", "year": 2004, "ids": {{"tmdb": 4607}}}}}}
+            ]"#
+        );
+
+        assert_eq!(
+            summaries(&parsed(ImportSource::Trakt, &body)),
+            [
+                (Some(70523), "Dark", Some(2017), Show, Some(1), Completed),
+                (Some(70523), "Dark", Some(2017), Show, Some(2), Completed),
+                (Some(70523), "Dark", Some(2017), Show, Some(3), Completed),
+                // One watched episode doesn't mean the season was finished
+                (Some(70523), "Dark", Some(2017), Show, Some(4), Watching),
+                (Some(4607), "Lost", Some(2004), Show, Some(1), Completed),
+            ]
+        );
+    }
+
+    #[test]
+    fn mal_anime_are_shows_unless_movies() {
+        let body = "<?xml version=\"1.0\" encoding=\"UTF-8\" ?>\
+            <myanimelist>\
+              <myinfo><user_name>someone</user_name></myinfo>\
+              <anime><series_title><![CDATA[Cowboy Bebop]]></series_title><series_type>TV</series_type><my_status>Completed</my_status><my_comments>Great</my_comments></anime>\
+              <anime><series_title>Akira</series_title><series_type>Movie</series_type><my_status>Plan to Watch</my_status></anime>\
+              <anime><series_title>Monster</series_title><series_type>TV</series_type><my_status>On-Hold</my_status></anime>\
+              <anime><series_title>Berserk</series_title><series_type>TV</series_type><my_status>Dropped</my_status></anime>\
+            </myanimelist>";
+
+        let entries = parsed(ImportSource::MyAnimeList, body);
+
+        assert_eq!(
+            summaries(&entries),
+            [
+                (None, "Cowboy Bebop", None, Show, Some(1), Completed),
+                (None, "Akira", None, Film, None, PlanToWatch),
+                (None, "Monster", None, Show, Some(1), Watching),
+                (None, "Berserk", None, Show, Some(1), Dropped),
+            ]
+        );
+        assert_eq!(entries[0].text, "Great");
+    }
+
+    #[test]
+    fn review_api_entries_keep_their_fields() {
+        let body = r#"{"version": 1, "reviews": [
+            {"tmdb_id": 949, "category": "Film", "status": "Completed", "text": "Great", "fun_during": true, "visibility": "Private"},
+            {"tmdb_id": 70523, "category": "Show", "season": 2, "status": "Watching"}
+        ]}"#;
+
+        let entries = parsed(ImportSource::ReviewApi, body);
+
+        assert_eq!(
+            summaries(&entries),
+            [
+                (Some(949), "949", None, Film, None, Completed),
+                (Some(70523), "70523", None, Show, Some(2), Watching),
+            ]
+        );
+        assert_eq!(entries[0].text, "Great");
+        assert_eq!(
+            (
+                entries[0].fun_before,
+                entries[0].fun_during,
+                entries[0].fun_after
+            ),
+            (false, true, false)
+        );
+        assert_eq!(entries[0].visibility, ReviewVisibility::Private);
+        // Exports from before visibility existed are public
+        assert_eq!(entries[1].visibility, ReviewVisibility::Public);
+    }
+
+    #[test]
+    fn duplicates_are_dropped_and_status_can_be_overridden() {
+        let body = "Name,Year\nHeat,1995\nheat,1995\nHeat,2009\n";
+
+        let entries = parse(ImportSource::Letterboxd, body.as_bytes(), Some(PlanToWatch)).unwrap();
+
+        assert_eq!(
+            summaries(&entries),
+            [
+                (None, "Heat", Some(1995), Film, None, PlanToWatch),
+                (None, "Heat", Some(2009), Film, None, PlanToWatch),
+            ]
+        );
+    }
+
+    #[test]
+    fn malformed_exports_are_invalid_imports() {
+        let invalid = (400, "invalid_import");
+
+        assert_eq!(
+            error_code(ImportSource::Letterboxd, b"Name,Year\nHeat,nineteen\n"),
+            invalid
+        );
+        assert_eq!(
+            error_code(ImportSource::Letterboxd, b"Title\nHeat\n"),
+            invalid
+        );
+        assert_eq!(error_code(ImportSource::Trakt, b"{\"movie\": {}}"), invalid);
+        assert_eq!(
+            error_code(ImportSource::Trakt, b"[{\"movie\": {\"title\": 1}}]"),
+            invalid
+        );
+        assert_eq!(
+            error_code(ImportSource::MyAnimeList, b"<myanimelist><anime>"),
+            invalid
+        );
+        assert_eq!(error_code(ImportSource::MyAnimeList, b"\xff\xfe"), invalid);
+        assert_eq!(
+            error_code(
+                ImportSource::MyAnimeList,
+                b"<myanimelist><anime><series_title>A</series_title><my_status>Rewatching</my_status></anime></myanimelist>"
+            ),
+            invalid
+        );
+        assert_eq!(error_code(ImportSource::ReviewApi, b"[]"), invalid);
+        assert_eq!(
+            error_code(
+                ImportSource::ReviewApi,
+                br#"{"reviews": [{"tmdb_id": 1, "category": "Book", "status": "Completed"}]}"#
+            ),
+            invalid
+        );
+    }
+}
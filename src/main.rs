@@ -7,6 +7,7 @@ use ::r2d2::PooledConnection;
 use actix_web::middleware;
 use actix_web::{cookie::Key, get, web, App, HttpResponse, HttpServer, Responder};
 use chrono::{DateTime, Utc};
+use clap::Parser;
 use diesel::prelude::*;
 use diesel::r2d2::{self, ConnectionManager};
 use serde_json::json;
@@ -15,95 +16,216 @@ use std::time::Duration;
 use actix_identity::IdentityMiddleware;
 use actix_session::{storage::RedisSessionStore, SessionMiddleware};
 mod actions;
+mod cache;
+mod cli;
+mod config;
 mod constants;
 mod errors;
+mod export;
 mod handlers;
+mod import;
+mod media;
+mod metrics;
+mod migrations;
 mod models;
+mod openapi;
 mod pagination;
+mod recommendations;
+mod routes;
 mod schema;
+mod telemetry;
+#[cfg(test)]
+mod testing;
+mod tmdb;
 mod utils;
+mod validation;
 
 pub type Pool = r2d2::Pool<ConnectionManager<PgConnection>>;
 pub type PooledConn = PooledConnection<ConnectionManager<PgConnection>>;
 
-use handlers::{auth, reviews, search, users};
+use handlers::monitoring;
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
+    let command = cli::Cli::parse().command.unwrap_or(cli::Command::Serve);
+
     dotenvy::dotenv().ok();
-    std::env::set_var("RUST_LOG", "actix_web=debug");
-    let db_url = std::env::var("DATABASE_URL").expect("DATABASE_URL is missing");
-    let redis_url = std::env::var("REDIS_URL").expect("REDIS_URL is missing");
+    let config = match config::Config::load(command.needs()) {
+        Ok(config) => config,
+        // Logging is configured too, so there's nothing to log with yet
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    };
+    telemetry::init(&config.log);
+
+    let res = match command {
+        cli::Command::Serve => return serve(config).await,
+        cli::Command::Migrate { action } => {
+            connect(&config).and_then(|mut conn| cli::migrate::run(&mut conn, action))
+        }
+        cli::Command::Admin { action } => connect(&config).and_then(|mut conn| {
+            Ok(cli::admin::run(
+                &mut conn,
+                action,
+                config.auth.secret_key.expose(),
+            )?)
+        }),
+    };
+
+    if let Err(e) = res {
+        eprintln!("{e}");
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// A single connection for the CLI commands.
+fn connect(config: &config::Config) -> Result<PooledConn, errors::DbError> {
+    let manager = ConnectionManager::<PgConnection>::new(config.database.url.expose());
+    let pool = r2d2::Pool::builder().max_size(1).build(manager)?;
+
+    Ok(pool.get()?)
+}
+
+async fn serve(config: config::Config) -> std::io::Result<()> {
+    tracing::info!("Starting with {config:?}");
 
     openssl_probe::init_ssl_cert_env_vars();
 
-    let manager = ConnectionManager::<PgConnection>::new(db_url);
+    pagination::set_limits(config.pagination);
+    errors::set_config(config.errors);
+    metrics::init();
+
+    let manager = ConnectionManager::<PgConnection>::new(config.database.url.expose());
 
     let pool = r2d2::Pool::builder()
+        .max_size(config.database.pool_size)
+        .event_handler(Box::new(metrics::PoolEvents))
         .build(manager)
         .expect("Failed to build pool");
 
-    let store = RedisSessionStore::new(redis_url)
+    let mut conn = pool.get().expect("Failed to connect to database");
+    if let Err(e) = migrations::on_start(&mut conn, config.database.migrations) {
+        tracing::error!("{e}");
+        std::process::exit(1);
+    }
+    let stale_after = chrono::Duration::minutes(import::STALE_AFTER_MINUTES);
+    match actions::imports::fail_stale_import_jobs(&mut conn, stale_after) {
+        Ok(0) => {}
+        Ok(failed) => tracing::warn!("Failed {failed} imports interrupted by a shutdown"),
+        Err(e) => tracing::error!("Couldn't fail interrupted imports: {e}"),
+    }
+    drop(conn);
+
+    let redis = match cache::connect_redis(config.redis.url.expose()).await {
+        Ok(redis) => Some(redis),
+        Err(e) => {
+            tracing::warn!("Couldn't connect to Redis: {e}");
+            None
+        }
+    };
+    let response_cache = cache::ResponseCache::new(&config.cache, redis.clone());
+
+    let store = RedisSessionStore::new(config.redis.url.expose())
         .await
         .expect("Failed to connect to redis");
 
     let session_secret = Key::generate();
 
-    HttpServer::new(move || {
+    actix_web::rt::spawn(recommendations::schedule(
+        pool.clone(),
+        tmdb::TmdbClient::new(config.tmdb.clone(), response_cache.clone()),
+        config.recommendations.interval_hours,
+    ));
+
+    let bind_to = (config.server.host.clone(), config.server.port);
+    let metrics_port = config.metrics.port;
+    let login_deadline = Duration::from_secs(config.auth.login_deadline_secs);
+    let config = web::Data::new(config);
+    let pool = web::Data::new(pool);
+
+    let metrics_server = match metrics_port {
+        Some(port) => {
+            let (config, pool) = (config.clone(), pool.clone());
+            let server = HttpServer::new(move || {
+                App::new()
+                    .app_data(config.clone())
+                    .app_data(pool.clone())
+                    .service(monitoring::get_metrics)
+            })
+            .workers(1)
+            .bind((bind_to.0.clone(), port))?
+            .run();
+
+            Some(server)
+        }
+        None => None,
+    };
+
+    let server = HttpServer::new(move || {
         App::new()
             .wrap(
                 IdentityMiddleware::builder()
-                    // Logout after 1 day
-                    .login_deadline(Some(Duration::new(86400, 0)))
+                    .login_deadline(Some(login_deadline))
                     .build(),
             )
             .wrap(SessionMiddleware::new(
                 store.clone(),
                 session_secret.clone(),
             ))
+            .wrap_fn(validation::limit_query)
             .wrap(middleware::NormalizePath::trim())
-            .app_data(web::Data::new(pool.clone()))
+            .wrap(telemetry::RequestTracing)
+            .app_data(validation::json_config())
+            .app_data(validation::query_config())
+            .app_data(validation::path_config())
+            .app_data(config.clone())
+            .app_data(pool.clone())
+            .app_data(web::Data::new(response_cache.clone()))
+            .app_data(web::Data::new(redis.clone()))
+            .app_data(web::Data::new(tmdb::TmdbClient::new(
+                config.tmdb.clone(),
+                response_cache.clone(),
+            )))
             .app_data(web::Data::new(Utc::now()))
+            .default_service(web::to(route_not_found))
             .service(health)
             .service(
-                web::scope("/auth")
-                    .service(auth::login)
-                    .service(auth::logout)
-                    .service(auth::me),
-            )
-            .service(
-                web::scope("/users")
-                    .service(users::get_users)
-                    .service(users::get_users_id)
-                    .service(users::delete_users_id)
-                    .service(users::patch_users_id)
-                    .service(users::post_users),
-            )
-            .service(
-                web::scope("/search")
-                    .service(search::search_movies)
-                    .service(search::search_shows),
-            )
-            .service(
-                web::scope("/reviews")
-                    .service(reviews::get_reviews)
-                    .service(reviews::post_reviews)
-                    .service(
-                        web::resource(["/{category}/{id}/{season}", "/{category}/{id}"])
-                            .route(web::patch().to(reviews::patch_reviews))
-                            .route(web::delete().to(reviews::delete_reviews)),
-                    ),
+                web::scope("/health")
+                    .service(monitoring::get_live)
+                    .service(monitoring::get_ready),
             )
+            .configure(|cfg| {
+                if metrics_port.is_none() {
+                    cfg.service(monitoring::get_metrics);
+                }
+            })
+            .service(openapi::get_openapi)
+            .service(openapi::get_docs)
+            .configure(routes::configure)
     })
-    .bind(("0.0.0.0", 8080))?
-    .run()
-    .await
+    .bind(bind_to)?
+    .run();
+
+    match metrics_server {
+        Some(metrics_server) => futures_util::try_join!(server, metrics_server).map(|_| ()),
+        None => server.await,
+    }
 }
 
 #[get("/")]
 async fn health(birth: web::Data<DateTime<Utc>>) -> impl Responder {
-    // TODO perhaps make this useful
     HttpResponse::Ok().json(json!({
         "last_deploy": birth.into_inner().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
     }))
 }
+
+async fn route_not_found() -> Result<HttpResponse, errors::ServiceError> {
+    Err(errors::ServiceError::new(
+        404,
+        "route_not_found",
+        "No such endpoint",
+    ))
+}
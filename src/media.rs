@@ -0,0 +1,510 @@
+// Keeps the media table in sync with TMDB, and serves from it when TMDB is unavailable
+
+use std::str::FromStr;
+
+use actix_web::web;
+use serde::Deserialize;
+
+use crate::{
+    actions::media::{find_media, save_media_details, save_search_results, search_media},
+    errors::{FieldError, ServiceError},
+    models::{Media, MediaCategory, NewMedia},
+    tmdb::{Movie, MovieDetails, Results, SearchInfo, Show, ShowDetails, TmdbClient},
+    utils::block,
+    Pool,
+};
+
+// How long fetched details are trusted before asking TMDB again
+const MEDIA_TTL_DAYS: i64 = 7;
+
+/// How reviews are checked against TMDB before saving, set with `tmdb.validation`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Validation {
+    /// Reject anything that can't be checked
+    Strict,
+    /// Only reject what TMDB says doesn't exist
+    Lenient,
+    /// For offline dev
+    Off,
+}
+
+impl FromStr for Validation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "strict" => Ok(Validation::Strict),
+            "lenient" => Ok(Validation::Lenient),
+            "off" => Ok(Validation::Off),
+            other => Err(format!("must be strict, lenient or off, not {other}")),
+        }
+    }
+}
+
+fn parse_date(date: &str) -> Option<chrono::NaiveDate> {
+    chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()
+}
+
+fn format_date(date: Option<chrono::NaiveDate>) -> String {
+    date.map(|date| date.format("%Y-%m-%d").to_string())
+        .unwrap_or_default()
+}
+
+impl From<&Movie> for NewMedia {
+    fn from(movie: &Movie) -> Self {
+        NewMedia {
+            tmdb_id: movie.id,
+            category: MediaCategory::Film,
+            title: movie.title.clone(),
+            original_title: movie.original_title.clone(),
+            original_language: movie.original_language.clone(),
+            release_date: parse_date(&movie.release_date),
+            overview: movie.overview.clone(),
+            poster_path: movie.poster_path.clone(),
+            number_of_seasons: None,
+            number_of_episodes: None,
+            details_fetched_at: None,
+        }
+    }
+}
+
+impl From<&Show> for NewMedia {
+    fn from(show: &Show) -> Self {
+        NewMedia {
+            tmdb_id: show.id,
+            category: MediaCategory::Show,
+            title: show.name.clone(),
+            original_title: show.original_name.clone(),
+            original_language: show.original_language.clone(),
+            release_date: parse_date(&show.first_air_date),
+            overview: show.overview.clone(),
+            poster_path: show.poster_path.clone(),
+            number_of_seasons: None,
+            number_of_episodes: None,
+            details_fetched_at: None,
+        }
+    }
+}
+
+impl From<&MovieDetails> for NewMedia {
+    fn from(movie: &MovieDetails) -> Self {
+        NewMedia {
+            tmdb_id: movie.id,
+            category: MediaCategory::Film,
+            title: movie.title.clone(),
+            original_title: movie.original_title.clone(),
+            original_language: movie.original_language.clone(),
+            release_date: parse_date(&movie.release_date),
+            overview: movie.overview.clone(),
+            poster_path: movie.poster_path.clone(),
+            number_of_seasons: None,
+            number_of_episodes: None,
+            details_fetched_at: Some(chrono::Utc::now()),
+        }
+    }
+}
+
+impl From<&ShowDetails> for NewMedia {
+    fn from(show: &ShowDetails) -> Self {
+        NewMedia {
+            tmdb_id: show.id,
+            category: MediaCategory::Show,
+            title: show.name.clone(),
+            original_title: show.original_name.clone(),
+            original_language: show.original_language.clone(),
+            release_date: parse_date(&show.first_air_date),
+            overview: show.overview.clone(),
+            poster_path: show.poster_path.clone(),
+            number_of_seasons: Some(show.number_of_seasons),
+            number_of_episodes: Some(show.number_of_episodes),
+            details_fetched_at: Some(chrono::Utc::now()),
+        }
+    }
+}
+
+impl From<Media> for Movie {
+    fn from(media: Media) -> Self {
+        Movie {
+            id: media.tmdb_id,
+            title: media.title,
+            original_title: media.original_title,
+            original_language: media.original_language,
+            release_date: format_date(media.release_date),
+            overview: media.overview,
+            poster_path: media.poster_path,
+        }
+    }
+}
+
+impl From<Media> for Show {
+    fn from(media: Media) -> Self {
+        Show {
+            id: media.tmdb_id,
+            name: media.title,
+            original_name: media.original_title,
+            original_language: media.original_language,
+            first_air_date: format_date(media.release_date),
+            overview: media.overview,
+            poster_path: media.poster_path,
+        }
+    }
+}
+
+impl Media {
+    pub fn is_stale(&self) -> bool {
+        match self.details_fetched_at {
+            Some(fetched_at) => {
+                chrono::Utc::now() - fetched_at > chrono::Duration::days(MEDIA_TTL_DAYS)
+            }
+            None => true,
+        }
+    }
+}
+
+// Titles are only cached in the default language
+fn is_cacheable(lang: Option<&str>) -> bool {
+    matches!(lang, None | Some("en-US"))
+}
+
+/// Saves search results in the background.
+pub fn cache_search_results(pool: &Pool, lang: Option<&str>, results: Vec<NewMedia>) {
+    if !is_cacheable(lang) || results.is_empty() {
+        return;
+    }
+
+    let pool = pool.clone();
+    actix_web::rt::spawn(async move {
+        let _ = block(move || {
+            let mut conn = pool.get()?;
+            save_search_results(&mut conn, &results)
+        })
+        .await;
+    });
+}
+
+/// Saves freshly fetched details in the background.
+pub fn cache_details(pool: &Pool, lang: Option<&str>, details: NewMedia) {
+    if !is_cacheable(lang) {
+        return;
+    }
+
+    let pool = pool.clone();
+    actix_web::rt::spawn(async move {
+        let _ = block(move || {
+            let mut conn = pool.get()?;
+            save_media_details(&mut conn, &details)
+        })
+        .await;
+    });
+}
+
+/// Cached search results, or `None` if nothing matched.
+pub async fn search_cached<T: From<Media>>(
+    pool: &Pool,
+    category: MediaCategory,
+    info: &SearchInfo,
+) -> Result<Option<Results<T>>, ServiceError> {
+    if !is_cacheable(info.lang.as_deref()) {
+        return Ok(None);
+    }
+
+    let pool = pool.clone();
+    let query = info.query.clone();
+    let year = info.year;
+    let page = info.page.map(i64::from);
+
+    let cached = block(move || {
+        let mut conn = pool.get()?;
+        search_media(&mut conn, category, &query, year, page)
+    })
+    .await??;
+
+    if cached.results.is_empty() {
+        return Ok(None);
+    }
+
+    Ok(Some(Results {
+        results: cached.results.into_iter().map(T::from).collect(),
+        page: cached.page as i32,
+        total_pages: cached.total_pages as i32,
+        total_results: cached.total_results as i32,
+    }))
+}
+
+/// Gets details for a title, asking TMDB if the cached copy is missing or stale.
+/// A stale copy is still returned if TMDB fails.
+pub async fn get_media(
+    pool: &Pool,
+    tmdb: &TmdbClient,
+    tmdb_id: i32,
+    category: MediaCategory,
+) -> Result<Media, ServiceError> {
+    let find_pool = pool.clone();
+    let cached = block(move || {
+        let mut conn = find_pool.get()?;
+        find_media(&mut conn, tmdb_id, category)
+    })
+    .await??;
+
+    if let Some(cached) = &cached {
+        if !cached.is_stale() {
+            return Ok(cached.clone());
+        }
+    }
+
+    let fetched = match category {
+        MediaCategory::Film => tmdb
+            .get_movie(tmdb_id, None)
+            .await
+            .map(|details| NewMedia::from(&details)),
+        MediaCategory::Show => tmdb
+            .get_show(tmdb_id, None)
+            .await
+            .map(|details| NewMedia::from(&details)),
+    };
+
+    let details = match (fetched, cached) {
+        (Ok(details), _) => details,
+        (Err(_), Some(cached)) => return Ok(cached),
+        (Err(e), None) => return Err(e),
+    };
+
+    let pool = pool.clone();
+    let saved = block(move || {
+        let mut conn = pool.get()?;
+        save_media_details(&mut conn, &details)
+    })
+    .await??;
+
+    Ok(saved)
+}
+
+/// Makes sure details for a title are cached, without waiting on TMDB.
+pub fn prefetch_media(
+    pool: &Pool,
+    tmdb: &web::Data<TmdbClient>,
+    tmdb_id: i32,
+    category: MediaCategory,
+) {
+    let pool = pool.clone();
+    let tmdb = tmdb.clone();
+    actix_web::rt::spawn(async move {
+        let _ = get_media(&pool, &tmdb, tmdb_id, category).await;
+    });
+}
+
+/// Checks that a title exists for `category`, that films have no season, and that shows have
+/// one they actually aired. Season 0 is for specials.
+pub async fn validate_review(
+    pool: &Pool,
+    tmdb: &TmdbClient,
+    tmdb_id: i32,
+    category: MediaCategory,
+    season: Option<i32>,
+) -> Result<(), ServiceError> {
+    let validation = tmdb.config().validation;
+
+    if validation == Validation::Off {
+        return Ok(());
+    }
+
+    let invalid = |field, code, message| {
+        ServiceError::validation(vec![FieldError::new(field, code, message)])
+    };
+
+    match (category, season) {
+        (MediaCategory::Film, Some(_)) => {
+            return Err(invalid(
+                "season",
+                "not_allowed",
+                "Films don't have seasons".into(),
+            ))
+        }
+        (MediaCategory::Show, None) => {
+            return Err(invalid("season", "required", "Shows need a season".into()))
+        }
+        _ => {}
+    }
+
+    let media = match get_media(pool, tmdb, tmdb_id, category).await {
+        Ok(media) => media,
+        Err(e) if e.status == 404 => {
+            return Err(invalid(
+                "tmdb_id",
+                "not_found",
+                format!("No {category:?} with tmdb_id {tmdb_id}"),
+            ))
+        }
+        Err(_) if validation == Validation::Lenient => return Ok(()),
+        Err(e) => return Err(e),
+    };
+
+    if let (Some(season), Some(number_of_seasons)) = (season, media.number_of_seasons) {
+        if !(0..=number_of_seasons).contains(&season) {
+            return Err(invalid(
+                "season",
+                "out_of_range",
+                format!("{} only has {number_of_seasons} seasons", media.title),
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{web, App, HttpResponse, HttpServer};
+    use diesel::prelude::*;
+
+    use super::{validate_review, Validation};
+    use crate::{
+        cache::ResponseCache,
+        config::Secret,
+        errors::ServiceError,
+        models::{MediaCategory, NewMedia},
+        schema::media,
+        testing,
+        tmdb::{TmdbClient, TmdbConfig},
+        Pool,
+    };
+
+    /// A stand-in TMDB that answers everything with `status`.
+    fn tmdb(status: u16, validation: Validation) -> TmdbClient {
+        let server = HttpServer::new(move || {
+            App::new().default_service(web::to(move || async move {
+                HttpResponse::build(status.try_into().unwrap()).finish()
+            }))
+        })
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+        let addr = server.addrs()[0];
+        actix_web::rt::spawn(server.run());
+
+        let mut config = TmdbConfig::default();
+        config.base_url = format!("http://{addr}");
+        config.api_key = Some("k".parse::<Secret>().unwrap());
+        config.max_retries = 0;
+        config.rate_limit = 0;
+        config.validation = validation;
+        TmdbClient::new(config, ResponseCache::memory(10))
+    }
+
+    /// Freshly cached details, so TMDB isn't asked.
+    fn cache(pool: &Pool, tmdb_id: i32, category: MediaCategory, seasons: Option<i32>) {
+        diesel::insert_into(media::table)
+            .values(NewMedia {
+                tmdb_id,
+                category,
+                title: "Cached".to_string(),
+                original_title: "Cached".to_string(),
+                original_language: "en".to_string(),
+                release_date: None,
+                overview: String::new(),
+                poster_path: None,
+                number_of_seasons: seasons,
+                number_of_episodes: None,
+                details_fetched_at: Some(chrono::Utc::now()),
+            })
+            .execute(&mut pool.get().unwrap())
+            .unwrap();
+    }
+
+    fn field_error(result: Result<(), ServiceError>) -> (String, String) {
+        let e = result.unwrap_err();
+        assert_eq!((e.status, e.code), (422, "validation_failed"));
+        (e.fields[0].field.clone(), e.fields[0].code.to_string())
+    }
+
+    #[actix_web::test]
+    async fn seasons_must_match_the_category() {
+        let Some(pool) = testing::pool() else { return };
+        let tmdb = tmdb(500, Validation::Strict);
+        cache(&pool, 1, MediaCategory::Film, None);
+        cache(&pool, 2, MediaCategory::Show, Some(3));
+
+        let check =
+            |tmdb_id, category, season| validate_review(&pool, &tmdb, tmdb_id, category, season);
+
+        assert!(check(1, MediaCategory::Film, None).await.is_ok());
+        assert_eq!(
+            field_error(check(1, MediaCategory::Film, Some(1)).await),
+            ("season".into(), "not_allowed".into())
+        );
+        assert_eq!(
+            field_error(check(2, MediaCategory::Show, None).await),
+            ("season".into(), "required".into())
+        );
+    }
+
+    #[actix_web::test]
+    async fn seasons_must_have_aired() {
+        let Some(pool) = testing::pool() else { return };
+        let tmdb = tmdb(500, Validation::Strict);
+        cache(&pool, 2, MediaCategory::Show, Some(3));
+
+        for season in [0, 1, 3] {
+            assert!(
+                validate_review(&pool, &tmdb, 2, MediaCategory::Show, Some(season))
+                    .await
+                    .is_ok()
+            );
+        }
+        for season in [-1, 4] {
+            assert_eq!(
+                field_error(
+                    validate_review(&pool, &tmdb, 2, MediaCategory::Show, Some(season)).await
+                ),
+                ("season".into(), "out_of_range".into())
+            );
+        }
+    }
+
+    #[actix_web::test]
+    async fn titles_tmdb_doesnt_have_are_rejected() {
+        let Some(pool) = testing::pool() else { return };
+
+        for validation in [Validation::Strict, Validation::Lenient] {
+            let tmdb = tmdb(404, validation);
+            assert_eq!(
+                field_error(validate_review(&pool, &tmdb, 3, MediaCategory::Film, None).await),
+                ("tmdb_id".into(), "not_found".into())
+            );
+        }
+    }
+
+    #[actix_web::test]
+    async fn only_strict_rejects_what_it_cant_check() {
+        let Some(pool) = testing::pool() else { return };
+
+        let strict = tmdb(500, Validation::Strict);
+        let e = validate_review(&pool, &strict, 3, MediaCategory::Film, None)
+            .await
+            .unwrap_err();
+        assert_eq!((e.status, e.code), (502, "tmdb_unavailable"));
+
+        let lenient = tmdb(500, Validation::Lenient);
+        assert!(
+            validate_review(&pool, &lenient, 3, MediaCategory::Film, None)
+                .await
+                .is_ok()
+        );
+    }
+
+    #[actix_web::test]
+    async fn off_checks_nothing() {
+        let Some(pool) = testing::pool() else { return };
+        let tmdb = tmdb(404, Validation::Off);
+
+        assert!(
+            validate_review(&pool, &tmdb, 3, MediaCategory::Film, Some(1))
+                .await
+                .is_ok()
+        );
+        assert!(validate_review(&pool, &tmdb, 3, MediaCategory::Show, None)
+            .await
+            .is_ok());
+    }
+}
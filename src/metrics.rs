@@ -0,0 +1,198 @@
+// Prometheus metrics, all registered in the default registry and served by `GET /metrics`
+
+use std::{sync::LazyLock, time::Duration};
+
+use prometheus::{
+    register_histogram, register_histogram_vec, register_int_counter, register_int_counter_vec,
+    register_int_gauge_vec, Encoder, Histogram, HistogramVec, IntCounter, IntCounterVec,
+    IntGaugeVec, TextEncoder,
+};
+use r2d2::event::{CheckoutEvent, HandleEvent, TimeoutEvent};
+use serde::Deserialize;
+
+use crate::{config::Secret, errors::ServiceError, Pool};
+
+static HTTP_REQUESTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "http_requests_total",
+        "HTTP requests handled, by route and status",
+        &["method", "route", "status"]
+    )
+    .unwrap()
+});
+
+static HTTP_DURATION: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!(
+        "http_request_duration_seconds",
+        "How long HTTP requests took to handle, by route and status",
+        &["method", "route", "status"]
+    )
+    .unwrap()
+});
+
+static DB_CONNECTIONS: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register_int_gauge_vec!(
+        "db_pool_connections",
+        "Database connections in the pool, by whether they're idle or in use",
+        &["state"]
+    )
+    .unwrap()
+});
+
+static DB_WAIT: LazyLock<Histogram> = LazyLock::new(|| {
+    register_histogram!(
+        "db_pool_wait_seconds",
+        "How long it took to check a connection out of the pool"
+    )
+    .unwrap()
+});
+
+static DB_TIMEOUTS: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!(
+        "db_pool_timeouts_total",
+        "Times no connection was free before the pool's timeout"
+    )
+    .unwrap()
+});
+
+static TMDB_DURATION: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!(
+        "tmdb_request_duration_seconds",
+        "How long TMDB calls took including retries, by endpoint",
+        &["endpoint"]
+    )
+    .unwrap()
+});
+
+static TMDB_FAILURES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "tmdb_failures_total",
+        "TMDB calls that failed, by endpoint and the status we responded with",
+        &["endpoint", "status"]
+    )
+    .unwrap()
+});
+
+static LOGINS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!("logins_total", "Login attempts, by result", &["result"]).unwrap()
+});
+
+/// The `[metrics]` config section.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsConfig {
+    /// Serves `/metrics` on its own port instead of the API's, e.g. one that isn't public
+    pub port: Option<u16>,
+    /// Requires `Authorization: Bearer <token>` to scrape
+    pub token: Option<Secret>,
+}
+
+/// Registers everything up front, so scrapes show every metric from the start.
+pub fn init() {
+    LazyLock::force(&HTTP_REQUESTS);
+    LazyLock::force(&HTTP_DURATION);
+    LazyLock::force(&DB_CONNECTIONS);
+    LazyLock::force(&DB_WAIT);
+    LazyLock::force(&DB_TIMEOUTS);
+    LazyLock::force(&TMDB_DURATION);
+    LazyLock::force(&TMDB_FAILURES);
+    LazyLock::force(&LOGINS);
+}
+
+pub fn observe_request(method: &str, route: &str, status: u16, elapsed: Duration) {
+    let status = status.to_string();
+    let labels = [method, route, status.as_str()];
+
+    HTTP_REQUESTS.with_label_values(&labels).inc();
+    HTTP_DURATION
+        .with_label_values(&labels)
+        .observe(elapsed.as_secs_f64());
+}
+
+// IDs would give every title its own series
+fn endpoint(path: &str) -> String {
+    path.split('/')
+        .map(|segment| match segment.parse::<i64>() {
+            Ok(_) => "{id}",
+            Err(_) => segment,
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+pub fn observe_tmdb(path: &str, elapsed: Duration, error: Option<&ServiceError>) {
+    let endpoint = endpoint(path);
+
+    TMDB_DURATION
+        .with_label_values(&[&endpoint])
+        .observe(elapsed.as_secs_f64());
+
+    if let Some(error) = error {
+        TMDB_FAILURES
+            .with_label_values(&[&endpoint, &error.status.to_string()])
+            .inc();
+    }
+}
+
+// Only wrong credentials count as failures, so an outage doesn't look like credential stuffing
+fn login_result<T>(result: &Result<T, ServiceError>) -> &'static str {
+    match result {
+        Ok(_) => "success",
+        Err(e) if e.code == "invalid_credentials" => "failure",
+        Err(e) if e.code == "account_suspended" => "suspended",
+        Err(_) => "error",
+    }
+}
+
+pub fn observe_login<T>(result: &Result<T, ServiceError>) {
+    LOGINS.with_label_values(&[login_result(result)]).inc();
+}
+
+/// Times checkouts from the pool, pass to `r2d2::Builder::event_handler`.
+#[derive(Debug)]
+pub struct PoolEvents;
+
+impl HandleEvent for PoolEvents {
+    fn handle_checkout(&self, event: CheckoutEvent) {
+        DB_WAIT.observe(event.duration().as_secs_f64());
+    }
+
+    fn handle_timeout(&self, _event: TimeoutEvent) {
+        DB_TIMEOUTS.inc();
+    }
+}
+
+/// Everything in the Prometheus text format, with the pool gauges as of now.
+pub fn render(pool: &Pool) -> Result<String, ServiceError> {
+    let state = pool.state();
+    DB_CONNECTIONS
+        .with_label_values(&["idle"])
+        .set(state.idle_connections.into());
+    DB_CONNECTIONS
+        .with_label_values(&["active"])
+        .set((state.connections - state.idle_connections).into());
+
+    let mut buffer = Vec::new();
+    TextEncoder::new()
+        .encode(&prometheus::gather(), &mut buffer)
+        .map_err(ServiceError::internal)?;
+
+    String::from_utf8(buffer).map_err(ServiceError::internal)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::login_result;
+    use crate::errors::ServiceError;
+
+    #[test]
+    fn only_wrong_credentials_are_failed_logins() {
+        let error = |status, code| Err::<(), _>(ServiceError::new(status, code, ""));
+
+        assert_eq!(login_result(&Ok(())), "success");
+        assert_eq!(login_result(&error(401, "invalid_credentials")), "failure");
+        assert_eq!(login_result(&error(403, "account_suspended")), "suspended");
+        assert_eq!(login_result(&error(500, "internal_error")), "error");
+        assert_eq!(login_result(&error(503, "service_unavailable")), "error");
+    }
+}
@@ -0,0 +1,206 @@
+// Migrations are built into the binary, so deploys don't need the diesel CLI
+
+use std::str::FromStr;
+
+use diesel::{migration::MigrationSource, pg::Pg, sql_query, PgConnection, RunQueryDsl};
+use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+use serde::Deserialize;
+
+use crate::errors::DbError;
+
+pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
+
+// Arbitrary, just has to be the same for every instance
+const LOCK_KEY: i64 = 0x7265_7669_6577;
+
+/// What to do about pending migrations at startup.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OnStart {
+    /// Run them before serving
+    Apply,
+    /// Refuse to start until they've been run
+    #[default]
+    Check,
+    /// Start anyway, for when something else manages the schema
+    Ignore,
+}
+
+impl FromStr for OnStart {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "apply" => Ok(OnStart::Apply),
+            "check" => Ok(OnStart::Check),
+            "ignore" => Ok(OnStart::Ignore),
+            other => Err(format!("must be apply, check or ignore, not {other}")),
+        }
+    }
+}
+
+/// Holds a Postgres advisory lock while `f` runs, so instances starting together don't both
+/// migrate.
+fn locked<T>(
+    conn: &mut PgConnection,
+    f: impl FnOnce(&mut PgConnection) -> Result<T, DbError>,
+) -> Result<T, DbError> {
+    sql_query(format!("SELECT pg_advisory_lock({LOCK_KEY})")).execute(conn)?;
+    let res = f(conn);
+    sql_query(format!("SELECT pg_advisory_unlock({LOCK_KEY})")).execute(conn)?;
+    res
+}
+
+pub fn pending(conn: &mut PgConnection) -> Result<Vec<String>, DbError> {
+    Ok(conn
+        .pending_migrations(MIGRATIONS)?
+        .iter()
+        .map(|migration| migration.name().to_string())
+        .collect())
+}
+
+/// Runs every pending migration, returning the versions run.
+pub fn up(conn: &mut PgConnection) -> Result<Vec<String>, DbError> {
+    locked(conn, |conn| {
+        Ok(conn
+            .run_pending_migrations(MIGRATIONS)?
+            .iter()
+            .map(ToString::to_string)
+            .collect())
+    })
+}
+
+/// Reverts the latest `steps` migrations, or as many as have run, returning the versions
+/// reverted.
+pub fn down(conn: &mut PgConnection, steps: usize) -> Result<Vec<String>, DbError> {
+    locked(conn, |conn| {
+        let mut reverted = Vec::new();
+
+        for _ in 0..steps {
+            if conn.applied_migrations()?.is_empty() {
+                break;
+            }
+            reverted.push(conn.revert_last_migration(MIGRATIONS)?.to_string());
+        }
+
+        Ok(reverted)
+    })
+}
+
+/// Every migration in the binary and whether it's run, oldest first. Versions the database
+/// has that the binary doesn't, e.g. from a newer deploy, come last as `unknown`.
+pub fn status(conn: &mut PgConnection) -> Result<Vec<(String, &'static str)>, DbError> {
+    let applied: Vec<String> = conn
+        .applied_migrations()?
+        .iter()
+        .map(ToString::to_string)
+        .collect();
+
+    let mut known: Vec<String> = Vec::new();
+    let mut status: Vec<(String, &'static str)> = MigrationSource::<Pg>::migrations(&MIGRATIONS)?
+        .iter()
+        .map(|migration| {
+            let version = migration.name().version().to_string();
+            let state = if applied.contains(&version) {
+                "applied"
+            } else {
+                "pending"
+            };
+            known.push(version);
+            (migration.name().to_string(), state)
+        })
+        .collect();
+    status.sort();
+
+    let mut unknown: Vec<String> = applied
+        .into_iter()
+        .filter(|version| !known.contains(version))
+        .collect();
+    unknown.sort();
+    status.extend(unknown.into_iter().map(|version| (version, "unknown")));
+
+    Ok(status)
+}
+
+/// Applies or checks for pending migrations before the server starts, per `on_start`.
+pub fn on_start(conn: &mut PgConnection, on_start: OnStart) -> Result<(), DbError> {
+    match on_start {
+        OnStart::Apply => {
+            for version in up(conn)? {
+                tracing::info!("Applied migration {version}");
+            }
+        }
+        OnStart::Check => {
+            let pending = pending(conn)?;
+            if !pending.is_empty() {
+                return Err(format!(
+                    "{} pending migrations ({}), run `review-api migrate up` or set database.migrations to apply",
+                    pending.len(),
+                    pending.join(", ")
+                )
+                .into());
+            }
+        }
+        OnStart::Ignore => {
+            let pending = pending(conn)?;
+            if !pending.is_empty() {
+                tracing::warn!("Starting with {} pending migrations", pending.len());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{down, on_start, pending, OnStart};
+    use crate::testing;
+
+    #[test]
+    fn on_start_parses_from_config_and_env() {
+        assert_eq!("apply".parse(), Ok(OnStart::Apply));
+        assert_eq!("check".parse(), Ok(OnStart::Check));
+        assert_eq!("ignore".parse(), Ok(OnStart::Ignore));
+        assert_eq!(
+            "Apply".parse::<OnStart>(),
+            Err("must be apply, check or ignore, not Apply".to_string())
+        );
+
+        let from_toml: OnStart = toml::Value::String("ignore".into()).try_into().unwrap();
+        assert_eq!(from_toml, OnStart::Ignore);
+        assert_eq!(OnStart::default(), OnStart::Check);
+    }
+
+    #[test]
+    fn up_to_date_databases_start_either_way() {
+        let Some(mut conn) = testing::conn() else {
+            return;
+        };
+
+        for on in [OnStart::Apply, OnStart::Check, OnStart::Ignore] {
+            on_start(&mut conn, on).unwrap();
+        }
+    }
+
+    #[test]
+    fn only_check_refuses_pending_migrations() {
+        let Some(mut conn) = testing::conn() else {
+            return;
+        };
+        // Rolled back with the rest of the test transaction
+        assert_eq!(down(&mut conn, 1).unwrap().len(), 1);
+        let reverted = pending(&mut conn).unwrap();
+        assert_eq!(reverted.len(), 1);
+
+        let e = on_start(&mut conn, OnStart::Check).unwrap_err();
+        assert!(e.to_string().starts_with("1 pending migrations"), "{e}");
+        assert!(e.to_string().contains(&reverted[0]), "{e}");
+
+        on_start(&mut conn, OnStart::Ignore).unwrap();
+        assert_eq!(pending(&mut conn).unwrap().len(), 1);
+
+        on_start(&mut conn, OnStart::Apply).unwrap();
+        assert!(pending(&mut conn).unwrap().is_empty());
+    }
+}
@@ -2,8 +2,10 @@ use crate::schema::*;
 use diesel::associations::Associations;
 use diesel_derive_enum::DbEnum;
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use validator::Validate;
 
-#[derive(Debug, Serialize, Deserialize, Queryable, Identifiable)]
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Identifiable, ToSchema)]
 pub struct User {
     pub id: i32,
     pub name: String,
@@ -13,9 +15,13 @@ pub struct User {
     pub hash: String,
     pub created_at: chrono::DateTime<chrono::Utc>,
     pub updated_at: chrono::DateTime<chrono::Utc>,
+    pub private: bool,
+    pub role: UserRole,
+    #[serde(skip_serializing)]
+    pub suspended_at: Option<chrono::DateTime<chrono::Utc>>,
 }
 
-#[derive(Debug, Serialize, Queryable)]
+#[derive(Debug, Serialize, Queryable, ToSchema)]
 pub struct AuthenticatedUser {
     pub id: i32,
     pub name: String,
@@ -24,6 +30,9 @@ pub struct AuthenticatedUser {
     pub hash: String,
     pub created_at: chrono::DateTime<chrono::Utc>,
     pub updated_at: chrono::DateTime<chrono::Utc>,
+    pub private: bool,
+    pub role: UserRole,
+    pub suspended_at: Option<chrono::DateTime<chrono::Utc>>,
 }
 
 #[derive(Debug, Insertable)]
@@ -34,7 +43,7 @@ pub struct NewUser<'a> {
     pub hash: &'a str,
 }
 
-#[derive(Debug, Serialize, Deserialize, Queryable, Identifiable, Associations)]
+#[derive(Debug, Serialize, Deserialize, Queryable, Identifiable, Associations, ToSchema)]
 #[diesel(primary_key(user_id, tmdb_id, category))]
 #[diesel(belongs_to(User))]
 pub struct Review {
@@ -50,10 +59,12 @@ pub struct Review {
     pub updated_at: chrono::DateTime<chrono::Utc>,
     #[serde(skip_serializing_if = "invalid_season")]
     pub season: i32,
+    pub visibility: ReviewVisibility,
+    pub hidden: bool,
 }
 
 fn invalid_season(season: &i32) -> bool {
-    return *season < 0;
+    *season < 0
 }
 
 #[derive(Debug, Insertable)]
@@ -68,20 +79,186 @@ pub struct NewReview<'a> {
     pub fun_before: bool,
     pub fun_during: bool,
     pub fun_after: bool,
+    pub visibility: ReviewVisibility,
 }
 
-#[derive(Debug, Serialize, Deserialize, AsChangeset)]
+#[derive(Debug, Serialize, Deserialize, AsChangeset, Validate, ToSchema)]
 #[diesel(table_name = reviews)]
 pub struct EditReview {
     status: Option<WatchStatus>,
+    #[validate(length(max = 10000))]
     text: Option<String>,
     fun_before: Option<bool>,
     fun_during: Option<bool>,
     fun_after: Option<bool>,
+    visibility: Option<ReviewVisibility>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Queryable, ToSchema)]
+pub struct Follow {
+    pub follower_id: i32,
+    pub followee_id: i32,
+    pub accepted: bool,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = follows)]
+pub struct NewFollow {
+    pub follower_id: i32,
+    pub followee_id: i32,
+    pub accepted: bool,
 }
 
-#[derive(Serialize, Deserialize, Debug, Copy, Clone, DbEnum, Eq, PartialEq, Hash)]
-#[DieselTypePath = "crate::schema::sql_types::WatchStatus"]
+#[derive(Debug, Serialize, Deserialize, Queryable, ToSchema)]
+pub struct Block {
+    pub user_id: i32,
+    pub blocked_id: i32,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = blocks)]
+pub struct NewBlock {
+    pub user_id: i32,
+    pub blocked_id: i32,
+}
+
+#[derive(Debug, Serialize, Deserialize, Queryable, ToSchema)]
+pub struct Mute {
+    pub user_id: i32,
+    pub muted_id: i32,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = mutes)]
+pub struct NewMute {
+    pub user_id: i32,
+    pub muted_id: i32,
+}
+
+#[derive(Debug, Serialize, Deserialize, Queryable, Identifiable, ToSchema)]
+pub struct Report {
+    pub id: i32,
+    pub reporter_id: i32,
+    pub user_id: i32,
+    pub tmdb_id: Option<i32>,
+    pub category: Option<MediaCategory>,
+    pub season: Option<i32>,
+    pub reason: ReportReason,
+    pub details: String,
+    pub status: ReportStatus,
+    pub resolved_by: Option<i32>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = reports)]
+pub struct NewReport<'a> {
+    pub reporter_id: i32,
+    pub user_id: i32,
+    pub tmdb_id: Option<i32>,
+    pub category: Option<MediaCategory>,
+    pub season: Option<i32>,
+    pub reason: ReportReason,
+    pub details: &'a str,
+}
+
+#[derive(Debug, Serialize, Queryable, Identifiable, ToSchema)]
+pub struct ImportJob {
+    pub id: i32,
+    #[serde(skip_serializing)]
+    pub user_id: i32,
+    pub source: ImportSource,
+    pub status: JobStatus,
+    pub dry_run: bool,
+    pub total: i32,
+    pub processed: i32,
+    pub imported: i32,
+    pub skipped: i32,
+    pub unmatched: Vec<String>,
+    pub error: Option<String>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = import_jobs)]
+pub struct NewImportJob {
+    pub user_id: i32,
+    pub source: ImportSource,
+    pub dry_run: bool,
+    pub total: i32,
+}
+
+// Cached TMDB metadata
+#[derive(Debug, Clone, Serialize, Queryable, ToSchema)]
+pub struct Media {
+    pub tmdb_id: i32,
+    pub category: MediaCategory,
+    pub title: String,
+    pub original_title: String,
+    pub original_language: String,
+    pub release_date: Option<chrono::NaiveDate>,
+    pub overview: String,
+    pub poster_path: Option<String>,
+    pub number_of_seasons: Option<i32>,
+    pub number_of_episodes: Option<i32>,
+    #[serde(skip_serializing)]
+    pub details_fetched_at: Option<chrono::DateTime<chrono::Utc>>,
+    #[serde(skip_serializing)]
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = media)]
+pub struct NewMedia {
+    pub tmdb_id: i32,
+    pub category: MediaCategory,
+    pub title: String,
+    pub original_title: String,
+    pub original_language: String,
+    pub release_date: Option<chrono::NaiveDate>,
+    pub overview: String,
+    pub poster_path: Option<String>,
+    pub number_of_seasons: Option<i32>,
+    pub number_of_episodes: Option<i32>,
+    pub details_fetched_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[derive(Debug, Serialize, Queryable, Identifiable, Associations, ToSchema)]
+#[diesel(primary_key(user_id, tmdb_id, category))]
+#[diesel(belongs_to(User))]
+pub struct Recommendation {
+    #[serde(skip_serializing)]
+    pub user_id: i32,
+    pub tmdb_id: i32,
+    pub category: MediaCategory,
+    pub score: f64,
+    pub source: RecommendationSource,
+    #[serde(skip_serializing)]
+    pub because_tmdb_id: i32,
+    #[serde(skip_serializing)]
+    pub because_category: MediaCategory,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = recommendations)]
+pub struct NewRecommendation {
+    pub user_id: i32,
+    pub tmdb_id: i32,
+    pub category: MediaCategory,
+    pub score: f64,
+    pub source: RecommendationSource,
+    pub because_tmdb_id: i32,
+    pub because_category: MediaCategory,
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, DbEnum, Eq, PartialEq, Hash, ToSchema)]
+#[ExistingTypePath = "crate::schema::sql_types::WatchStatus"]
 #[DbValueStyle = "PascalCase"]
 pub enum WatchStatus {
     Completed,
@@ -90,14 +267,74 @@ pub enum WatchStatus {
     PlanToWatch,
 }
 
-#[derive(Serialize, Deserialize, Debug, Copy, Clone, DbEnum, Eq, PartialEq, Hash)]
-#[DieselTypePath = "crate::schema::sql_types::MediaCategory"]
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, DbEnum, Eq, PartialEq, Hash, ToSchema)]
+#[ExistingTypePath = "crate::schema::sql_types::MediaCategory"]
 #[DbValueStyle = "PascalCase"]
 pub enum MediaCategory {
     Film,
     Show,
 }
 
+// Private reviews are only visible to their author.
+// Followers reviews are also visible to accepted followers.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, DbEnum, Eq, PartialEq, Hash, ToSchema)]
+#[ExistingTypePath = "crate::schema::sql_types::ReviewVisibility"]
+#[DbValueStyle = "PascalCase"]
+pub enum ReviewVisibility {
+    Public,
+    Followers,
+    Private,
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, DbEnum, Eq, PartialEq, Hash, ToSchema)]
+#[ExistingTypePath = "crate::schema::sql_types::UserRole"]
+#[DbValueStyle = "PascalCase"]
+pub enum UserRole {
+    User,
+    Admin,
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, DbEnum, Eq, PartialEq, Hash, ToSchema)]
+#[ExistingTypePath = "crate::schema::sql_types::ReportReason"]
+#[DbValueStyle = "PascalCase"]
+pub enum ReportReason {
+    Spam,
+    Harassment,
+    Spoilers,
+    Inappropriate,
+    Other,
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, DbEnum, Eq, PartialEq, Hash, ToSchema)]
+#[ExistingTypePath = "crate::schema::sql_types::ReportStatus"]
+#[DbValueStyle = "PascalCase"]
+pub enum ReportStatus {
+    Open,
+    Resolved,
+    Dismissed,
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, DbEnum, Eq, PartialEq, Hash, ToSchema)]
+#[ExistingTypePath = "crate::schema::sql_types::ImportSource"]
+#[DbValueStyle = "PascalCase"]
+pub enum ImportSource {
+    Letterboxd,
+    Trakt,
+    MyAnimeList,
+    // Our own json export
+    ReviewApi,
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, DbEnum, Eq, PartialEq, Hash, ToSchema)]
+#[ExistingTypePath = "crate::schema::sql_types::JobStatus"]
+#[DbValueStyle = "PascalCase"]
+pub enum JobStatus {
+    Pending,
+    Running,
+    Completed,
+    Failed,
+}
+
 impl TryFrom<String> for MediaCategory {
     type Error = &'static str;
 
@@ -117,3 +354,13 @@ impl TryFrom<String> for MediaCategory {
 //     pub user_id: i32,
 //     pub permissions: Vec<ApiPermissions>,
 // }
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, DbEnum, Eq, PartialEq, Hash, ToSchema)]
+#[ExistingTypePath = "crate::schema::sql_types::RecommendationSource"]
+#[DbValueStyle = "PascalCase"]
+pub enum RecommendationSource {
+    // Liked by people who liked the same things
+    Community,
+    // TMDB's similar titles
+    Similar,
+}
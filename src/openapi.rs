@@ -0,0 +1,350 @@
+use actix_web::{get, HttpResponse};
+use std::sync::OnceLock;
+use utoipa::{
+    openapi::security::{ApiKey, ApiKeyValue, SecurityScheme},
+    Modify, OpenApi,
+};
+
+use crate::{
+    actions::{
+        recommendations::{Because, ExplainedRecommendation},
+        reports::{InputReport, ModerationAction, ResolveReport},
+        reviews::{
+            self, FilmDetailsWithStats, FilmWithStats, InputReview, ReviewStats,
+            ReviewWithIncludes, SeasonWithStats, ShowDetailsWithStats, ShowWithStats,
+        },
+        users::{self, InputUser, ModerateUser, UpdateUser},
+    },
+    errors::{FieldError, Problem},
+    export::ExportFormat,
+    handlers::{
+        admin::{self, ModerateReview, Purged},
+        auth::{self, AuthData},
+        browse::{self, Browsed},
+        imports,
+        search::{self, SearchResult, Summary},
+        titles, users as user_handlers, Deleted,
+    },
+    models::{
+        AuthenticatedUser, Block, EditReview, Follow, ImportJob, ImportSource, JobStatus, Media,
+        MediaCategory, Mute, Recommendation, RecommendationSource, Report, ReportReason,
+        ReportStatus, Review, ReviewVisibility, User, UserRole, WatchStatus,
+    },
+    pagination::{
+        PaginatedBlocks, PaginatedFollows, PaginatedMutes, PaginatedRecommendations,
+        PaginatedReports, PaginatedReviews, PaginatedUsers,
+    },
+    tmdb::{
+        DiscoverSort, Episode, FilmResults, FilmsWithStats, Genre, Movie, MovieDetails,
+        SearchResults, SeasonDetails, SeasonSummary, Show, ShowDetails, ShowResults,
+        ShowsWithStats, TimeWindow,
+    },
+};
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "review-api",
+        description = "Reviews of films and shows, with titles looked up on TMDB. Errors are \
+                       `application/problem+json` documents with a stable `code`."
+    ),
+    paths(
+        auth::me,
+        auth::login,
+        auth::logout,
+        user_handlers::get_users,
+        user_handlers::post_users,
+        user_handlers::get_users_id,
+        user_handlers::patch_users_id,
+        user_handlers::delete_users_id,
+        user_handlers::post_users_id_follow,
+        user_handlers::delete_users_id_follow,
+        user_handlers::get_users_id_followers,
+        user_handlers::put_users_id_followers,
+        user_handlers::delete_users_id_followers,
+        user_handlers::post_users_id_report,
+        user_handlers::post_users_id_block,
+        user_handlers::delete_users_id_block,
+        user_handlers::get_users_id_blocks,
+        user_handlers::post_users_id_mute,
+        user_handlers::delete_users_id_mute,
+        user_handlers::get_users_id_mutes,
+        user_handlers::get_users_id_export,
+        user_handlers::get_users_id_recommendations,
+        search::search_all,
+        search::search_movies,
+        search::search_shows,
+        browse::get_trending,
+        browse::get_popular,
+        browse::get_discover,
+        titles::get_film,
+        titles::get_show,
+        titles::get_show_season,
+        crate::handlers::reviews::get_reviews,
+        crate::handlers::reviews::post_reviews,
+        crate::handlers::reviews::patch_reviews,
+        crate::handlers::reviews::patch_reviews_season,
+        crate::handlers::reviews::delete_reviews,
+        crate::handlers::reviews::delete_reviews_season,
+        crate::handlers::reviews::post_reviews_report,
+        crate::handlers::reviews::post_reviews_season_report,
+        imports::post_import,
+        imports::get_import,
+        admin::get_reports,
+        admin::patch_reports_id,
+        admin::patch_users_id,
+        admin::patch_reviews,
+        admin::patch_reviews_season,
+        admin::delete_cache,
+    ),
+    components(schemas(
+        Problem,
+        FieldError,
+        Deleted,
+        Purged,
+        AuthData,
+        AuthenticatedUser,
+        User,
+        UserRole,
+        users::SortBy,
+        InputUser,
+        UpdateUser,
+        ModerateUser,
+        Follow,
+        Block,
+        Mute,
+        Report,
+        ReportReason,
+        ReportStatus,
+        InputReport,
+        ResolveReport,
+        ModerationAction,
+        Review,
+        EditReview,
+        InputReview,
+        ModerateReview,
+        ReviewWithIncludes,
+        ReviewStats,
+        reviews::SortBy,
+        WatchStatus,
+        MediaCategory,
+        ReviewVisibility,
+        Media,
+        Recommendation,
+        RecommendationSource,
+        Because,
+        ExplainedRecommendation,
+        ImportJob,
+        ImportSource,
+        JobStatus,
+        ExportFormat,
+        Summary,
+        SearchResult,
+        Movie,
+        Show,
+        Genre,
+        MovieDetails,
+        ShowDetails,
+        SeasonSummary,
+        SeasonDetails,
+        Episode,
+        TimeWindow,
+        DiscoverSort,
+        Browsed,
+        FilmWithStats,
+        ShowWithStats,
+        FilmDetailsWithStats,
+        ShowDetailsWithStats,
+        SeasonWithStats,
+        FilmResults,
+        ShowResults,
+        SearchResults,
+        FilmsWithStats,
+        ShowsWithStats,
+        PaginatedUsers,
+        PaginatedReviews,
+        PaginatedFollows,
+        PaginatedBlocks,
+        PaginatedMutes,
+        PaginatedReports,
+        PaginatedRecommendations,
+    )),
+    tags(
+        (name = "auth", description = "Logging in and out"),
+        (name = "users", description = "Accounts, follows, blocks, mutes and exports"),
+        (name = "search", description = "Searching TMDB"),
+        (name = "browse", description = "Trending, popular and discoverable titles"),
+        (name = "media", description = "Title details"),
+        (name = "reviews", description = "Reviews of films, shows and seasons"),
+        (name = "import", description = "Importing reviews from other sites"),
+        (name = "admin", description = "Moderation, for moderators and admins"),
+    ),
+    servers((url = "/v1")),
+    modifiers(&SessionCookie)
+)]
+pub struct ApiDoc;
+
+/// The session cookie set by `POST /auth`.
+struct SessionCookie;
+
+impl Modify for SessionCookie {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        if let Some(components) = openapi.components.as_mut() {
+            components.add_security_scheme(
+                "session",
+                SecurityScheme::ApiKey(ApiKey::Cookie(ApiKeyValue::new("id"))),
+            );
+        }
+    }
+}
+
+static DOCUMENT: OnceLock<String> = OnceLock::new();
+
+#[get("/openapi.json")]
+pub async fn get_openapi() -> HttpResponse {
+    let document = DOCUMENT.get_or_init(|| {
+        let mut doc = ApiDoc::openapi();
+        // There's no license in Cargo.toml, and an empty one isn't valid OpenAPI
+        doc.info.license = None;
+        doc.to_json().expect("the OpenAPI document serializes")
+    });
+
+    HttpResponse::Ok()
+        .content_type("application/json")
+        .body(document.as_str())
+}
+
+// Redoc is pinned, a new version is a deliberate change
+const DOCS: &str = r#"<!DOCTYPE html>
+<html>
+  <head>
+    <title>review-api</title>
+    <meta charset="utf-8"/>
+    <meta name="viewport" content="width=device-width, initial-scale=1">
+  </head>
+  <body>
+    <redoc spec-url="/openapi.json"></redoc>
+    <script src="https://cdn.redoc.ly/redoc/v2.1.5/bundles/redoc.standalone.js" crossorigin="anonymous"></script>
+  </body>
+</html>
+"#;
+
+#[get("/docs")]
+pub async fn get_docs() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(DOCS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ApiDoc;
+    use crate::routes;
+    use actix_web::http::Method;
+    use std::collections::BTreeSet;
+    use utoipa::{openapi::PathItemType, OpenApi};
+
+    const METHODS: [Method; 5] = [
+        Method::GET,
+        Method::POST,
+        Method::PUT,
+        Method::PATCH,
+        Method::DELETE,
+    ];
+
+    fn item_type(method: &Method) -> PathItemType {
+        match *method {
+            Method::GET => PathItemType::Get,
+            Method::POST => PathItemType::Post,
+            Method::PUT => PathItemType::Put,
+            Method::PATCH => PathItemType::Patch,
+            Method::DELETE => PathItemType::Delete,
+            ref other => panic!("no OpenAPI operation for {other}"),
+        }
+    }
+
+    // Registered in main.rs rather than the route table, for operators and tooling rather than
+    // API clients, so they're left out of the document on purpose. Keep in step with `serve`.
+    const UNDOCUMENTED: [&str; 6] = [
+        "GET /",
+        "GET /health/live",
+        "GET /health/ready",
+        "GET /metrics",
+        "GET /openapi.json",
+        "GET /docs",
+    ];
+
+    fn served() -> BTreeSet<String> {
+        routes::v1()
+            .iter()
+            .map(|endpoint| format!("{} {}", endpoint.method, endpoint.path))
+            .collect()
+    }
+
+    fn documented() -> BTreeSet<String> {
+        let doc = ApiDoc::openapi();
+        doc.paths
+            .paths
+            .iter()
+            .flat_map(|(path, item)| {
+                METHODS
+                    .iter()
+                    .filter(|method| item.operations.contains_key(&item_type(method)))
+                    .map(move |method| format!("{method} {path}"))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn every_route_is_documented() {
+        let undocumented: Vec<_> = served().difference(&documented()).cloned().collect();
+
+        assert!(
+            undocumented.is_empty(),
+            "served but missing from the OpenAPI document: {undocumented:?}"
+        );
+    }
+
+    #[test]
+    fn every_documented_route_is_served() {
+        let unserved: Vec<_> = documented().difference(&served()).cloned().collect();
+
+        assert!(
+            unserved.is_empty(),
+            "documented but not served: {unserved:?}"
+        );
+    }
+
+    #[test]
+    fn routes_outside_the_table_stay_undocumented() {
+        let documented = documented();
+        let served = served();
+
+        for route in UNDOCUMENTED {
+            assert!(!documented.contains(route), "{route} is documented");
+            assert!(!served.contains(route), "{route} is in the route table");
+        }
+    }
+
+    #[test]
+    fn every_schema_reference_resolves() {
+        let doc = ApiDoc::openapi();
+        let schemas: BTreeSet<_> = doc
+            .components
+            .as_ref()
+            .map(|components| components.schemas.keys().cloned().collect())
+            .unwrap_or_default();
+        let json = doc.to_json().unwrap();
+        let missing: BTreeSet<_> = json
+            .split("\"#/components/schemas/")
+            .skip(1)
+            .filter_map(|rest| rest.split('"').next())
+            .filter(|name| !schemas.contains(*name))
+            .collect();
+
+        assert!(
+            missing.is_empty(),
+            "referenced but not registered: {missing:?}"
+        );
+    }
+}
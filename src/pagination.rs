@@ -1,21 +1,88 @@
 // Modified from https://github.com/diesel-rs/diesel/blob/master/examples/postgres/advanced-blog-cli/src/pagination.rs
 
+use std::sync::OnceLock;
+
 use diesel::pg::Pg;
 use diesel::prelude::*;
 use diesel::query_builder::*;
 use diesel::query_dsl::methods::LoadQuery;
 use diesel::sql_types::BigInt;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+use validator::{Validate, ValidationError};
+
+use crate::actions::{recommendations::ExplainedRecommendation, reviews::ReviewWithIncludes};
+use crate::models::{Block, Follow, Mute, Report, User};
+
+/// The `[pagination]` config section.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PaginationConfig {
+    pub default_per_page: i64,
+    pub max_per_page: i64,
+}
+
+impl Default for PaginationConfig {
+    fn default() -> Self {
+        PaginationConfig {
+            default_per_page: 10,
+            max_per_page: 50,
+        }
+    }
+}
 
-const DEFAULT_PER_PAGE: i64 = 10;
-const MAX_PER_PAGE: i64 = 50;
+static LIMITS: OnceLock<PaginationConfig> = OnceLock::new();
 
-#[derive(Serialize)]
+/// Sets the page sizes `paginate_safe` allows, once at startup.
+pub fn set_limits(config: PaginationConfig) {
+    LIMITS.set(config).ok();
+}
+
+#[derive(Serialize, ToSchema)]
+#[aliases(
+    PaginatedUsers = PaginatedResults<User>,
+    PaginatedReviews = PaginatedResults<ReviewWithIncludes>,
+    PaginatedFollows = PaginatedResults<Follow>,
+    PaginatedBlocks = PaginatedResults<Block>,
+    PaginatedMutes = PaginatedResults<Mute>,
+    PaginatedReports = PaginatedResults<Report>,
+    PaginatedRecommendations = PaginatedResults<ExplainedRecommendation>,
+)]
 pub struct PaginatedResults<U> {
-    results: Vec<U>,
-    page: i64,
-    total_pages: i64,
-    total_results: i64,
+    pub results: Vec<U>,
+    pub page: i64,
+    pub total_pages: i64,
+    pub total_results: i64,
+}
+
+/// `per_page` can't be more than the configured `max_per_page`, so it's checked here rather
+/// than with a `range` rule.
+pub fn per_page_limit(per_page: i64) -> Result<(), ValidationError> {
+    let max = LIMITS.get_or_init(PaginationConfig::default).max_per_page;
+
+    if (1..=max).contains(&per_page) {
+        return Ok(());
+    }
+
+    // Described like a range rule
+    let mut error = ValidationError::new("range");
+    error.add_param("min".into(), &1);
+    error.add_param("max".into(), &max);
+    error.add_param("value".into(), &per_page);
+    Err(error)
+}
+
+/// Query params for lists that take nothing but a page.
+#[derive(Deserialize, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PageQuery {
+    #[validate(range(min = 1, max = 1000000))]
+    #[param(minimum = 1, maximum = 1000000)]
+    pub page: Option<i64>,
+    /// Up to `pagination.max_per_page`
+    #[validate(custom = "per_page_limit")]
+    #[param(minimum = 1)]
+    pub per_page: Option<i64>,
 }
 
 pub trait Paginate: Sized {
@@ -38,9 +105,10 @@ impl<T> Paginate for T {
             _ => 1,
         };
 
+        let limits = LIMITS.get_or_init(PaginationConfig::default);
         let per_page = match per_page {
-            Some(per_page) if per_page > 0 && per_page <= MAX_PER_PAGE => per_page,
-            _ => DEFAULT_PER_PAGE,
+            Some(per_page) if per_page > 0 && per_page <= limits.max_per_page => per_page,
+            _ => limits.default_per_page,
         };
 
         self.paginate(page, per_page)
@@ -64,7 +132,7 @@ impl<T> Paginated<T> {
         let per_page = self.per_page;
 
         let records = self.load::<(U, i64)>(conn)?;
-        let total_results = records.get(0).map(|x| x.1).unwrap_or(0);
+        let total_results = records.first().map(|x| x.1).unwrap_or(0);
         let results = records.into_iter().map(|x| x.0).collect();
         let total_pages = (total_results as f64 / per_page as f64).ceil() as i64;
 
@@ -117,3 +185,40 @@ where
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use validator::Validate;
+
+    use super::PageQuery;
+    use crate::errors::ServiceError;
+
+    fn fields(page: Option<i64>, per_page: Option<i64>) -> Vec<String> {
+        match (PageQuery { page, per_page }).validate() {
+            Ok(()) => vec![],
+            Err(e) => ServiceError::from(e)
+                .fields
+                .iter()
+                .map(|field| format!("{} {}: {}", field.field, field.code, field.message))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn pages_are_checked_against_the_limits() {
+        assert!(fields(None, None).is_empty());
+        assert!(fields(Some(1), Some(50)).is_empty());
+
+        assert_eq!(
+            fields(Some(0), Some(0)),
+            [
+                "page too_small: Must be between 1 and 1000000",
+                "per_page too_small: Must be between 1 and 50"
+            ]
+        );
+        assert_eq!(
+            fields(None, Some(51)),
+            ["per_page too_large: Must be between 1 and 50"]
+        );
+    }
+}
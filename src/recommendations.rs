@@ -0,0 +1,549 @@
+// Recomputes everyone's recommendations in the background, so requests only read them
+
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
+
+use crate::{
+    actions::{
+        media::save_search_results,
+        recommendations::{
+            get_last_recommended_at, get_own_reviews, get_public_likes, get_users_with_likes,
+            replace_recommendations, try_lock_batch, unlock_batch, TitleKey,
+        },
+    },
+    errors::ServiceError,
+    models::{MediaCategory, NewMedia, NewRecommendation, RecommendationSource},
+    tmdb::{Movie, Show, TmdbClient},
+    utils::block,
+    Pool,
+};
+
+// How many of a user's favourite titles to ask TMDB for similar titles to
+const SIMILAR_FOR: usize = 5;
+const MAX_NEIGHBOURS: usize = 50;
+const MAX_RECOMMENDATIONS: usize = 50;
+
+/// Who liked what, from public reviews.
+struct Likes {
+    by_user: HashMap<i32, HashSet<TitleKey>>,
+    by_title: HashMap<TitleKey, Vec<i32>>,
+}
+
+impl Likes {
+    fn new(likes: Vec<(i32, TitleKey)>) -> Self {
+        let mut by_user: HashMap<i32, HashSet<TitleKey>> = HashMap::new();
+        let mut by_title: HashMap<TitleKey, Vec<i32>> = HashMap::new();
+
+        for (user_id, key) in likes {
+            by_user.entry(user_id).or_default().insert(key);
+            by_title.entry(key).or_default().push(user_id);
+        }
+
+        Likes { by_user, by_title }
+    }
+}
+
+/// Candidate titles, with how much each of the user's titles contributed to them.
+#[derive(Default)]
+struct Scores(HashMap<TitleKey, (f64, HashMap<TitleKey, f64>)>);
+
+impl Scores {
+    fn add(&mut self, key: TitleKey, because: TitleKey, weight: f64) {
+        let (total, reasons) = self.0.entry(key).or_default();
+        *total += weight;
+        *reasons.entry(because).or_default() += weight;
+    }
+
+    /// Scales totals to between 0 and 1, so sources can be compared.
+    fn normalized(mut self) -> Self {
+        let max = self.0.values().map(|(total, _)| *total).fold(0.0, f64::max);
+
+        if max > 0.0 {
+            for (total, _) in self.0.values_mut() {
+                *total /= max;
+            }
+        }
+
+        self
+    }
+
+    fn total(&self, key: &TitleKey) -> f64 {
+        self.0.get(key).map(|(total, _)| *total).unwrap_or_default()
+    }
+
+    fn top_reason(&self, key: &TitleKey) -> Option<TitleKey> {
+        let (_, reasons) = self.0.get(key)?;
+
+        reasons
+            .iter()
+            .max_by(|a, b| a.1.total_cmp(b.1).then(b.0 .0.cmp(&a.0 .0)))
+            .map(|(because, _)| *because)
+    }
+}
+
+/// Titles liked by people who liked the same things, weighted by how much they overlap and
+/// how picky they are.
+fn community(likes: &Likes, user_id: i32, liked: &HashSet<TitleKey>) -> Scores {
+    let mut overlaps: HashMap<i32, Vec<TitleKey>> = HashMap::new();
+
+    for key in liked {
+        for other in likes.by_title.get(key).into_iter().flatten() {
+            if *other != user_id {
+                overlaps.entry(*other).or_default().push(*key);
+            }
+        }
+    }
+
+    let mut neighbours: Vec<(i32, Vec<TitleKey>)> = overlaps.into_iter().collect();
+    neighbours.sort_by(|a, b| b.1.len().cmp(&a.1.len()).then(a.0.cmp(&b.0)));
+    neighbours.truncate(MAX_NEIGHBOURS);
+
+    let mut scores = Scores::default();
+
+    for (neighbour, shared) in neighbours {
+        let Some(their_likes) = likes.by_user.get(&neighbour) else {
+            continue;
+        };
+        let weight = shared.len() as f64 / (their_likes.len() as f64).sqrt();
+
+        for key in their_likes {
+            for because in &shared {
+                scores.add(*key, *because, weight / shared.len() as f64);
+            }
+        }
+    }
+
+    scores
+}
+
+/// TMDB's similar titles for the user's favourites, higher the closer to the top.
+async fn similar(
+    pool: &Pool,
+    tmdb: &TmdbClient,
+    favourites: &[TitleKey],
+) -> Result<Scores, ServiceError> {
+    let mut scores = Scores::default();
+    let mut cacheable: Vec<NewMedia> = Vec::new();
+
+    for because in favourites {
+        let (tmdb_id, category) = *because;
+
+        let similar: Vec<NewMedia> = match category {
+            MediaCategory::Film => tmdb
+                .similar::<Movie>(category, tmdb_id)
+                .await?
+                .results
+                .iter()
+                .map(NewMedia::from)
+                .collect(),
+            MediaCategory::Show => tmdb
+                .similar::<Show>(category, tmdb_id)
+                .await?
+                .results
+                .iter()
+                .map(NewMedia::from)
+                .collect(),
+        };
+
+        let count = similar.len() as f64;
+        for (rank, media) in similar.iter().enumerate() {
+            scores.add(
+                (media.tmdb_id, media.category),
+                *because,
+                1.0 - rank as f64 / count,
+            );
+        }
+
+        // The same title can be similar to several favourites, but can only be saved once
+        for media in similar {
+            if !cacheable
+                .iter()
+                .any(|m| (m.tmdb_id, m.category) == (media.tmdb_id, media.category))
+            {
+                cacheable.push(media);
+            }
+        }
+    }
+
+    if !cacheable.is_empty() {
+        let pool = pool.clone();
+        block(move || {
+            let mut conn = pool.get()?;
+            save_search_results(&mut conn, &cacheable)
+        })
+        .await??;
+    }
+
+    Ok(scores)
+}
+
+async fn recommend(
+    pool: &Pool,
+    tmdb: &TmdbClient,
+    likes: &Likes,
+    user_id: i32,
+) -> Result<Vec<NewRecommendation>, ServiceError> {
+    let own_pool = pool.clone();
+    let mut own = block(move || {
+        let mut conn = own_pool.get()?;
+        get_own_reviews(&mut conn, user_id)
+    })
+    .await??;
+
+    // Anything already reviewed is out, whether it was dropped or not
+    let reviewed: HashSet<TitleKey> = own.iter().map(|review| review.key).collect();
+
+    own.sort_by(|a, b| b.fun.cmp(&a.fun).then(b.updated_at.cmp(&a.updated_at)));
+    let mut favourites: Vec<TitleKey> = Vec::new();
+    for review in own.iter().filter(|review| review.liked) {
+        if !favourites.contains(&review.key) {
+            favourites.push(review.key);
+        }
+    }
+    let liked: HashSet<TitleKey> = favourites.iter().copied().collect();
+    favourites.truncate(SIMILAR_FOR);
+
+    let community = community(likes, user_id, &liked);
+    // Community picks don't need TMDB, so they're still worth saving on their own
+    let similar = match similar(pool, tmdb, &favourites).await {
+        Ok(similar) => similar,
+        Err(e) => {
+            tracing::warn!("Couldn't find similar titles for user {user_id}: {e}");
+            Scores::default()
+        }
+    };
+
+    Ok(rank(user_id, community, similar, &reviewed))
+}
+
+/// Combines both sources into the best recommendations, leaving out anything `reviewed`.
+fn rank(
+    user_id: i32,
+    community: Scores,
+    similar: Scores,
+    reviewed: &HashSet<TitleKey>,
+) -> Vec<NewRecommendation> {
+    let community = community.normalized();
+    let similar = similar.normalized();
+
+    let candidates: HashSet<TitleKey> = community
+        .0
+        .keys()
+        .chain(similar.0.keys())
+        .filter(|key| !reviewed.contains(key))
+        .copied()
+        .collect();
+
+    let mut recommendations: Vec<NewRecommendation> = candidates
+        .into_iter()
+        .filter_map(|key| {
+            let (from_community, from_similar) = (community.total(&key), similar.total(&key));
+            let (source, because) = if from_community >= from_similar {
+                (RecommendationSource::Community, community.top_reason(&key)?)
+            } else {
+                (RecommendationSource::Similar, similar.top_reason(&key)?)
+            };
+
+            Some(NewRecommendation {
+                user_id,
+                tmdb_id: key.0,
+                category: key.1,
+                score: from_community + from_similar,
+                source,
+                because_tmdb_id: because.0,
+                because_category: because.1,
+            })
+        })
+        .collect();
+
+    recommendations.sort_by(|a, b| b.score.total_cmp(&a.score).then(a.tmdb_id.cmp(&b.tmdb_id)));
+    recommendations.truncate(MAX_RECOMMENDATIONS);
+
+    recommendations
+}
+
+/// Recomputes recommendations for everyone who's liked something. A user failing, e.g. on a
+/// database error, keeps their previous recommendations. Without TMDB, users only get
+/// recommendations from the community.
+pub async fn run_batch(pool: &Pool, tmdb: &TmdbClient) -> Result<usize, ServiceError> {
+    let batch_pool = pool.clone();
+    let (public_likes, users) = block(move || {
+        let mut conn = batch_pool.get()?;
+        Ok::<_, crate::errors::DbError>((
+            get_public_likes(&mut conn)?,
+            get_users_with_likes(&mut conn)?,
+        ))
+    })
+    .await??;
+
+    let likes = Likes::new(public_likes);
+    let mut updated = 0;
+
+    for user_id in users {
+        let recommendations = match recommend(pool, tmdb, &likes, user_id).await {
+            Ok(recommendations) => recommendations,
+            Err(e) => {
+                tracing::warn!("Couldn't recommend for user {user_id}: {e}");
+                continue;
+            }
+        };
+
+        let pool = pool.clone();
+        let replaced = block(move || {
+            let mut conn = pool.get()?;
+            replace_recommendations(&mut conn, user_id, recommendations)
+        })
+        .await;
+
+        match replaced {
+            Ok(Ok(_)) => updated += 1,
+            Ok(Err(e)) => tracing::warn!("Couldn't save recommendations for user {user_id}: {e}"),
+            Err(e) => tracing::warn!("Couldn't save recommendations for user {user_id}: {e}"),
+        }
+    }
+
+    Ok(updated)
+}
+
+/// Runs the batch unless another instance is, or one finished within half of `period`.
+/// Returns how many users were updated, or `None` if it was skipped.
+async fn run_scheduled(
+    pool: &Pool,
+    tmdb: &TmdbClient,
+    period: Duration,
+) -> Result<Option<usize>, ServiceError> {
+    let recent =
+        chrono::Duration::from_std(period / 2).unwrap_or_else(|_| chrono::Duration::max_value());
+
+    let lock_pool = pool.clone();
+    let lock = block(move || {
+        let mut conn = lock_pool.get()?;
+        if !try_lock_batch(&mut conn)? {
+            return Ok(None);
+        }
+
+        let ran_recently = get_last_recommended_at(&mut conn)
+            .map(|last| last.is_some_and(|last| chrono::Utc::now() - last < recent));
+        match ran_recently {
+            Ok(false) => Ok(Some(conn)),
+            Ok(true) => unlock_batch(&mut conn).map(|_| None),
+            Err(e) => unlock_batch(&mut conn).and(Err(e)),
+        }
+    })
+    .await??;
+
+    // The lock is this connection's, so it's kept out of the pool until the batch is done
+    let Some(mut conn) = lock else {
+        return Ok(None);
+    };
+    let updated = run_batch(pool, tmdb).await;
+    block(move || unlock_batch(&mut conn)).await??;
+
+    updated.map(Some)
+}
+
+/// Runs the batch now and then every `interval_hours`, 0 turns it off. With several instances,
+/// only one runs each batch.
+pub async fn schedule(pool: Pool, tmdb: TmdbClient, interval_hours: u64) {
+    let hours = interval_hours;
+
+    if hours == 0 {
+        return;
+    }
+
+    let period = Duration::from_secs(hours * 60 * 60);
+    let mut interval = actix_web::rt::time::interval(period);
+
+    loop {
+        interval.tick().await;
+
+        match run_scheduled(&pool, &tmdb, period).await {
+            Ok(Some(updated)) => tracing::info!("Recommended for {updated} users"),
+            Ok(None) => tracing::debug!("Another instance is recommending, or just has"),
+            Err(e) => tracing::error!("Couldn't recommend: {e}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashSet, time::Duration};
+
+    use diesel::prelude::*;
+
+    use super::{community, rank, run_batch, run_scheduled, Likes, Scores};
+    use crate::{
+        actions::recommendations::{replace_recommendations, try_lock_batch, unlock_batch},
+        cache::ResponseCache,
+        config::Secret,
+        models::{MediaCategory, NewRecommendation, RecommendationSource, ReviewVisibility},
+        schema::recommendations,
+        testing,
+        tmdb::{TmdbClient, TmdbConfig},
+    };
+
+    fn film(tmdb_id: i32) -> (i32, MediaCategory) {
+        (tmdb_id, MediaCategory::Film)
+    }
+
+    #[test]
+    fn normalizes_to_the_best_score() {
+        let mut scores = Scores::default();
+        scores.add(film(1), film(10), 4.0);
+        scores.add(film(2), film(10), 1.0);
+        scores.add(film(2), film(11), 1.0);
+
+        let scores = scores.normalized();
+        assert_eq!(scores.total(&film(1)), 1.0);
+        assert_eq!(scores.total(&film(2)), 0.5);
+        assert_eq!(scores.total(&film(3)), 0.0);
+
+        assert!(Scores::default().normalized().0.is_empty());
+    }
+
+    #[test]
+    fn top_reason_breaks_ties_by_lowest_id() {
+        let mut scores = Scores::default();
+        scores.add(film(1), film(12), 1.0);
+        scores.add(film(1), film(11), 1.0);
+        scores.add(film(1), film(13), 0.5);
+
+        assert_eq!(scores.top_reason(&film(1)), Some(film(11)));
+
+        scores.add(film(1), film(13), 1.0);
+        assert_eq!(scores.top_reason(&film(1)), Some(film(13)));
+        assert_eq!(scores.top_reason(&film(2)), None);
+    }
+
+    #[test]
+    fn community_follows_people_who_liked_the_same_things() {
+        let likes = Likes::new(vec![
+            (1, film(10)),
+            (2, film(10)),
+            (2, film(20)),
+            (3, film(30)),
+        ]);
+        let liked = HashSet::from([film(10)]);
+
+        let scores = community(&likes, 1, &liked);
+        assert!(scores.total(&film(20)) > 0.0);
+        assert_eq!(scores.top_reason(&film(20)), Some(film(10)));
+        // Nobody who liked 10 liked 30
+        assert_eq!(scores.total(&film(30)), 0.0);
+    }
+
+    #[test]
+    fn ranks_without_reviewed_titles() {
+        let mut community = Scores::default();
+        community.add(film(1), film(10), 2.0);
+        community.add(film(2), film(10), 2.0);
+        community.add(film(3), film(10), 1.0);
+        let mut similar = Scores::default();
+        similar.add(film(3), film(11), 1.0);
+        similar.add(film(4), film(11), 0.5);
+
+        let reviewed = HashSet::from([film(1)]);
+        let ranked = rank(7, community, similar, &reviewed);
+
+        let ids: Vec<i32> = ranked.iter().map(|r| r.tmdb_id).collect();
+        // 3 has 0.5 from the community and 1 from similar, 2 has 1 from the community
+        assert_eq!(ids, vec![3, 2, 4]);
+        assert!(ranked.iter().all(|r| r.user_id == 7));
+
+        assert_eq!(ranked[0].source, RecommendationSource::Similar);
+        assert_eq!(ranked[0].because_tmdb_id, 11);
+        assert_eq!(ranked[1].source, RecommendationSource::Community);
+        assert_eq!(ranked[1].because_tmdb_id, 10);
+    }
+
+    #[test]
+    fn ranks_ties_by_lowest_id() {
+        let mut community = Scores::default();
+        community.add(film(5), film(10), 1.0);
+        community.add(film(4), film(10), 1.0);
+
+        let ranked = rank(7, community, Scores::default(), &HashSet::new());
+        let ids: Vec<i32> = ranked.iter().map(|r| r.tmdb_id).collect();
+        assert_eq!(ids, vec![4, 5]);
+    }
+
+    /// Nothing listens there, so every request fails.
+    fn offline_tmdb() -> TmdbClient {
+        let mut config = TmdbConfig::default();
+        config.base_url = "http://127.0.0.1:9".to_string();
+        config.api_key = Some("k".parse::<Secret>().unwrap());
+        config.max_retries = 0;
+        config.rate_limit = 0;
+        TmdbClient::new(config, ResponseCache::memory(10))
+    }
+
+    #[actix_web::test]
+    async fn community_picks_are_saved_without_tmdb() {
+        let Some(pool) = testing::pool() else { return };
+        let mut conn = pool.get().unwrap();
+        let (fan, other) = (
+            testing::user(&mut conn, "fan"),
+            testing::user(&mut conn, "other"),
+        );
+        testing::review(&mut conn, fan, 1, None, ReviewVisibility::Public);
+        testing::review(&mut conn, fan, 2, None, ReviewVisibility::Public);
+        testing::review(&mut conn, other, 1, None, ReviewVisibility::Public);
+        drop(conn);
+
+        assert_eq!(run_batch(&pool, &offline_tmdb()).await.unwrap(), 2);
+
+        let saved: Vec<(i32, i32, RecommendationSource)> = recommendations::table
+            .select((
+                recommendations::user_id,
+                recommendations::tmdb_id,
+                recommendations::source,
+            ))
+            .load(&mut pool.get().unwrap())
+            .unwrap();
+        assert_eq!(saved, vec![(other, 2, RecommendationSource::Community)]);
+    }
+
+    #[actix_web::test]
+    async fn scheduled_batches_skip_when_another_instance_has_it() {
+        let (Some(pool), Some(mut other)) = (testing::pool(), testing::conn()) else {
+            return;
+        };
+        let hour = Duration::from_secs(60 * 60);
+
+        assert!(try_lock_batch(&mut other).unwrap());
+        assert_eq!(
+            run_scheduled(&pool, &offline_tmdb(), hour).await.unwrap(),
+            None
+        );
+        unlock_batch(&mut other).unwrap();
+
+        let mut conn = pool.get().unwrap();
+        let user = testing::user(&mut conn, "recommended");
+        replace_recommendations(
+            &mut conn,
+            user,
+            vec![NewRecommendation {
+                user_id: user,
+                tmdb_id: 2,
+                category: MediaCategory::Film,
+                score: 1.0,
+                source: RecommendationSource::Community,
+                because_tmdb_id: 1,
+                because_category: MediaCategory::Film,
+            }],
+        )
+        .unwrap();
+        drop(conn);
+
+        // Just ran elsewhere
+        assert_eq!(
+            run_scheduled(&pool, &offline_tmdb(), hour).await.unwrap(),
+            None
+        );
+        // The lock was let go both times
+        assert!(try_lock_batch(&mut other).unwrap());
+        unlock_batch(&mut other).unwrap();
+    }
+}
@@ -0,0 +1,324 @@
+// Every API endpoint is registered from these tables, so what's served can be checked against the
+// OpenAPI document. A new version starts from the last one and only replaces what changed, e.g.
+// `with_overrides(v1(), vec![Endpoint::new(Method::PATCH, "/reviews/{category}/{tmdb_id}", ..)])`
+// mounted with `version("/v2", ..)`.
+
+use actix_web::{
+    dev::{HttpServiceFactory, Service},
+    guard,
+    http::{
+        header::{HeaderName, HeaderValue, LINK},
+        Method,
+    },
+    web, FromRequest, Handler, Resource, Responder, Scope,
+};
+
+use crate::{
+    constants::{LEGACY_DEPRECATION, LEGACY_SUNSET, MAX_IMPORT_BYTES},
+    handlers::{admin, auth, browse, imports, reviews, search, titles, users},
+};
+
+/// One method on one path, as its own resource like the route macros make.
+// Method and path are only read by tests until a version overrides anything
+#[cfg_attr(not(test), allow(dead_code))]
+pub struct Endpoint {
+    pub method: Method,
+    pub path: &'static str,
+    resource: Resource,
+}
+
+impl Endpoint {
+    pub fn new<F, Args>(method: Method, path: &'static str, handler: F) -> Self
+    where
+        F: Handler<Args>,
+        Args: FromRequest + 'static,
+        F::Output: Responder + 'static,
+    {
+        let resource = web::resource(path)
+            .guard(guard::Method(method.clone()))
+            .to(handler);
+
+        Endpoint {
+            method,
+            path,
+            resource,
+        }
+    }
+
+    /// Raises the body size limit for `web::Bytes` bodies.
+    fn payload_limit(mut self, bytes: usize) -> Self {
+        self.resource = self.resource.app_data(web::PayloadConfig::new(bytes));
+        self
+    }
+}
+
+/// Every endpoint of v1.
+pub fn v1() -> Vec<Endpoint> {
+    vec![
+        Endpoint::new(Method::GET, "/auth", auth::me),
+        Endpoint::new(Method::POST, "/auth", auth::login),
+        Endpoint::new(Method::DELETE, "/auth", auth::logout),
+        Endpoint::new(Method::GET, "/users", users::get_users),
+        Endpoint::new(Method::POST, "/users", users::post_users),
+        Endpoint::new(Method::GET, "/users/{id}", users::get_users_id),
+        Endpoint::new(Method::PATCH, "/users/{id}", users::patch_users_id),
+        Endpoint::new(Method::DELETE, "/users/{id}", users::delete_users_id),
+        Endpoint::new(
+            Method::POST,
+            "/users/{id}/follow",
+            users::post_users_id_follow,
+        ),
+        Endpoint::new(
+            Method::DELETE,
+            "/users/{id}/follow",
+            users::delete_users_id_follow,
+        ),
+        Endpoint::new(
+            Method::GET,
+            "/users/{id}/followers",
+            users::get_users_id_followers,
+        ),
+        Endpoint::new(
+            Method::PUT,
+            "/users/{id}/followers/{follower_id}",
+            users::put_users_id_followers,
+        ),
+        Endpoint::new(
+            Method::DELETE,
+            "/users/{id}/followers/{follower_id}",
+            users::delete_users_id_followers,
+        ),
+        Endpoint::new(
+            Method::POST,
+            "/users/{id}/report",
+            users::post_users_id_report,
+        ),
+        Endpoint::new(
+            Method::POST,
+            "/users/{id}/block",
+            users::post_users_id_block,
+        ),
+        Endpoint::new(
+            Method::DELETE,
+            "/users/{id}/block",
+            users::delete_users_id_block,
+        ),
+        Endpoint::new(
+            Method::GET,
+            "/users/{id}/blocks",
+            users::get_users_id_blocks,
+        ),
+        Endpoint::new(Method::POST, "/users/{id}/mute", users::post_users_id_mute),
+        Endpoint::new(
+            Method::DELETE,
+            "/users/{id}/mute",
+            users::delete_users_id_mute,
+        ),
+        Endpoint::new(Method::GET, "/users/{id}/mutes", users::get_users_id_mutes),
+        Endpoint::new(
+            Method::GET,
+            "/users/{id}/export",
+            users::get_users_id_export,
+        ),
+        Endpoint::new(
+            Method::GET,
+            "/users/{id}/recommendations",
+            users::get_users_id_recommendations,
+        ),
+        Endpoint::new(Method::GET, "/search", search::search_all),
+        Endpoint::new(Method::GET, "/search/Film", search::search_movies),
+        Endpoint::new(Method::GET, "/search/Show", search::search_shows),
+        Endpoint::new(Method::GET, "/browse/trending", browse::get_trending),
+        Endpoint::new(Method::GET, "/browse/popular", browse::get_popular),
+        Endpoint::new(Method::GET, "/browse/discover", browse::get_discover),
+        Endpoint::new(Method::GET, "/media/Film/{id}", titles::get_film),
+        Endpoint::new(Method::GET, "/media/Show/{id}", titles::get_show),
+        Endpoint::new(
+            Method::GET,
+            "/media/Show/{id}/seasons/{season}",
+            titles::get_show_season,
+        ),
+        Endpoint::new(Method::GET, "/reviews", reviews::get_reviews),
+        Endpoint::new(Method::POST, "/reviews", reviews::post_reviews),
+        Endpoint::new(
+            Method::PATCH,
+            "/reviews/{category}/{tmdb_id}",
+            reviews::patch_reviews,
+        ),
+        Endpoint::new(
+            Method::PATCH,
+            "/reviews/{category}/{tmdb_id}/{season}",
+            reviews::patch_reviews_season,
+        ),
+        Endpoint::new(
+            Method::DELETE,
+            "/reviews/{category}/{tmdb_id}",
+            reviews::delete_reviews,
+        ),
+        Endpoint::new(
+            Method::DELETE,
+            "/reviews/{category}/{tmdb_id}/{season}",
+            reviews::delete_reviews_season,
+        ),
+        Endpoint::new(
+            Method::POST,
+            "/reviews/{user_id}/{category}/{tmdb_id}/report",
+            reviews::post_reviews_report,
+        ),
+        Endpoint::new(
+            Method::POST,
+            "/reviews/{user_id}/{category}/{tmdb_id}/{season}/report",
+            reviews::post_reviews_season_report,
+        ),
+        Endpoint::new(Method::POST, "/import/{source}", imports::post_import)
+            .payload_limit(MAX_IMPORT_BYTES),
+        Endpoint::new(Method::GET, "/import/{id}", imports::get_import),
+        Endpoint::new(Method::GET, "/admin/reports", admin::get_reports),
+        Endpoint::new(
+            Method::PATCH,
+            "/admin/reports/{id}",
+            admin::patch_reports_id,
+        ),
+        Endpoint::new(Method::PATCH, "/admin/users/{id}", admin::patch_users_id),
+        Endpoint::new(
+            Method::PATCH,
+            "/admin/reviews/{user_id}/{category}/{tmdb_id}",
+            admin::patch_reviews,
+        ),
+        Endpoint::new(
+            Method::PATCH,
+            "/admin/reviews/{user_id}/{category}/{tmdb_id}/{season}",
+            admin::patch_reviews_season,
+        ),
+        Endpoint::new(Method::DELETE, "/admin/cache", admin::delete_cache),
+    ]
+}
+
+/// `base` with every endpoint that has the same method and path as one of `overrides` replaced by
+/// it, in place so routes keep their precedence. Overrides of nothing are added at the end.
+#[cfg_attr(not(test), allow(dead_code))]
+pub fn with_overrides(mut base: Vec<Endpoint>, overrides: Vec<Endpoint>) -> Vec<Endpoint> {
+    for endpoint in overrides {
+        match base
+            .iter()
+            .position(|e| e.method == endpoint.method && e.path == endpoint.path)
+        {
+            Some(i) => base[i] = endpoint,
+            None => base.push(endpoint),
+        }
+    }
+    base
+}
+
+fn version(prefix: &str, endpoints: Vec<Endpoint>) -> Scope {
+    endpoints
+        .into_iter()
+        .fold(web::scope(prefix), |scope, endpoint| {
+            scope.service(endpoint.resource)
+        })
+}
+
+/// v1 without the prefix, with `Deprecation` and `Sunset` headers and a link to the same path
+/// under `/v1`.
+fn legacy(endpoints: Vec<Endpoint>) -> impl HttpServiceFactory {
+    let scope = web::scope("").wrap_fn(|req, srv| {
+        let successor =
+            HeaderValue::from_str(&format!("</v1{}>; rel=\"successor-version\"", req.path()));
+        let res = srv.call(req);
+
+        async move {
+            let mut res = res.await?;
+            // Paths that aren't API routes fall through to the 404 handler, leave those alone
+            if res.request().match_pattern().is_some() {
+                let headers = res.headers_mut();
+                headers.insert(
+                    HeaderName::from_static("deprecation"),
+                    HeaderValue::from_static(LEGACY_DEPRECATION),
+                );
+                headers.insert(
+                    HeaderName::from_static("sunset"),
+                    HeaderValue::from_static(LEGACY_SUNSET),
+                );
+                if let Ok(successor) = successor {
+                    headers.insert(LINK, successor);
+                }
+            }
+            Ok(res)
+        }
+    });
+
+    endpoints
+        .into_iter()
+        .fold(scope, |scope, endpoint| scope.service(endpoint.resource))
+}
+
+/// Registers the API, which has to come after every other service since the legacy routes are
+/// matched with an empty prefix.
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(version("/v1", v1())).service(legacy(v1()));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{legacy, version, with_overrides, Endpoint};
+    use actix_web::{http::Method, test, App};
+
+    #[actix_web::test]
+    async fn overrides_replace_only_their_endpoint() {
+        let v1 = || {
+            vec![
+                Endpoint::new(Method::GET, "/a", || async { "v1 a" }),
+                Endpoint::new(Method::GET, "/b", || async { "v1 b" }),
+            ]
+        };
+        let v2 = with_overrides(
+            v1(),
+            vec![
+                Endpoint::new(Method::GET, "/a", || async { "v2 a" }),
+                Endpoint::new(Method::GET, "/c", || async { "v2 c" }),
+            ],
+        );
+        assert_eq!(v2.len(), 3);
+
+        let app = test::init_service(
+            App::new()
+                .service(version("/v1", v1()))
+                .service(version("/v2", v2)),
+        )
+        .await;
+        for (path, expected) in [
+            ("/v1/a", "v1 a"),
+            ("/v2/a", "v2 a"),
+            ("/v2/b", "v1 b"),
+            ("/v2/c", "v2 c"),
+        ] {
+            let req = test::TestRequest::get().uri(path).to_request();
+            let body = test::call_and_read_body(&app, req).await;
+            assert_eq!(body, expected, "{path}");
+        }
+    }
+
+    #[actix_web::test]
+    async fn legacy_routes_are_deprecated() {
+        let app = test::init_service(App::new().service(legacy(vec![Endpoint::new(
+            Method::GET,
+            "/a/{id}",
+            || async { "a" },
+        )])))
+        .await;
+
+        let req = test::TestRequest::get().uri("/a/1").to_request();
+        let res = test::call_service(&app, req).await;
+        let headers = res.headers();
+        assert!(headers.contains_key("deprecation"));
+        assert!(headers.contains_key("sunset"));
+        assert_eq!(
+            headers.get("link").unwrap(),
+            "</v1/a/1>; rel=\"successor-version\""
+        );
+
+        let req = test::TestRequest::get().uri("/b").to_request();
+        let res = test::call_service(&app, req).await;
+        assert!(!res.headers().contains_key("deprecation"));
+    }
+}
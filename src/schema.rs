@@ -1,19 +1,154 @@
 // @generated automatically by Diesel CLI.
 
 pub mod sql_types {
-    #[derive(diesel::sql_types::SqlType)]
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "import_source"))]
+    pub struct ImportSource;
+
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "job_status"))]
+    pub struct JobStatus;
+
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
     #[diesel(postgres_type(name = "media_category"))]
     pub struct MediaCategory;
 
-    #[derive(diesel::sql_types::SqlType)]
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "recommendation_source"))]
+    pub struct RecommendationSource;
+
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "report_reason"))]
+    pub struct ReportReason;
+
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "report_status"))]
+    pub struct ReportStatus;
+
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "review_visibility"))]
+    pub struct ReviewVisibility;
+
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "user_role"))]
+    pub struct UserRole;
+
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
     #[diesel(postgres_type(name = "watch_status"))]
     pub struct WatchStatus;
 }
 
+diesel::table! {
+    blocks (user_id, blocked_id) {
+        user_id -> Int4,
+        blocked_id -> Int4,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    follows (follower_id, followee_id) {
+        follower_id -> Int4,
+        followee_id -> Int4,
+        accepted -> Bool,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::ImportSource;
+    use super::sql_types::JobStatus;
+
+    import_jobs (id) {
+        id -> Int4,
+        user_id -> Int4,
+        source -> ImportSource,
+        status -> JobStatus,
+        dry_run -> Bool,
+        total -> Int4,
+        processed -> Int4,
+        imported -> Int4,
+        skipped -> Int4,
+        unmatched -> Array<Text>,
+        error -> Nullable<Text>,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::MediaCategory;
+
+    media (tmdb_id, category) {
+        tmdb_id -> Int4,
+        category -> MediaCategory,
+        title -> Text,
+        original_title -> Text,
+        original_language -> Text,
+        release_date -> Nullable<Date>,
+        overview -> Text,
+        poster_path -> Nullable<Text>,
+        number_of_seasons -> Nullable<Int4>,
+        number_of_episodes -> Nullable<Int4>,
+        details_fetched_at -> Nullable<Timestamptz>,
+        updated_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    mutes (user_id, muted_id) {
+        user_id -> Int4,
+        muted_id -> Int4,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::MediaCategory;
+    use super::sql_types::RecommendationSource;
+
+    recommendations (user_id, tmdb_id, category) {
+        user_id -> Int4,
+        tmdb_id -> Int4,
+        category -> MediaCategory,
+        score -> Float8,
+        source -> RecommendationSource,
+        because_tmdb_id -> Int4,
+        because_category -> MediaCategory,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::MediaCategory;
+    use super::sql_types::ReportReason;
+    use super::sql_types::ReportStatus;
+
+    reports (id) {
+        id -> Int4,
+        reporter_id -> Int4,
+        user_id -> Int4,
+        tmdb_id -> Nullable<Int4>,
+        category -> Nullable<MediaCategory>,
+        season -> Nullable<Int4>,
+        reason -> ReportReason,
+        details -> Text,
+        status -> ReportStatus,
+        resolved_by -> Nullable<Int4>,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+    }
+}
+
 diesel::table! {
     use diesel::sql_types::*;
     use super::sql_types::MediaCategory;
     use super::sql_types::WatchStatus;
+    use super::sql_types::ReviewVisibility;
 
     reviews (user_id, tmdb_id, category, season) {
         user_id -> Int4,
@@ -27,10 +162,15 @@ diesel::table! {
         created_at -> Timestamptz,
         updated_at -> Timestamptz,
         season -> Int4,
+        visibility -> ReviewVisibility,
+        hidden -> Bool,
     }
 }
 
 diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::UserRole;
+
     users (id) {
         id -> Int4,
         name -> Text,
@@ -38,10 +178,23 @@ diesel::table! {
         hash -> Text,
         created_at -> Timestamptz,
         updated_at -> Timestamptz,
+        private -> Bool,
+        role -> UserRole,
+        suspended_at -> Nullable<Timestamptz>,
     }
 }
 
+diesel::joinable!(import_jobs -> users (user_id));
+diesel::joinable!(recommendations -> users (user_id));
+
 diesel::allow_tables_to_appear_in_same_query!(
+    blocks,
+    follows,
+    import_jobs,
+    media,
+    mutes,
+    recommendations,
+    reports,
     reviews,
     users,
 );
@@ -0,0 +1,178 @@
+// Structured logs and metrics, with every request in a span tagged by its ID
+
+use std::{
+    future::{ready, Ready},
+    str::FromStr,
+    time::Instant,
+};
+
+use actix_web::{
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    http::header::{HeaderName, HeaderValue},
+    Error,
+};
+use futures_util::future::LocalBoxFuture;
+use serde::Deserialize;
+use tracing::Instrument;
+use tracing_subscriber::{fmt::format::FmtSpan, EnvFilter};
+
+use crate::metrics;
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+// Longer incoming IDs are replaced rather than trusted
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+/// The ID of the request being handled, `None` outside of one.
+pub fn request_id() -> Option<String> {
+    REQUEST_ID.try_with(|id| id.clone()).ok()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Json,
+    /// Human readable, for local dev
+    Pretty,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(LogFormat::Json),
+            "pretty" => Ok(LogFormat::Pretty),
+            other => Err(format!("must be json or pretty, not {other}")),
+        }
+    }
+}
+
+/// The `[log]` config section.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    pub format: LogFormat,
+    /// Which spans and events to keep, in `RUST_LOG` syntax
+    pub filter: String,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        LogConfig {
+            format: LogFormat::Json,
+            filter: "info".to_string(),
+        }
+    }
+}
+
+/// Installs the global subscriber. Records from the `log` crate, e.g. actix's, go through it
+/// too.
+pub fn init(config: &LogConfig) {
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::new(&config.filter))
+        // Closing spans logs how long they took
+        .with_span_events(FmtSpan::CLOSE);
+
+    match config.format {
+        LogFormat::Json => builder.json().init(),
+        LogFormat::Pretty => builder.init(),
+    }
+}
+
+fn incoming_id(req: &ServiceRequest) -> Option<String> {
+    let id = req.headers().get(REQUEST_ID_HEADER)?.to_str().ok()?;
+
+    let valid = !id.is_empty()
+        && id.len() <= MAX_REQUEST_ID_LEN
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+
+    valid.then(|| id.to_string())
+}
+
+/// Gives every request an ID, reusing the caller's `X-Request-Id` if it's sensible, and
+/// handles it inside a `request` span. The ID is echoed back in `X-Request-Id`, and the
+/// request is counted and timed.
+pub struct RequestTracing;
+
+impl<S, B> Transform<S, ServiceRequest> for RequestTracing
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = RequestTracingMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequestTracingMiddleware { service }))
+    }
+}
+
+pub struct RequestTracingMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for RequestTracingMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let id = incoming_id(&req).unwrap_or_else(|| format!("{:032x}", rand::random::<u128>()));
+
+        let span = tracing::info_span!(
+            "request",
+            request_id = %id,
+            method = %req.method(),
+            path = %req.path(),
+            status = tracing::field::Empty,
+        );
+
+        let method = req.method().to_string();
+        let started = Instant::now();
+
+        let fut = REQUEST_ID.sync_scope(id.clone(), || span.in_scope(|| self.service.call(req)));
+
+        let handled = async move {
+            let mut res = fut.await?;
+
+            let status = res.status();
+            tracing::Span::current().record("status", status.as_u16());
+            if status.is_server_error() {
+                tracing::error!("Responded {status}");
+            }
+
+            // Patterns rather than paths, so IDs don't each get their own series
+            let route = res.request().match_pattern();
+            metrics::observe_request(
+                &method,
+                route.as_deref().unwrap_or("unmatched"),
+                status.as_u16(),
+                started.elapsed(),
+            );
+
+            if let Ok(value) = HeaderValue::from_str(&request_id().unwrap_or_default()) {
+                res.headers_mut()
+                    .insert(HeaderName::from_static(REQUEST_ID_HEADER), value);
+            }
+
+            Ok(res)
+        };
+
+        Box::pin(REQUEST_ID.scope(id, handled.instrument(span)))
+    }
+}
@@ -0,0 +1,123 @@
+// Fixtures for tests that need Postgres, which only run when TEST_DATABASE_URL is set
+
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    OnceLock,
+};
+
+use diesel::{
+    prelude::*,
+    r2d2::{self, ConnectionManager},
+};
+
+use crate::{
+    migrations,
+    models::{MediaCategory, NewFollow, NewReview, NewUser, ReviewVisibility, WatchStatus},
+    schema::{follows, reviews, users},
+    Pool, PooledConn,
+};
+
+static MIGRATED: OnceLock<()> = OnceLock::new();
+
+// Emails are unique, even across tests running at the same time
+static USERS: AtomicUsize = AtomicUsize::new(0);
+
+/// A migrated connection inside a transaction that's rolled back when it's dropped, or `None`
+/// without a test database, in which case the test should return early.
+pub fn conn() -> Option<PooledConn> {
+    pool().map(|pool| pool.get().unwrap())
+}
+
+/// A pool of one connection, inside a transaction that's rolled back when the pool is dropped.
+/// For code that takes a `Pool` rather than a connection.
+pub fn pool() -> Option<Pool> {
+    let Ok(url) = std::env::var("TEST_DATABASE_URL") else {
+        eprintln!("Skipped, TEST_DATABASE_URL isn't set");
+        return None;
+    };
+
+    // A pool of its own, since pools hand connections in a test transaction out again
+    let pool: Pool = r2d2::Pool::builder()
+        .max_size(1)
+        .build(ConnectionManager::new(url))
+        .expect("Failed to connect to TEST_DATABASE_URL");
+    let mut conn = pool.get().unwrap();
+
+    MIGRATED.get_or_init(|| {
+        migrations::up(&mut conn).expect("Failed to migrate the test database");
+    });
+    conn.begin_test_transaction().unwrap();
+    drop(conn);
+    Some(pool)
+}
+
+/// A public user, returning their id.
+pub fn user(conn: &mut PooledConn, name: &str) -> i32 {
+    let n = USERS.fetch_add(1, Ordering::Relaxed);
+    let email = format!("{name}.{}.{n}@test.io", std::process::id());
+
+    diesel::insert_into(users::table)
+        .values(NewUser {
+            name,
+            email: &email,
+            hash: "",
+        })
+        .returning(users::id)
+        .get_result(conn)
+        .unwrap()
+}
+
+pub fn make_private(conn: &mut PooledConn, id: i32) {
+    diesel::update(users::table.find(id))
+        .set(users::private.eq(true))
+        .execute(conn)
+        .unwrap();
+}
+
+pub fn suspend(conn: &mut PooledConn, id: i32) {
+    diesel::update(users::table.find(id))
+        .set(users::suspended_at.eq(Some(chrono::Utc::now())))
+        .execute(conn)
+        .unwrap();
+}
+
+pub fn follow(conn: &mut PooledConn, follower_id: i32, followee_id: i32, accepted: bool) {
+    diesel::insert_into(follows::table)
+        .values(NewFollow {
+            follower_id,
+            followee_id,
+            accepted,
+        })
+        .execute(conn)
+        .unwrap();
+}
+
+/// A completed review of a film, or of a season when there is one.
+pub fn review(
+    conn: &mut PooledConn,
+    user_id: i32,
+    tmdb_id: i32,
+    season: Option<i32>,
+    visibility: ReviewVisibility,
+) {
+    let category = match season {
+        Some(_) => MediaCategory::Show,
+        None => MediaCategory::Film,
+    };
+
+    diesel::insert_into(reviews::table)
+        .values(NewReview {
+            user_id,
+            tmdb_id,
+            category,
+            season,
+            status: WatchStatus::Completed,
+            text: "",
+            fun_before: false,
+            fun_during: true,
+            fun_after: false,
+            visibility,
+        })
+        .execute(conn)
+        .unwrap();
+}
@@ -0,0 +1,1069 @@
+use std::{
+    sync::{Arc, Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+
+use actix_web::rt::time::sleep;
+use awc::{
+    error::SendRequestError,
+    http::{header::RETRY_AFTER, StatusCode},
+};
+use serde::{
+    de::{DeserializeOwned, IgnoredAny},
+    Deserialize, Deserializer, Serialize,
+};
+use utoipa::{IntoParams, ToSchema};
+use validator::Validate;
+
+use crate::{
+    actions::reviews::{FilmWithStats, ShowWithStats},
+    cache::ResponseCache,
+    config::Secret,
+    errors::ServiceError,
+    handlers::search::SearchResult,
+    media::Validation,
+    metrics,
+    models::MediaCategory,
+};
+
+const DEFAULT_BASE_URL: &str = "https://api.themoviedb.org/3";
+const DEFAULT_LANG: &str = "en-US";
+// Don't let TMDB hold a request open for longer than this, between Retry-After and the backoff
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(10);
+const SEARCH_FILM_PATH: &str = "/search/movie";
+const SEARCH_SHOW_PATH: &str = "/search/tv";
+const SEARCH_MULTI_PATH: &str = "/search/multi";
+const FILM_PATH: &str = "/movie";
+const SHOW_PATH: &str = "/tv";
+const SEASON_PATH: &str = "/season";
+const TRENDING_PATH: &str = "/trending";
+const POPULAR_PATH: &str = "/popular";
+const DISCOVER_PATH: &str = "/discover";
+const SIMILAR_PATH: &str = "/similar";
+const CONFIGURATION_PATH: &str = "/configuration";
+const API_PARAM: &str = "api_key";
+const QUERY_PARAM: &str = "query";
+const PAGE_PARAM: &str = "page";
+const LANG_PARAM: &str = "language";
+const FILM_YEAR_PARAM: &str = "year"; // primary_release_year is an alternative?
+const SHOW_YEAR_PARAM: &str = "first_air_date_year";
+const GENRE_PARAM: &str = "with_genres";
+const ORIGINAL_LANG_PARAM: &str = "with_original_language";
+const SORT_PARAM: &str = "sort_by";
+
+#[derive(Deserialize, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SearchInfo {
+    #[validate(length(min = 1, max = 200))]
+    #[param(min_length = 1, max_length = 200)]
+    pub query: String,
+    // TMDB doesn't go past page 500
+    #[validate(range(min = 1, max = 500))]
+    #[param(minimum = 1, maximum = 500)]
+    pub page: Option<i32>,
+    /// e.g. `en-US`
+    #[validate(length(min = 2, max = 10))]
+    #[param(min_length = 2, max_length = 10)]
+    pub lang: Option<String>,
+    /// Release year for films, first air year for shows, ignored when searching everything
+    #[validate(range(min = 1800, max = 2200))]
+    #[param(minimum = 1800, maximum = 2200)]
+    pub year: Option<i32>,
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct Movie {
+    pub id: i32,
+    pub title: String,
+    pub original_title: String,
+    pub original_language: String,
+    #[serde(default)]
+    pub release_date: String,
+    #[serde(default)]
+    pub overview: String,
+    pub poster_path: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct Show {
+    pub id: i32,
+    pub name: String,
+    pub original_name: String,
+    pub original_language: String,
+    #[serde(default)]
+    pub first_air_date: String,
+    #[serde(default)]
+    pub overview: String,
+    pub poster_path: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct Person {
+    pub id: i32,
+    pub name: String,
+    pub known_for_department: Option<String>,
+    pub profile_path: Option<String>,
+}
+
+/// A multi search result, anything else TMDB adds is skipped.
+#[derive(Deserialize, Debug)]
+#[serde(tag = "media_type", rename_all = "lowercase")]
+pub enum MultiResult {
+    Movie(Movie),
+    Tv(Show),
+    Person(Person),
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct Genre {
+    pub id: i32,
+    pub name: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct MovieDetails {
+    pub id: i32,
+    pub title: String,
+    pub original_title: String,
+    pub original_language: String,
+    #[serde(default)]
+    pub release_date: String,
+    #[serde(default)]
+    pub overview: String,
+    pub poster_path: Option<String>,
+    #[serde(default)]
+    pub genres: Vec<Genre>,
+    pub runtime: Option<i32>,
+    #[serde(default)]
+    pub status: String,
+    pub tagline: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct ShowDetails {
+    pub id: i32,
+    pub name: String,
+    pub original_name: String,
+    pub original_language: String,
+    #[serde(default)]
+    pub first_air_date: String,
+    #[serde(default)]
+    pub overview: String,
+    pub poster_path: Option<String>,
+    pub number_of_seasons: i32,
+    pub number_of_episodes: i32,
+    #[serde(default)]
+    pub genres: Vec<Genre>,
+    #[serde(default)]
+    pub status: String,
+    pub tagline: Option<String>,
+    #[serde(default)]
+    pub seasons: Vec<SeasonSummary>,
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct SeasonSummary {
+    pub id: i32,
+    pub season_number: i32,
+    pub name: String,
+    pub air_date: Option<String>,
+    pub episode_count: i32,
+    pub poster_path: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct SeasonDetails {
+    pub id: i32,
+    pub season_number: i32,
+    pub name: String,
+    #[serde(default)]
+    pub overview: String,
+    pub air_date: Option<String>,
+    pub poster_path: Option<String>,
+    #[serde(default)]
+    pub episodes: Vec<Episode>,
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct Episode {
+    pub id: i32,
+    pub episode_number: i32,
+    pub name: String,
+    #[serde(default)]
+    pub overview: String,
+    pub air_date: Option<String>,
+    pub runtime: Option<i32>,
+    pub still_path: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+#[serde(bound(deserialize = "T: DeserializeOwned"))]
+#[aliases(
+    FilmResults = Results<Movie>,
+    ShowResults = Results<Show>,
+    SearchResults = Results<SearchResult>,
+    FilmsWithStats = Results<FilmWithStats>,
+    ShowsWithStats = Results<ShowWithStats>,
+)]
+pub struct Results<T> {
+    #[serde(deserialize_with = "skip_invalid")]
+    pub results: Vec<T>,
+    pub page: i32,
+    pub total_pages: i32,
+    pub total_results: i32,
+}
+
+// One odd title shouldn't fail the whole page
+fn skip_invalid<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: DeserializeOwned,
+{
+    let values = Vec::<serde_json::Value>::deserialize(deserializer)?;
+
+    let results = values
+        .into_iter()
+        .filter_map(|value| match serde_json::from_value(value) {
+            Ok(result) => Some(result),
+            Err(e) => {
+                tracing::warn!("Skipped TMDB result that didn't match: {e}");
+                None
+            }
+        })
+        .collect();
+
+    Ok(results)
+}
+
+fn search_params(
+    info: &SearchInfo,
+    year_param: Option<&'static str>,
+) -> Vec<(&'static str, String)> {
+    let mut params = vec![(QUERY_PARAM, info.query.to_string())];
+
+    if let Some(page) = &info.page {
+        params.push((PAGE_PARAM, page.to_string()));
+    }
+    if let Some(lang) = &info.lang {
+        params.push((LANG_PARAM, lang.to_string()));
+    }
+    if let (Some(year), Some(year_param)) = (&info.year, year_param) {
+        params.push((year_param, year.to_string()));
+    }
+
+    params
+}
+
+#[derive(Deserialize, Clone, Copy, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum TimeWindow {
+    Day,
+    Week,
+}
+
+#[derive(Deserialize, Clone, Copy, ToSchema)]
+pub enum DiscoverSort {
+    #[serde(rename = "popularity.asc")]
+    PopularityAsc,
+    #[serde(rename = "popularity.desc")]
+    PopularityDesc,
+    #[serde(rename = "release_date.asc")]
+    ReleaseDateAsc,
+    #[serde(rename = "release_date.desc")]
+    ReleaseDateDesc,
+    #[serde(rename = "vote_average.asc")]
+    VoteAverageAsc,
+    #[serde(rename = "vote_average.desc")]
+    VoteAverageDesc,
+}
+
+#[derive(Default)]
+pub struct DiscoverFilters {
+    /// Comma separated TMDB genre ids, any of them match
+    pub genre: Option<String>,
+    pub year_from: Option<i32>,
+    pub year_to: Option<i32>,
+    /// ISO 639-1 original language
+    pub language: Option<String>,
+    pub sort_by: Option<DiscoverSort>,
+}
+
+// TMDB calls films movies and shows tv
+fn category_path(category: MediaCategory) -> &'static str {
+    match category {
+        MediaCategory::Film => FILM_PATH,
+        MediaCategory::Show => SHOW_PATH,
+    }
+}
+
+fn page_params(page: Option<i32>, lang: Option<&str>) -> Vec<(&'static str, String)> {
+    let mut params = lang_params(lang);
+
+    if let Some(page) = page {
+        params.push((PAGE_PARAM, page.to_string()));
+    }
+
+    params
+}
+
+fn discover_params(
+    category: MediaCategory,
+    filters: &DiscoverFilters,
+    page: Option<i32>,
+    lang: Option<&str>,
+) -> Vec<(&'static str, String)> {
+    let mut params = page_params(page, lang);
+
+    let (date_from, date_to, release_date) = match category {
+        MediaCategory::Film => (
+            "primary_release_date.gte",
+            "primary_release_date.lte",
+            "primary_release_date",
+        ),
+        MediaCategory::Show => ("first_air_date.gte", "first_air_date.lte", "first_air_date"),
+    };
+
+    if let Some(genre) = &filters.genre {
+        params.push((GENRE_PARAM, genre.replace(',', "|")));
+    }
+    if let Some(year_from) = filters.year_from {
+        params.push((date_from, format!("{year_from}-01-01")));
+    }
+    if let Some(year_to) = filters.year_to {
+        params.push((date_to, format!("{year_to}-12-31")));
+    }
+    if let Some(language) = &filters.language {
+        params.push((ORIGINAL_LANG_PARAM, language.to_string()));
+    }
+    if let Some(sort_by) = filters.sort_by {
+        let sort_by = match sort_by {
+            DiscoverSort::PopularityAsc => "popularity.asc".to_string(),
+            DiscoverSort::PopularityDesc => "popularity.desc".to_string(),
+            DiscoverSort::ReleaseDateAsc => format!("{release_date}.asc"),
+            DiscoverSort::ReleaseDateDesc => format!("{release_date}.desc"),
+            DiscoverSort::VoteAverageAsc => "vote_average.asc".to_string(),
+            DiscoverSort::VoteAverageDesc => "vote_average.desc".to_string(),
+        };
+        params.push((SORT_PARAM, sort_by));
+    }
+
+    params
+}
+
+/// The `[tmdb]` config section. Point `base_url` at a local stand-in server to run without
+/// network.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TmdbConfig {
+    pub base_url: String,
+    pub api_key: Option<Secret>,
+    pub connect_timeout_secs: u64,
+    pub timeout_secs: u64,
+    pub max_retries: u32,
+    pub retry_backoff_ms: u64,
+    /// Requests per second across all workers, 0 is unlimited
+    pub rate_limit: u32,
+    pub validation: Validation,
+    // Built on first use so every clone of a loaded config shares it
+    #[serde(skip)]
+    limiter: Arc<OnceLock<RateLimiter>>,
+}
+
+impl Default for TmdbConfig {
+    fn default() -> Self {
+        TmdbConfig {
+            base_url: DEFAULT_BASE_URL.to_string(),
+            api_key: None,
+            connect_timeout_secs: 2,
+            timeout_secs: 5,
+            max_retries: 2,
+            retry_backoff_ms: 250,
+            rate_limit: 40,
+            validation: Validation::Strict,
+            limiter: Default::default(),
+        }
+    }
+}
+
+impl TmdbConfig {
+    fn limiter(&self) -> &RateLimiter {
+        self.limiter
+            .get_or_init(|| RateLimiter::new(self.rate_limit))
+    }
+}
+
+/// Spaces requests out evenly, shared by every worker's client.
+#[derive(Debug)]
+pub struct RateLimiter {
+    interval: Duration,
+    next: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    /// 0 is unlimited
+    pub fn new(per_second: u32) -> Self {
+        RateLimiter {
+            interval: Duration::from_secs(1)
+                .checked_div(per_second)
+                .unwrap_or_default(),
+            next: Mutex::new(Instant::now()),
+        }
+    }
+
+    async fn wait(&self) {
+        let wait = {
+            let mut next = self.next.lock().unwrap_or_else(|e| e.into_inner());
+            let now = Instant::now();
+            let slot = (*next).max(now);
+            *next = slot + self.interval;
+            slot - now
+        };
+
+        if !wait.is_zero() {
+            sleep(wait).await;
+        }
+    }
+}
+
+/// awc clients can't be shared between threads, so each worker builds its own.
+#[derive(Clone)]
+pub struct TmdbClient {
+    client: awc::Client,
+    config: TmdbConfig,
+    cache: ResponseCache,
+}
+
+fn is_retryable(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+#[derive(Deserialize)]
+struct ErrorBody {
+    status_message: String,
+}
+
+// TMDB's message is only logged, it can mention our API key
+fn upstream_error(
+    path: &str,
+    status: StatusCode,
+    message: &str,
+    retry_after: Duration,
+) -> ServiceError {
+    if status == StatusCode::NOT_FOUND {
+        return ServiceError::new(404, "title_not_found", "Not found on TMDB");
+    }
+
+    // Anything else TMDB won't take is down to what the client asked for
+    let ours = [StatusCode::UNAUTHORIZED, StatusCode::TOO_MANY_REQUESTS];
+    if status.is_client_error() && !ours.contains(&status) {
+        tracing::debug!("TMDB responded {status} for {path}: {message}");
+        return ServiceError::new(
+            400,
+            "rejected_by_tmdb",
+            format!("TMDB rejected the request with {status}"),
+        );
+    }
+
+    tracing::warn!("TMDB responded {status} for {path}: {message}");
+
+    match status {
+        StatusCode::UNAUTHORIZED => {
+            ServiceError::new(502, "tmdb_unavailable", "TMDB rejected our API key")
+        }
+        StatusCode::TOO_MANY_REQUESTS => ServiceError::new(
+            503,
+            "tmdb_rate_limited",
+            "TMDB is rate limiting us, try again later",
+        )
+        .with_retry_after(retry_after.as_secs().max(1)),
+        _ => ServiceError::new(
+            502,
+            "tmdb_unavailable",
+            format!("TMDB responded with {status}"),
+        ),
+    }
+}
+
+fn retry_after(res: &awc::ClientResponse<impl Sized>) -> Option<Duration> {
+    let secs = res
+        .headers()
+        .get(RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .parse()
+        .ok()?;
+
+    Some(Duration::from_secs(secs).min(MAX_RETRY_AFTER))
+}
+
+// Searches and trending change often, details rarely
+fn ttl_for(path: &str) -> Duration {
+    let minutes = if path.starts_with("/search") || path.starts_with(TRENDING_PATH) {
+        60
+    } else if path.starts_with(DISCOVER_PATH) || path.ends_with(POPULAR_PATH) {
+        6 * 60
+    } else {
+        24 * 60
+    };
+
+    Duration::from_secs(minutes * 60)
+}
+
+// Same key however the params were written, and never including the API key
+fn cache_key(path: &str, params: &[(&str, String)]) -> String {
+    let mut params: Vec<_> = params
+        .iter()
+        .filter(|(name, value)| {
+            *name != API_PARAM && !(*name == LANG_PARAM && value == DEFAULT_LANG)
+        })
+        .map(|(name, value)| match *name {
+            QUERY_PARAM => {
+                let query = value.split_whitespace().collect::<Vec<_>>().join(" ");
+                (*name, query.to_lowercase())
+            }
+            _ => (*name, value.to_string()),
+        })
+        .collect();
+    params.sort();
+
+    format!(
+        "{}?{}",
+        path,
+        serde_urlencoded::to_string(params).unwrap_or_default()
+    )
+}
+
+impl TmdbClient {
+    pub fn new(config: TmdbConfig, cache: ResponseCache) -> Self {
+        let client = awc::Client::builder()
+            .connector(
+                awc::Connector::new().timeout(Duration::from_secs(config.connect_timeout_secs)),
+            )
+            .timeout(Duration::from_secs(config.timeout_secs))
+            .finish();
+
+        TmdbClient {
+            client,
+            config,
+            cache,
+        }
+    }
+
+    pub fn config(&self) -> &TmdbConfig {
+        &self.config
+    }
+
+    /// Serves from the cache when it can. Stale responses are still served, and refreshed in
+    /// the background.
+    async fn get<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        params: Vec<(&'static str, String)>,
+    ) -> Result<T, ServiceError> {
+        let key = cache_key(path, &params);
+
+        if let Some(entry) = self.cache.get(&key).await {
+            if let Ok(body) = serde_json::from_str(&entry.body) {
+                if !entry.is_fresh() {
+                    self.refresh(key, path.to_string(), params);
+                }
+                return Ok(body);
+            }
+        }
+
+        let body = self.fetch(path, params).await?;
+
+        let parsed = serde_json::from_str(&body).map_err(|e| {
+            tracing::error!("Unexpected TMDB response for {path}: {e}");
+            ServiceError::new(502, "tmdb_bad_response", "Unexpected TMDB response")
+        })?;
+
+        self.cache.set(&key, body, ttl_for(path)).await;
+
+        Ok(parsed)
+    }
+
+    fn refresh(&self, key: String, path: String, params: Vec<(&'static str, String)>) {
+        if !self.cache.start_refresh(&key) {
+            return;
+        }
+
+        let tmdb = self.clone();
+        actix_web::rt::spawn(async move {
+            if let Ok(body) = tmdb.fetch(&path, params).await {
+                if serde_json::from_str::<IgnoredAny>(&body).is_ok() {
+                    tmdb.cache.set(&key, body, ttl_for(&path)).await;
+                }
+            }
+            tmdb.cache.finish_refresh(&key);
+        });
+    }
+
+    #[tracing::instrument(name = "tmdb", skip_all, fields(path = %path))]
+    async fn fetch(
+        &self,
+        path: &str,
+        params: Vec<(&'static str, String)>,
+    ) -> Result<String, ServiceError> {
+        let started = Instant::now();
+        let res = self.send(path, params).await;
+        metrics::observe_tmdb(path, started.elapsed(), res.as_ref().err());
+
+        res
+    }
+
+    async fn send(
+        &self,
+        path: &str,
+        mut params: Vec<(&'static str, String)>,
+    ) -> Result<String, ServiceError> {
+        if let Some(api_key) = &self.config.api_key {
+            params.push((API_PARAM, api_key.expose().to_string()));
+        }
+
+        let Ok(path_query) = serde_urlencoded::to_string(params) else {
+            return Err(ServiceError::new(
+                400,
+                "invalid_query",
+                "Invalid search params",
+            ));
+        };
+
+        let url = format!("{}{}?{}", self.config.base_url, path, path_query);
+
+        let mut attempt = 0;
+        loop {
+            self.config.limiter().wait().await;
+
+            let backoff = Duration::from_millis(self.config.retry_backoff_ms)
+                .saturating_mul(2u32.saturating_pow(attempt))
+                .min(MAX_RETRY_AFTER);
+            let can_retry = attempt < self.config.max_retries;
+
+            let delay = match self.client.get(&url).send().await {
+                Ok(mut res) if res.status().is_success() => {
+                    let body = res.body().await.map_err(|e| {
+                        tracing::warn!("Couldn't read TMDB response for {path}: {e}");
+                        ServiceError::new(502, "tmdb_bad_response", "Couldn't read TMDB response")
+                    })?;
+
+                    return String::from_utf8(body.to_vec()).map_err(|e| {
+                        tracing::error!("Unexpected TMDB response for {path}: {e}");
+                        ServiceError::new(502, "tmdb_bad_response", "Unexpected TMDB response")
+                    });
+                }
+                Ok(res) if can_retry && is_retryable(res.status()) => {
+                    retry_after(&res).unwrap_or(backoff)
+                }
+                Ok(mut res) => {
+                    let retry_after = retry_after(&res).unwrap_or(backoff);
+                    let message = res
+                        .json::<ErrorBody>()
+                        .await
+                        .map(|body| body.status_message)
+                        .unwrap_or_default();
+
+                    return Err(upstream_error(path, res.status(), &message, retry_after));
+                }
+                Err(_) if can_retry => backoff,
+                Err(e) => {
+                    tracing::warn!("Couldn't reach TMDB for {path}: {e}");
+
+                    return Err(match e {
+                        SendRequestError::Timeout => {
+                            ServiceError::new(504, "tmdb_timeout", "TMDB took too long to respond")
+                        }
+                        _ => ServiceError::new(502, "tmdb_unavailable", "Couldn't reach TMDB"),
+                    });
+                }
+            };
+
+            sleep(delay).await;
+            attempt += 1;
+        }
+    }
+
+    pub async fn search_movies(&self, info: &SearchInfo) -> Result<Results<Movie>, ServiceError> {
+        self.get(SEARCH_FILM_PATH, search_params(info, Some(FILM_YEAR_PARAM)))
+            .await
+    }
+
+    pub async fn search_shows(&self, info: &SearchInfo) -> Result<Results<Show>, ServiceError> {
+        self.get(SEARCH_SHOW_PATH, search_params(info, Some(SHOW_YEAR_PARAM)))
+            .await
+    }
+
+    /// Films, shows and people at once, `year` is ignored.
+    pub async fn search_multi(
+        &self,
+        info: &SearchInfo,
+    ) -> Result<Results<MultiResult>, ServiceError> {
+        self.get(SEARCH_MULTI_PATH, search_params(info, None)).await
+    }
+
+    /// `T` is `Movie` or `Show` to match `category`.
+    pub async fn trending<T: DeserializeOwned>(
+        &self,
+        category: MediaCategory,
+        window: TimeWindow,
+        page: Option<i32>,
+        lang: Option<&str>,
+    ) -> Result<Results<T>, ServiceError> {
+        let window = match window {
+            TimeWindow::Day => "day",
+            TimeWindow::Week => "week",
+        };
+        let path = format!("{}{}/{}", TRENDING_PATH, category_path(category), window);
+
+        self.get(&path, page_params(page, lang)).await
+    }
+
+    /// `T` is `Movie` or `Show` to match `category`.
+    pub async fn popular<T: DeserializeOwned>(
+        &self,
+        category: MediaCategory,
+        page: Option<i32>,
+        lang: Option<&str>,
+    ) -> Result<Results<T>, ServiceError> {
+        let path = format!("{}{}", category_path(category), POPULAR_PATH);
+
+        self.get(&path, page_params(page, lang)).await
+    }
+
+    /// `T` is `Movie` or `Show` to match `category`.
+    pub async fn similar<T: DeserializeOwned>(
+        &self,
+        category: MediaCategory,
+        id: i32,
+    ) -> Result<Results<T>, ServiceError> {
+        let path = format!("{}/{}{}", category_path(category), id, SIMILAR_PATH);
+
+        self.get(&path, vec![]).await
+    }
+
+    /// `T` is `Movie` or `Show` to match `category`.
+    pub async fn discover<T: DeserializeOwned>(
+        &self,
+        category: MediaCategory,
+        filters: &DiscoverFilters,
+        page: Option<i32>,
+        lang: Option<&str>,
+    ) -> Result<Results<T>, ServiceError> {
+        let path = format!("{}{}", DISCOVER_PATH, category_path(category));
+
+        self.get(&path, discover_params(category, filters, page, lang))
+            .await
+    }
+
+    pub async fn get_movie(
+        &self,
+        id: i32,
+        lang: Option<&str>,
+    ) -> Result<MovieDetails, ServiceError> {
+        self.get(&format!("{}/{}", FILM_PATH, id), lang_params(lang))
+            .await
+    }
+
+    pub async fn get_show(&self, id: i32, lang: Option<&str>) -> Result<ShowDetails, ServiceError> {
+        self.get(&format!("{}/{}", SHOW_PATH, id), lang_params(lang))
+            .await
+    }
+
+    pub async fn get_season(
+        &self,
+        id: i32,
+        season: i32,
+        lang: Option<&str>,
+    ) -> Result<SeasonDetails, ServiceError> {
+        self.get(
+            &format!("{}/{}{}/{}", SHOW_PATH, id, SEASON_PATH, season),
+            lang_params(lang),
+        )
+        .await
+    }
+
+    /// Checks TMDB is reachable and accepts our API key, skipping the cache.
+    pub async fn ping(&self) -> Result<(), ServiceError> {
+        self.fetch(CONFIGURATION_PATH, Vec::new()).await.map(|_| ())
+    }
+}
+
+fn lang_params(lang: Option<&str>) -> Vec<(&'static str, String)> {
+    lang.map(|lang| vec![(LANG_PARAM, lang.to_string())])
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{Arc, Mutex},
+        time::{Duration, Instant},
+    };
+
+    use actix_web::{rt::time::sleep, web, App, HttpRequest, HttpResponse, HttpServer};
+    use serde_json::json;
+
+    use super::{cache_key, SearchInfo, TmdbClient, TmdbConfig};
+    use crate::{cache::ResponseCache, config::Secret};
+
+    #[derive(Clone, Copy)]
+    enum Reply {
+        Found,
+        Status(u16, Option<u64>),
+        Slow(Duration),
+    }
+
+    /// Query strings of the requests a stand-in TMDB has had.
+    type Seen = Arc<Mutex<Vec<String>>>;
+
+    /// Starts a stand-in TMDB answering with `replies` in turn, then the last one forever.
+    fn stand_in(replies: Vec<Reply>) -> (String, Seen) {
+        let seen = Seen::default();
+        let state = web::Data::new((replies, seen.clone()));
+
+        let server = HttpServer::new(move || {
+            App::new()
+                .app_data(state.clone())
+                .default_service(web::to(respond))
+        })
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+        let addr = server.addrs()[0];
+        actix_web::rt::spawn(server.run());
+
+        (format!("http://{addr}"), seen)
+    }
+
+    async fn respond(req: HttpRequest, state: web::Data<(Vec<Reply>, Seen)>) -> HttpResponse {
+        let (replies, seen) = state.get_ref();
+        let reply = {
+            let mut seen = seen.lock().unwrap();
+            seen.push(req.query_string().to_string());
+            replies[(seen.len() - 1).min(replies.len() - 1)]
+        };
+
+        let found = json!({
+            "page": 1,
+            "total_pages": 1,
+            "total_results": 1,
+            "results": [{
+                "id": 550,
+                "title": "Fight Club",
+                "original_title": "Fight Club",
+                "original_language": "en",
+                "poster_path": null,
+            }],
+        });
+
+        match reply {
+            Reply::Found => HttpResponse::Ok().json(found),
+            Reply::Status(status, retry_after) => {
+                let mut res = HttpResponse::build(status.try_into().unwrap());
+                if let Some(secs) = retry_after {
+                    res.insert_header(("Retry-After", secs.to_string()));
+                }
+                res.json(json!({ "status_message": "Nope" }))
+            }
+            Reply::Slow(delay) => {
+                sleep(delay).await;
+                HttpResponse::Ok().json(found)
+            }
+        }
+    }
+
+    fn client(base_url: String, config: TmdbConfig) -> TmdbClient {
+        let config = TmdbConfig {
+            base_url,
+            api_key: Some("k".parse::<Secret>().unwrap()),
+            retry_backoff_ms: 1,
+            rate_limit: 0,
+            ..config
+        };
+
+        TmdbClient::new(config, ResponseCache::memory(10))
+    }
+
+    fn search(query: &str) -> SearchInfo {
+        SearchInfo {
+            query: query.to_string(),
+            page: None,
+            lang: None,
+            year: None,
+        }
+    }
+
+    #[actix_web::test]
+    async fn searches_through_the_base_url() {
+        let (url, seen) = stand_in(vec![Reply::Found]);
+        let tmdb = client(url, TmdbConfig::default());
+
+        let results = tmdb.search_movies(&search("fight club")).await.unwrap();
+        assert_eq!(results.results[0].title, "Fight Club");
+
+        let seen = seen.lock().unwrap();
+        assert!(seen[0].contains("query=fight+club"), "{}", seen[0]);
+        assert!(seen[0].contains("api_key=k"), "{}", seen[0]);
+    }
+
+    #[actix_web::test]
+    async fn retries_rate_limits_and_server_errors() {
+        let (url, seen) = stand_in(vec![
+            Reply::Status(429, Some(1)),
+            Reply::Status(503, None),
+            Reply::Found,
+        ]);
+        let tmdb = client(url, TmdbConfig::default());
+
+        let started = Instant::now();
+        tmdb.search_movies(&search("a")).await.unwrap();
+
+        assert_eq!(seen.lock().unwrap().len(), 3);
+        // Waited as long as Retry-After said, not the 1ms backoff
+        assert!(started.elapsed() >= Duration::from_secs(1));
+    }
+
+    #[actix_web::test]
+    async fn gives_up_after_max_retries() {
+        let (url, seen) = stand_in(vec![Reply::Status(429, Some(0))]);
+        let tmdb = client(
+            url,
+            TmdbConfig {
+                max_retries: 1,
+                ..Default::default()
+            },
+        );
+
+        let e = tmdb.search_movies(&search("a")).await.unwrap_err();
+        assert_eq!((e.status, e.code), (503, "tmdb_rate_limited"));
+        assert_eq!(e.retry_after, Some(1));
+        assert_eq!(seen.lock().unwrap().len(), 2);
+
+        let (url, _) = stand_in(vec![Reply::Status(500, None)]);
+        let tmdb = client(url, TmdbConfig::default());
+
+        let e = tmdb.search_movies(&search("a")).await.unwrap_err();
+        assert_eq!((e.status, e.code), (502, "tmdb_unavailable"));
+    }
+
+    #[actix_web::test]
+    async fn blames_clients_for_what_tmdb_rejects() {
+        for (status, expected) in [
+            (400, (400, "rejected_by_tmdb")),
+            (422, (400, "rejected_by_tmdb")),
+            (404, (404, "title_not_found")),
+            (401, (502, "tmdb_unavailable")),
+        ] {
+            let (url, seen) = stand_in(vec![Reply::Status(status, None)]);
+            let tmdb = client(url, TmdbConfig::default());
+
+            let e = tmdb.search_movies(&search("a")).await.unwrap_err();
+            assert_eq!((e.status, e.code), expected, "TMDB responded {status}");
+            assert_eq!(seen.lock().unwrap().len(), 1, "retried {status}");
+        }
+    }
+
+    #[actix_web::test]
+    async fn maps_timeouts_to_504() {
+        let (url, _) = stand_in(vec![Reply::Slow(Duration::from_secs(3))]);
+        let tmdb = client(
+            url,
+            TmdbConfig {
+                timeout_secs: 1,
+                max_retries: 0,
+                ..Default::default()
+            },
+        );
+
+        let e = tmdb.search_movies(&search("a")).await.unwrap_err();
+        assert_eq!((e.status, e.code), (504, "tmdb_timeout"));
+    }
+
+    #[actix_web::test]
+    async fn spaces_requests_out_by_the_rate_limit() {
+        let (url, seen) = stand_in(vec![Reply::Found]);
+        let tmdb = TmdbClient::new(
+            TmdbConfig {
+                base_url: url,
+                rate_limit: 10,
+                ..Default::default()
+            },
+            ResponseCache::memory(10),
+        );
+
+        let started = Instant::now();
+        for _ in 0..4 {
+            tmdb.ping().await.unwrap();
+        }
+
+        assert_eq!(seen.lock().unwrap().len(), 4);
+        // The first goes straight away, then one every 100ms
+        assert!(started.elapsed() >= Duration::from_millis(300));
+    }
+
+    #[test]
+    fn cache_keys_are_normalized() {
+        let key = cache_key(
+            "/search/movie",
+            &[
+                ("query", "  Fight   CLUB ".to_string()),
+                ("page", "2".to_string()),
+                ("language", "en-US".to_string()),
+                ("api_key", "secret".to_string()),
+            ],
+        );
+        let same = cache_key(
+            "/search/movie",
+            &[
+                ("page", "2".to_string()),
+                ("query", "fight club".to_string()),
+            ],
+        );
+
+        assert_eq!(key, "/search/movie?page=2&query=fight+club");
+        assert_eq!(key, same);
+        assert_ne!(
+            key,
+            cache_key(
+                "/search/movie",
+                &[
+                    ("page", "2".to_string()),
+                    ("query", "fight club".to_string()),
+                    ("language", "de-DE".to_string()),
+                ],
+            )
+        );
+    }
+
+    #[actix_web::test]
+    async fn serves_stale_responses_while_refreshing() {
+        let (url, seen) = stand_in(vec![Reply::Found]);
+        let tmdb = client(url, TmdbConfig::default());
+        let key = cache_key("/search/movie", &[("query", "a".to_string())]);
+        let stale = json!({
+            "page": 1,
+            "total_pages": 1,
+            "total_results": 1,
+            "results": [{
+                "id": 1,
+                "title": "Stale",
+                "original_title": "Stale",
+                "original_language": "en",
+                "poster_path": null,
+            }],
+        });
+        tmdb.cache
+            .set(&key, stale.to_string(), Duration::ZERO)
+            .await;
+
+        let results = tmdb.search_movies(&search("a")).await.unwrap();
+        assert_eq!(results.results[0].title, "Stale");
+
+        // The refresh runs in the background
+        sleep(Duration::from_millis(200)).await;
+        assert_eq!(seen.lock().unwrap().len(), 1);
+        let entry = tmdb.cache.get(&key).await.unwrap();
+        assert!(entry.is_fresh());
+        assert!(entry.body.contains("Fight Club"));
+    }
+}
@@ -1,14 +1,11 @@
+use actix_web::{error::BlockingError, web};
 use argon2::{self, Config};
 
 use rand::Rng;
 
-lazy_static::lazy_static! {
-  pub static ref SECRET_KEY: String = std::env::var("SECRET_KEY").unwrap();
-}
-
-pub fn hash_password(password: &str) -> Result<String, argon2::Error> {
+pub fn hash_password(password: &str, secret_key: &str) -> Result<String, argon2::Error> {
     let config = Config {
-        secret: SECRET_KEY.as_bytes(),
+        secret: secret_key.as_bytes(),
         ..Default::default()
     };
 
@@ -17,6 +14,25 @@ pub fn hash_password(password: &str) -> Result<String, argon2::Error> {
     argon2::hash_encoded(password.as_bytes(), &salt, &config)
 }
 
-pub fn verify_password(password: &str, hash: &str) -> Result<bool, argon2::Error> {
-    argon2::verify_encoded_ext(hash, password.as_bytes(), SECRET_KEY.as_bytes(), &[])
+pub fn verify_password(
+    password: &str,
+    hash: &str,
+    secret_key: &str,
+) -> Result<bool, argon2::Error> {
+    argon2::verify_encoded_ext(hash, password.as_bytes(), secret_key.as_bytes(), &[])
+}
+
+/// `web::block` inside a `db` span, so the work is traced under the request that asked for it.
+pub async fn block<F, R>(f: F) -> Result<R, BlockingError>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    let span = tracing::info_span!("db");
+    let entered = span.clone();
+    let res = web::block(move || entered.in_scope(f)).await;
+
+    // Closes here rather than on the blocking thread, so it's logged with the request
+    drop(span);
+    res
 }
@@ -0,0 +1,443 @@
+// Request bodies and queries are checked against the rules their types derive with
+// `Validate`, and everything that's wrong is reported in one 422
+
+use std::{borrow::Cow, ops::Deref};
+
+use actix_web::{
+    body::EitherBody,
+    dev::{Payload, Service, ServiceRequest, ServiceResponse},
+    error::{JsonPayloadError, PathError, QueryPayloadError},
+    web, Error, FromRequest, HttpRequest,
+};
+use futures_util::future::{ready, LocalBoxFuture, Ready};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use validator::{Validate, ValidationError, ValidationErrors};
+
+use crate::{
+    constants::{MAX_JSON_BYTES, MAX_QUERY_BYTES},
+    errors::{FieldError, ServiceError},
+};
+
+const MIN_PASSWORD_LEN: usize = 8;
+
+/// At least 8 characters, with a letter and a number or symbol.
+pub fn password_strength(password: &str) -> Result<(), ValidationError> {
+    let long_enough = password.chars().count() >= MIN_PASSWORD_LEN;
+    let has_letter = password.chars().any(char::is_alphabetic);
+    // Spaces don't count, or "password " would pass
+    let has_other = password
+        .chars()
+        .any(|c| c.is_ascii_digit() || c.is_ascii_punctuation());
+
+    if long_enough && has_letter && has_other {
+        return Ok(());
+    }
+
+    let mut error = ValidationError::new("weak_password");
+    error.message = Some(Cow::Owned(format!(
+        "Must be at least {MIN_PASSWORD_LEN} characters, with a letter and a number or symbol"
+    )));
+    Err(error)
+}
+
+fn param(error: &ValidationError, name: &str) -> Option<f64> {
+    error.params.get(name).and_then(Value::as_f64)
+}
+
+fn bounds(error: &ValidationError, unit: &str) -> String {
+    match (param(error, "min"), param(error, "max")) {
+        (Some(min), Some(max)) => format!("Must be between {min} and {max}{unit}"),
+        (Some(min), None) => format!("Must be at least {min}{unit}"),
+        (None, Some(max)) => format!("Must be at most {max}{unit}"),
+        (None, None) => "Out of range".to_string(),
+    }
+}
+
+/// A stable code and a readable message for one failed rule.
+fn describe(error: &ValidationError) -> (Cow<'static, str>, String) {
+    if let Some(message) = &error.message {
+        return (error.code.clone(), message.to_string());
+    }
+
+    match error.code.as_ref() {
+        "length" => {
+            let len = match error.params.get("value") {
+                Some(Value::String(value)) => value.chars().count() as f64,
+                _ => 0.0,
+            };
+            let code = match param(error, "min") {
+                Some(min) if len < min => "too_short",
+                _ => "too_long",
+            };
+            (code.into(), bounds(error, " characters"))
+        }
+        "range" => {
+            let value = error.params.get("value").and_then(Value::as_f64);
+            let code = match (value, param(error, "min")) {
+                (Some(value), Some(min)) if value < min => "too_small",
+                _ => "too_large",
+            };
+            (code.into(), bounds(error, ""))
+        }
+        "email" => ("invalid_email".into(), "Must be an email address".into()),
+        _ => (error.code.clone(), "Invalid".into()),
+    }
+}
+
+impl From<ValidationErrors> for ServiceError {
+    fn from(errors: ValidationErrors) -> Self {
+        let mut fields: Vec<FieldError> = errors
+            .field_errors()
+            .into_iter()
+            .flat_map(|(field, errors)| {
+                errors.iter().map(move |error| {
+                    let (code, message) = describe(error);
+                    FieldError::new(field, code, message)
+                })
+            })
+            .collect();
+        fields.sort_by(|a, b| a.field.cmp(&b.field));
+
+        ServiceError::validation(fields)
+    }
+}
+
+fn json_error(error: JsonPayloadError) -> ServiceError {
+    match error {
+        JsonPayloadError::OverflowKnownLength { .. } | JsonPayloadError::Overflow { .. } => {
+            ServiceError::new(
+                413,
+                "payload_too_large",
+                format!("Bodies can be at most {MAX_JSON_BYTES} bytes"),
+            )
+        }
+        JsonPayloadError::ContentType => ServiceError::new(
+            415,
+            "unsupported_media_type",
+            "Send JSON with Content-Type: application/json",
+        ),
+        JsonPayloadError::Deserialize(e) => {
+            ServiceError::new(400, "invalid_body", format!("Invalid JSON: {e}"))
+        }
+        e => ServiceError::new(400, "invalid_body", e.to_string()),
+    }
+}
+
+fn query_error(error: QueryPayloadError) -> ServiceError {
+    ServiceError::new(400, "invalid_query", error.to_string())
+}
+
+fn path_error(error: PathError) -> ServiceError {
+    ServiceError::new(400, "invalid_path", error.to_string())
+}
+
+/// Limits JSON bodies and responds to bad ones with a problem, register as app data.
+pub fn json_config() -> web::JsonConfig {
+    web::JsonConfig::default()
+        .limit(MAX_JSON_BYTES)
+        .error_handler(|e, _| json_error(e).into())
+}
+
+/// Rejects query strings over `MAX_QUERY_BYTES` before any handler parses them, wrap the app
+/// with it.
+pub fn limit_query<S, B>(
+    req: ServiceRequest,
+    srv: &S,
+) -> LocalBoxFuture<'static, Result<ServiceResponse<EitherBody<B>>, Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    if req.query_string().len() > MAX_QUERY_BYTES {
+        let error = ServiceError::new(
+            414,
+            "query_too_long",
+            format!("Query strings can be at most {MAX_QUERY_BYTES} bytes"),
+        );
+        return Box::pin(ready(Ok(req.error_response(error).map_into_right_body())));
+    }
+
+    let res = srv.call(req);
+    Box::pin(async move { Ok(res.await?.map_into_left_body()) })
+}
+
+pub fn query_config() -> web::QueryConfig {
+    web::QueryConfig::default().error_handler(|e, _| query_error(e).into())
+}
+
+pub fn path_config() -> web::PathConfig {
+    web::PathConfig::default().error_handler(|e, _| path_error(e).into())
+}
+
+/// Like `web::Json`, but rejects bodies that fail validation.
+pub struct ValidJson<T>(pub T);
+
+impl<T> ValidJson<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Deref for ValidJson<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: DeserializeOwned + Validate + 'static> FromRequest for ValidJson<T> {
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let json = web::Json::<T>::from_request(req, payload);
+
+        Box::pin(async move {
+            let json = json.await?.into_inner();
+            json.validate().map_err(ServiceError::from)?;
+            Ok(ValidJson(json))
+        })
+    }
+}
+
+/// Like `web::Query`, but rejects queries that fail validation.
+pub struct ValidQuery<T>(pub T);
+
+impl<T> ValidQuery<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Deref for ValidQuery<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: DeserializeOwned + Validate> FromRequest for ValidQuery<T> {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let res = web::Query::<T>::from_query(req.query_string())
+            .map_err(query_error)
+            .and_then(|query| {
+                let query = query.into_inner();
+                query.validate()?;
+                Ok(ValidQuery(query))
+            });
+
+        ready(res.map_err(Into::into))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{
+        http::{header::CONTENT_TYPE, StatusCode},
+        test::{call_service, init_service, read_body, TestRequest},
+        web, App,
+    };
+    use serde::Deserialize;
+    use serde_json::{json, Value};
+    use validator::Validate;
+
+    use super::{json_config, limit_query, password_strength, query_config, ValidJson, ValidQuery};
+    use crate::{
+        constants::{MAX_JSON_BYTES, MAX_QUERY_BYTES},
+        errors::ServiceError,
+    };
+
+    #[derive(Deserialize, Validate)]
+    struct Input {
+        #[validate(length(min = 2, max = 4))]
+        name: String,
+        #[validate(email)]
+        email: String,
+        #[validate(range(min = 1, max = 5))]
+        stars: i32,
+    }
+
+    fn input(name: &str, email: &str, stars: i32) -> Input {
+        Input {
+            name: name.to_string(),
+            email: email.to_string(),
+            stars,
+        }
+    }
+
+    // Every field's code, in field order
+    fn codes(input: Input) -> Vec<String> {
+        let error = ServiceError::from(input.validate().unwrap_err());
+        assert_eq!(error.status, 422);
+        assert_eq!(error.code, "validation_failed");
+        error
+            .fields
+            .iter()
+            .map(|field| format!("{} {}", field.field, field.code))
+            .collect()
+    }
+
+    async fn send(req: TestRequest) -> (StatusCode, Value) {
+        let app = init_service(
+            App::new()
+                .wrap_fn(limit_query)
+                .app_data(json_config())
+                .app_data(query_config())
+                .route(
+                    "/",
+                    web::post().to(|input: ValidJson<Input>| async move { input.name.clone() }),
+                )
+                .route(
+                    "/",
+                    web::get().to(|input: ValidQuery<Input>| async move { input.name.clone() }),
+                ),
+        )
+        .await;
+
+        let res = call_service(&app, req.to_request()).await;
+        let status = res.status();
+        let body = read_body(res).await;
+        (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+    }
+
+    #[test]
+    fn passwords_need_a_letter_and_a_number_or_symbol() {
+        assert!(password_strength("password1").is_ok());
+        assert!(password_strength("pass-word").is_ok());
+        assert!(password_strength("pässwörd!").is_ok());
+
+        for weak in ["pass1", "password", "password ", "12345678", "        "] {
+            let error = password_strength(weak).unwrap_err();
+            assert_eq!(error.code, "weak_password", "{weak:?}");
+        }
+    }
+
+    #[test]
+    fn lengths_are_too_short_or_too_long() {
+        assert_eq!(codes(input("a", "a@b.io", 3)), ["name too_short"]);
+        assert_eq!(codes(input("", "a@b.io", 3)), ["name too_short"]);
+        assert_eq!(codes(input("abcde", "a@b.io", 3)), ["name too_long"]);
+    }
+
+    #[test]
+    fn ranges_are_too_small_or_too_large() {
+        assert_eq!(codes(input("ab", "a@b.io", 0)), ["stars too_small"]);
+        assert_eq!(codes(input("ab", "a@b.io", 6)), ["stars too_large"]);
+    }
+
+    #[test]
+    fn emails_are_invalid_email() {
+        assert_eq!(codes(input("ab", "ab.io", 3)), ["email invalid_email"]);
+    }
+
+    #[test]
+    fn messages_give_the_bounds() {
+        let error = ServiceError::from(input("a", "a@b.io", 9).validate().unwrap_err());
+        let messages: Vec<_> = error.fields.iter().map(|f| f.message.as_str()).collect();
+
+        assert_eq!(
+            messages,
+            [
+                "Must be between 2 and 4 characters",
+                "Must be between 1 and 5"
+            ]
+        );
+    }
+
+    #[actix_web::test]
+    async fn reports_every_bad_field_at_once() {
+        let req = TestRequest::post()
+            .uri("/")
+            .set_json(json!({ "name": "abcdef", "email": "nope", "stars": 0 }));
+        let (status, body) = send(req).await;
+
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["code"], "validation_failed");
+        let fields: Vec<_> = body["errors"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|error| format!("{} {}", error["field"], error["code"]))
+            .collect();
+        assert_eq!(
+            fields,
+            [
+                r#""email" "invalid_email""#,
+                r#""name" "too_long""#,
+                r#""stars" "too_small""#
+            ]
+        );
+    }
+
+    #[actix_web::test]
+    async fn accepts_valid_bodies_and_queries() {
+        let req = TestRequest::post()
+            .uri("/")
+            .set_json(json!({ "name": "ab", "email": "a@b.io", "stars": 3 }));
+        assert_eq!(send(req).await.0, StatusCode::OK);
+
+        let req = TestRequest::get().uri("/?name=ab&email=a%40b.io&stars=3");
+        assert_eq!(send(req).await.0, StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn rejects_large_bodies_with_413() {
+        let name = "a".repeat(MAX_JSON_BYTES);
+        let req = TestRequest::post()
+            .uri("/")
+            .set_json(json!({ "name": name, "email": "a@b.io", "stars": 3 }));
+        let (status, body) = send(req).await;
+
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(body["code"], "payload_too_large");
+    }
+
+    #[actix_web::test]
+    async fn rejects_other_content_types_with_415() {
+        let req = TestRequest::post()
+            .uri("/")
+            .insert_header((CONTENT_TYPE, "text/plain"))
+            .set_payload("name=ab");
+        let (status, body) = send(req).await;
+
+        assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert_eq!(body["code"], "unsupported_media_type");
+    }
+
+    #[actix_web::test]
+    async fn rejects_malformed_json_with_400() {
+        let req = TestRequest::post()
+            .uri("/")
+            .insert_header((CONTENT_TYPE, "application/json"))
+            .set_payload(r#"{ "name": "ab", "#);
+        let (status, body) = send(req).await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["code"], "invalid_body");
+    }
+
+    #[actix_web::test]
+    async fn rejects_malformed_queries_with_400() {
+        let req = TestRequest::get().uri("/?name=ab&stars=many");
+        let (status, body) = send(req).await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["code"], "invalid_query");
+    }
+
+    #[actix_web::test]
+    async fn rejects_long_queries_with_414() {
+        let uri = format!("/?name={}", "a".repeat(MAX_QUERY_BYTES));
+        let (status, body) = send(TestRequest::get().uri(&uri)).await;
+
+        assert_eq!(status, StatusCode::URI_TOO_LONG);
+        assert_eq!(body["code"], "query_too_long");
+    }
+}